@@ -0,0 +1,593 @@
+//! Simulated dive computer
+//!
+//! [`SimulatedDevice`] answers every command from simulated state: a pressure,
+//! temperature and battery sensor, a dive that follows a square profile while
+//! the tissue and oxygen models run, a dive log, a draining battery and the
+//! bookkeeping of firmware updates.
+
+use std::collections::BTreeMap;
+
+use dive_computer_proto::buhlmann::GradientFactors;
+use dive_computer_proto::commands::{CommandError, ResponsePayload};
+use dive_computer_proto::dispatcher::{CommandHandler, HandlerResult};
+use dive_computer_proto::dive_calc::{
+    DiveProfile, GasType, PressureModel, WaterDensity, MAX_GASES, SEA_LEVEL_PRESSURE_MBAR,
+};
+use dive_computer_proto::protocol::calculate_crc16;
+use dive_computer_proto::real;
+use dive_computer_proto::sensor::ReadingType;
+use dive_computer_proto::surface::SurfaceState;
+
+/// Device ID reported by `Command::ID`
+const DEVICE_ID: u32 = 0x4456_0001;
+
+/// Firmware version before any update
+const FIRMWARE_VERSION: [u8; 4] = [1, 0, 0, 0];
+
+/// Hardware version reported by `Command::ID`
+const HARDWARE_VERSION: [u8; 4] = [1, 0, 0, 1];
+
+/// ID of the pressure sensor, which provides depth and pressure readings
+const PRESSURE_SENSOR_ID: u16 = 1;
+
+/// ID of the temperature sensor
+const TEMPERATURE_SENSOR_ID: u16 = 2;
+
+/// ID of the battery gauge
+const BATTERY_SENSOR_ID: u16 = 3;
+
+/// Bottom depth of simulated dives in centimeters, unless the maximum depth setting is shallower
+const DIVE_DEPTH_CM: u16 = 1800;
+
+/// Descent rate of simulated dives in cm/minute
+const DESCENT_RATE_CM_PER_MIN: u32 = 1000;
+
+/// Ascent rate of simulated dives in cm/minute
+const ASCENT_RATE_CM_PER_MIN: u32 = 900;
+
+/// Longest interval between two depth samples in seconds
+const SAMPLE_SECONDS: u32 = 10;
+
+/// Water temperature at the surface in degrees Celsius (scaled by 10)
+const SURFACE_TEMPERATURE_X10: i16 = 240;
+
+/// Temperature drop per meter of depth in degrees Celsius (scaled by 10)
+const TEMPERATURE_DROP_X10_PER_M: i16 = 2;
+
+/// Full battery charge in thousandths of a percent
+const FULL_BATTERY: u32 = 100_000;
+
+/// Battery drain at the surface in thousandths of a percent per second (about 9 hours)
+const SURFACE_DRAIN: u32 = 3;
+
+/// Battery drain during a dive in thousandths of a percent per second (about 5.5 hours)
+const DIVE_DRAIN: u32 = 5;
+
+/// Battery drain in low-power mode in thousandths of a percent per second (about 28 hours)
+const LOW_POWER_DRAIN: u32 = 1;
+
+/// Battery voltage when empty in millivolts
+const EMPTY_VOLTAGE_MV: u16 = 3300;
+
+/// Battery voltage gain per percent of charge in millivolts
+const VOLTAGE_MV_PER_PERCENT: u16 = 9;
+
+/// Lowest battery level at which a dive can be started in percent
+const MIN_DIVE_BATTERY_PERCENT: u8 = 10;
+
+/// Lowest battery level at which a firmware update can be started in percent
+const MIN_UPDATE_BATTERY_PERCENT: u8 = 30;
+
+/// Lowest battery level that passes the diagnostic in percent
+const MIN_HEALTHY_BATTERY_PERCENT: u8 = 20;
+
+/// Size of a firmware chunk in bytes
+const FIRMWARE_CHUNK_SIZE: usize = 32;
+
+/// Firmware update in progress
+#[derive(Debug)]
+struct FirmwareUpdate {
+    /// Version of the new firmware
+    version: [u8; 4],
+    /// Received image, `FIRMWARE_CHUNK_SIZE` bytes per chunk
+    image: Vec<u8>,
+    /// Which chunks have been received
+    received: Vec<bool>,
+}
+
+/// State of the emulated dive computer
+#[derive(Debug)]
+pub struct SimulatedDevice {
+    /// Installed firmware version
+    firmware_version: [u8; 4],
+    /// Simulated time since start in milliseconds
+    clock_ms: u64,
+    /// Battery charge in thousandths of a percent
+    battery: u32,
+    /// Whether the device is in low-power mode
+    low_power: bool,
+    /// Surface pressure measured by the barometer in millibars
+    barometer_mbar: u16,
+    /// Maximum depth setting in centimeters
+    max_depth_cm: u16,
+    /// Maximum dive time setting in minutes
+    max_time: u16,
+    /// Profile of the current dive, or settings and gases of the next dive at the surface
+    profile: DiveProfile,
+    /// Whether a dive is in progress
+    diving: bool,
+    /// Residual loading between dives
+    surface: SurfaceState,
+    /// Stored dive logs by dive ID
+    logs: BTreeMap<u32, [u8; 32]>,
+    /// ID of the next logged dive
+    next_dive_id: u32,
+    /// Firmware update in progress
+    firmware_update: Option<FirmwareUpdate>,
+}
+
+impl SimulatedDevice {
+    /// Creates a device with factory settings and one stored dive
+    ///
+    /// # Arguments
+    ///
+    /// * `battery_percent` - Initial battery level
+    pub fn new(battery_percent: u8) -> Self {
+        let mut device = SimulatedDevice {
+            firmware_version: FIRMWARE_VERSION,
+            clock_ms: 0,
+            battery: FULL_BATTERY * battery_percent.min(100) as u32 / 100,
+            low_power: false,
+            barometer_mbar: SEA_LEVEL_PRESSURE_MBAR,
+            max_depth_cm: 0,
+            max_time: 0,
+            profile: DiveProfile::new(GasType::Air),
+            diving: false,
+            surface: SurfaceState::new(),
+            logs: BTreeMap::new(),
+            next_dive_id: 1,
+            firmware_update: None,
+        };
+        device.reset_settings();
+        // A 42 minute dive to 18.2 m on air
+        device.store_log(log_record(1820, 42 * 60, 212, GasType::Air, 3));
+        device
+    }
+
+    /// Returns the simulated time since start in milliseconds
+    pub fn now_ms(&self) -> u64 {
+        self.clock_ms
+    }
+
+    /// Advances the simulation
+    ///
+    /// The dive, the surface interval and the battery advance in whole seconds;
+    /// fractions are carried over to the next call.
+    pub fn advance(&mut self, milliseconds: u64) {
+        let previous_seconds = self.clock_ms / 1000;
+        self.clock_ms += milliseconds;
+        let mut seconds = (self.clock_ms / 1000 - previous_seconds).min(u32::MAX as u64) as u32;
+        while seconds > 0 {
+            let step = seconds.min(SAMPLE_SECONDS);
+            self.simulate(step);
+            seconds -= step;
+        }
+    }
+
+    /// Runs the simulation for `seconds`
+    fn simulate(&mut self, seconds: u32) {
+        self.battery = self.battery.saturating_sub(self.drain() * seconds);
+
+        if self.diving {
+            let depth_cm = self.planned_depth_cm(self.profile.duration_seconds + seconds);
+            self.profile.record_sample(depth_cm, seconds);
+            self.profile.update_temperature(temperature_x10(depth_cm));
+        } else {
+            self.surface.surface_interval(seconds);
+        }
+    }
+
+    /// Returns the depth of the simulated square profile after `seconds` of dive time
+    ///
+    /// The diver descends to the bottom depth, stays there and ascends so as
+    /// to surface when the maximum dive time is reached.
+    fn planned_depth_cm(&self, seconds: u32) -> u16 {
+        let bottom_cm = DIVE_DEPTH_CM.min(self.max_depth_cm) as u32;
+        let descent_seconds = bottom_cm * 60 / DESCENT_RATE_CM_PER_MIN;
+        let ascent_seconds = bottom_cm * 60 / ASCENT_RATE_CM_PER_MIN;
+        let ascent_start = (self.max_time as u32 * 60).saturating_sub(ascent_seconds).max(descent_seconds);
+        let depth_cm = if seconds < descent_seconds {
+            seconds * DESCENT_RATE_CM_PER_MIN / 60
+        } else if seconds < ascent_start {
+            bottom_cm
+        } else {
+            bottom_cm.saturating_sub((seconds - ascent_start) * ASCENT_RATE_CM_PER_MIN / 60)
+        };
+        depth_cm as u16
+    }
+
+    /// Returns the current depth in centimeters
+    fn depth_cm(&self) -> u16 {
+        if self.diving {
+            self.profile.current_depth_cm
+        } else {
+            0
+        }
+    }
+
+    /// Returns the current battery drain in thousandths of a percent per second
+    fn drain(&self) -> u32 {
+        if self.low_power {
+            LOW_POWER_DRAIN
+        } else if self.diving {
+            DIVE_DRAIN
+        } else {
+            SURFACE_DRAIN
+        }
+    }
+
+    /// Returns the battery level in percent, rounded up
+    fn battery_percent(&self) -> u8 {
+        self.battery.div_ceil(FULL_BATTERY / 100) as u8
+    }
+
+    /// Restores the factory settings and gases
+    fn reset_settings(&mut self) {
+        self.max_depth_cm = 4000;
+        self.max_time = 60;
+        self.profile = DiveProfile::new(GasType::Air);
+        self.profile.set_gradient_factors(GradientFactors { low: 30, high: 85 });
+        self.surface = SurfaceState::new();
+    }
+
+    /// Stores a dive log under the next dive ID
+    fn store_log(&mut self, data: [u8; 32]) {
+        self.logs.insert(self.next_dive_id, data);
+        self.next_dive_id += 1;
+    }
+
+    /// Fails with `DeviceBusy` while a dive is in progress
+    fn ensure_surface(&self) -> Result<(), CommandError> {
+        if self.diving {
+            return Err(CommandError::DeviceBusy);
+        }
+        Ok(())
+    }
+
+    /// Fails with `DeviceBusy` in low-power mode
+    fn ensure_awake(&self) -> Result<(), CommandError> {
+        if self.low_power {
+            return Err(CommandError::DeviceBusy);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the water temperature at a depth in degrees Celsius (scaled by 10)
+fn temperature_x10(depth_cm: u16) -> i16 {
+    SURFACE_TEMPERATURE_X10 - (depth_cm / 100) as i16 * TEMPERATURE_DROP_X10_PER_M
+}
+
+/// Builds a dive log entry
+///
+/// Layout (big-endian): maximum depth in centimeters (u16), duration in
+/// seconds (u32), minimum temperature in degrees Celsius scaled by 10 (i16),
+/// oxygen and helium percentage of the first gas (u8 each), CNS percentage
+/// at the end of the dive (u16); the remaining bytes are zero.
+fn log_record(max_depth_cm: u16, duration_seconds: u32, temperature_x10: i16, gas: GasType, cns_percent: u16) -> [u8; 32] {
+    let mut data = [0u8; 32];
+    data[0..2].copy_from_slice(&max_depth_cm.to_be_bytes());
+    data[2..6].copy_from_slice(&duration_seconds.to_be_bytes());
+    data[6..8].copy_from_slice(&temperature_x10.to_be_bytes());
+    data[8] = gas.oxygen_percent();
+    data[9] = gas.helium_percent();
+    data[10..12].copy_from_slice(&cns_percent.to_be_bytes());
+    data
+}
+
+impl CommandHandler for SimulatedDevice {
+    fn device_info(&mut self) -> HandlerResult {
+        Ok(Some(ResponsePayload::DeviceInfo {
+            device_id: DEVICE_ID,
+            firmware_version: self.firmware_version,
+            hardware_version: HARDWARE_VERSION,
+        }))
+    }
+
+    fn read_sensor(&mut self, sensor_id: u16, reading_type: u8) -> HandlerResult {
+        self.ensure_awake()?;
+        let depth_cm = self.depth_cm();
+        let value = match (sensor_id, reading_type) {
+            (PRESSURE_SENSOR_ID, t) if t == ReadingType::Depth as u8 => depth_cm as i32,
+            (PRESSURE_SENSOR_ID, t) if t == ReadingType::Pressure as u8 => {
+                let pressure = PressureModel::new(self.barometer_mbar, self.profile.pressure.water);
+                real::round(pressure.depth_to_pressure(depth_cm) * real::from_int(1000))
+            }
+            (TEMPERATURE_SENSOR_ID, t) if t == ReadingType::Temperature as u8 => temperature_x10(depth_cm) as i32,
+            (BATTERY_SENSOR_ID, t) if t == ReadingType::Battery as u8 => self.battery_percent() as i32,
+            (PRESSURE_SENSOR_ID | TEMPERATURE_SENSOR_ID | BATTERY_SENSOR_ID, _) => {
+                return Err(CommandError::ReadingTypeNotSupported);
+            }
+            _ => return Err(CommandError::SensorNotFound),
+        };
+        Ok(Some(ResponsePayload::SensorData { sensor_id: sensor_id as u8, reading_type, value }))
+    }
+
+    fn start_dive(&mut self) -> HandlerResult {
+        self.ensure_awake()?;
+        self.ensure_surface()?;
+        if self.battery_percent() < MIN_DIVE_BATTERY_PERCENT {
+            return Err(CommandError::LowBattery);
+        }
+        // The next dive keeps the configured gases and settings and starts
+        // from the residual loading of the previous dives
+        self.surface.set_pressure_model(PressureModel::new(self.barometer_mbar, self.profile.pressure.water));
+        let mut profile = self.surface.start_dive(self.profile.gas);
+        profile.gases = self.profile.gases;
+        profile.current_gas_slot = self.profile.current_gas_slot;
+        profile.set_gradient_factors(self.profile.gradient_factors);
+        profile.update_temperature(SURFACE_TEMPERATURE_X10);
+        self.profile = profile;
+        self.diving = true;
+        Ok(None)
+    }
+
+    fn end_dive(&mut self) -> HandlerResult {
+        if !self.diving {
+            return Err(CommandError::DeviceBusy);
+        }
+        // Surface directly from the current depth
+        let depth_cm = self.profile.current_depth_cm as u32;
+        if depth_cm > 0 {
+            self.profile.record_sample(0, (depth_cm * 60).div_ceil(ASCENT_RATE_CM_PER_MIN));
+        }
+        self.surface.end_dive(&self.profile);
+        self.diving = false;
+
+        let first_gas = self.profile.gases[0].map_or(self.profile.gas, |slot| slot.gas);
+        let record = log_record(
+            self.profile.max_depth_cm,
+            self.profile.duration_seconds,
+            temperature_x10(self.profile.max_depth_cm),
+            first_gas,
+            self.profile.oxygen.cns_percent_rounded(),
+        );
+        self.store_log(record);
+        Ok(None)
+    }
+
+    fn set_parameters(&mut self, max_depth: u16, max_time: u16) -> HandlerResult {
+        if max_depth == 0 || max_time == 0 {
+            return Err(CommandError::InvalidParameters);
+        }
+        // The command sets meters; the simulation and DiveParameters use centimeters
+        self.max_depth_cm = max_depth.saturating_mul(100);
+        self.max_time = max_time;
+        Ok(None)
+    }
+
+    fn get_parameters(&mut self) -> HandlerResult {
+        let elapsed_time = if self.diving { self.profile.duration_seconds.min(u16::MAX as u32) as u16 } else { 0 };
+        Ok(Some(ResponsePayload::DiveParameters {
+            max_depth: self.max_depth_cm,
+            max_time: self.max_time,
+            current_depth: self.depth_cm(),
+            elapsed_time,
+        }))
+    }
+
+    fn set_deco_parameters(&mut self, gf_low: u8, gf_high: u8, water_density: u8) -> HandlerResult {
+        let gradient_factors = GradientFactors::new(gf_low, gf_high).ok_or(CommandError::InvalidParameters)?;
+        let water = WaterDensity::from_u8(water_density).ok_or(CommandError::InvalidParameters)?;
+        self.profile.set_gradient_factors(gradient_factors);
+        // The water density of a dive in progress is fixed at its start
+        if !self.diving {
+            self.profile.pressure.water = water;
+        }
+        Ok(None)
+    }
+
+    fn get_deco_parameters(&mut self) -> HandlerResult {
+        let surface_pressure = if self.diving { self.profile.pressure.surface_pressure_mbar } else { self.barometer_mbar };
+        Ok(Some(ResponsePayload::DecoParameters {
+            gf_low: self.profile.gradient_factors.low,
+            gf_high: self.profile.gradient_factors.high,
+            water_density: self.profile.pressure.water as u8,
+            surface_pressure,
+        }))
+    }
+
+    fn configure_gas(&mut self, slot: u8, oxygen_percent: u8, helium_percent: u8, active: bool) -> HandlerResult {
+        let gas = GasType::from_percentages(oxygen_percent, helium_percent).ok_or(CommandError::InvalidParameters)?;
+        self.profile.configure_gas(slot, gas, active).map_err(|_| CommandError::InvalidParameters)?;
+        Ok(None)
+    }
+
+    fn switch_gas(&mut self, slot: u8) -> HandlerResult {
+        self.profile.switch_gas(slot).map_err(|_| CommandError::InvalidParameters)?;
+        Ok(None)
+    }
+
+    fn get_gas_configuration(&mut self, slot: u8) -> HandlerResult {
+        if slot as usize >= MAX_GASES {
+            return Err(CommandError::InvalidParameters);
+        }
+        // Empty slots are reported as an inactive gas without oxygen
+        let (oxygen_percent, helium_percent, active) = match self.profile.gases[slot as usize] {
+            Some(gas_slot) => (gas_slot.gas.oxygen_percent(), gas_slot.gas.helium_percent(), gas_slot.active),
+            None => (0, 0, false),
+        };
+        Ok(Some(ResponsePayload::GasConfiguration {
+            slot,
+            oxygen_percent,
+            helium_percent,
+            active,
+            in_use: slot == self.profile.current_gas_slot,
+        }))
+    }
+
+    fn log_dive(&mut self, dive_id: u32, data: &[u8; 32]) -> HandlerResult {
+        self.logs.insert(dive_id, *data);
+        self.next_dive_id = self.next_dive_id.max(dive_id.saturating_add(1));
+        Ok(None)
+    }
+
+    fn get_dive_log(&mut self, dive_id: u32) -> HandlerResult {
+        let data = *self.logs.get(&dive_id).ok_or(CommandError::InvalidParameters)?;
+        Ok(Some(ResponsePayload::DiveLog { dive_id, data }))
+    }
+
+    fn get_oxygen_exposure(&mut self) -> HandlerResult {
+        let oxygen = if self.diving { self.profile.oxygen } else { self.surface.oxygen };
+        Ok(Some(ResponsePayload::OxygenExposure {
+            cns_percent: oxygen.cns_percent_rounded(),
+            otu: oxygen.otu_rounded(),
+        }))
+    }
+
+    fn plan_repetitive_dive(
+        &mut self,
+        surface_interval_minutes: u16,
+        depth: u16,
+        oxygen_percent: u8,
+        helium_percent: u8,
+    ) -> HandlerResult {
+        let gas = GasType::from_percentages(oxygen_percent, helium_percent).ok_or(CommandError::InvalidParameters)?;
+        // The command plans in meters, the tissue model takes centimeters
+        let ndl_minutes = self.surface.ndl_after_surface_interval(
+            surface_interval_minutes,
+            depth.saturating_mul(100),
+            gas,
+            self.profile.gradient_factors,
+        );
+        Ok(Some(ResponsePayload::RepetitiveDivePlan { surface_interval_minutes, ndl_minutes }))
+    }
+
+    fn get_no_fly_time(&mut self) -> HandlerResult {
+        let remaining_minutes = if self.diving { self.surface.no_fly_minutes } else { self.surface.no_fly_remaining_minutes() };
+        Ok(Some(ResponsePayload::NoFlyTime { remaining_minutes }))
+    }
+
+    fn get_battery_status(&mut self) -> HandlerResult {
+        let level = self.battery_percent();
+        Ok(Some(ResponsePayload::BatteryStatus {
+            level,
+            voltage: EMPTY_VOLTAGE_MV + level as u16 * VOLTAGE_MV_PER_PERCENT,
+            estimated_time_remaining: (self.battery / self.drain() / 60).min(u16::MAX as u32) as u16,
+        }))
+    }
+
+    fn enter_low_power_mode(&mut self) -> HandlerResult {
+        self.ensure_surface()?;
+        self.low_power = true;
+        Ok(None)
+    }
+
+    fn exit_low_power_mode(&mut self) -> HandlerResult {
+        self.low_power = false;
+        Ok(None)
+    }
+
+    fn calibrate_sensors(&mut self) -> HandlerResult {
+        self.ensure_awake()?;
+        self.ensure_surface()?;
+        // The barometer reads the ambient pressure at the surface
+        self.profile.pressure.surface_pressure_mbar = self.barometer_mbar;
+        Ok(None)
+    }
+
+    fn run_diagnostic(&mut self) -> HandlerResult {
+        let mut error_codes = [0u8; 4];
+        if self.battery_percent() < MIN_HEALTHY_BATTERY_PERCENT {
+            error_codes[0] = 1;
+        }
+        let status = error_codes.iter().filter(|&&code| code != 0).count() as u8;
+        Ok(Some(ResponsePayload::DiagnosticResults { status, error_codes }))
+    }
+
+    fn factory_reset(&mut self) -> HandlerResult {
+        self.ensure_surface()?;
+        self.reset_settings();
+        self.logs.clear();
+        self.next_dive_id = 1;
+        self.firmware_update = None;
+        Ok(None)
+    }
+
+    fn update_firmware_start(&mut self, version: [u8; 4], total_chunks: u16) -> HandlerResult {
+        self.ensure_surface()?;
+        if total_chunks == 0 {
+            return Err(CommandError::InvalidParameters);
+        }
+        if self.battery_percent() < MIN_UPDATE_BATTERY_PERCENT {
+            return Err(CommandError::LowBattery);
+        }
+        // Starting again discards a previous, incomplete update
+        self.firmware_update = Some(FirmwareUpdate {
+            version,
+            image: vec![0; total_chunks as usize * FIRMWARE_CHUNK_SIZE],
+            received: vec![false; total_chunks as usize],
+        });
+        Ok(None)
+    }
+
+    fn update_firmware_chunk(&mut self, chunk_id: u16, data: &[u8; 32]) -> HandlerResult {
+        let update = self.firmware_update.as_mut().ok_or(CommandError::InvalidParameters)?;
+        let received = update.received.get_mut(chunk_id as usize).ok_or(CommandError::InvalidParameters)?;
+        // Retransmitted chunks overwrite the earlier copy
+        *received = true;
+        let offset = chunk_id as usize * FIRMWARE_CHUNK_SIZE;
+        update.image[offset..offset + FIRMWARE_CHUNK_SIZE].copy_from_slice(data);
+        Ok(None)
+    }
+
+    fn update_firmware_complete(&mut self) -> HandlerResult {
+        let update = self.firmware_update.as_ref().ok_or(CommandError::InvalidParameters)?;
+        if update.received.contains(&false) {
+            return Err(CommandError::InvalidParameters);
+        }
+        println!(
+            "firmware {:?} installed ({} bytes, CRC-16 {:04x})",
+            update.version,
+            update.image.len(),
+            calculate_crc16(&update.image)
+        );
+        self.firmware_version = update.version;
+        self.firmware_update = None;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dive_computer_proto::buhlmann::MAX_NDL_MINUTES;
+
+    #[test]
+    fn max_depth_is_set_in_meters() {
+        let mut device = SimulatedDevice::new(100);
+        assert!(matches!(device.set_parameters(30, 45), Ok(None)));
+        device.start_dive().unwrap();
+        device.advance(5 * 60 * 1000);
+        match device.get_parameters() {
+            Ok(Some(ResponsePayload::DiveParameters { max_depth, max_time, current_depth, .. })) => {
+                assert_eq!((max_depth, max_time), (3000, 45));
+                assert_eq!(current_depth, DIVE_DEPTH_CM);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repetitive_dive_is_planned_at_the_depth_in_meters() {
+        let mut device = SimulatedDevice::new(100);
+        match device.plan_repetitive_dive(60, 30, 21, 0) {
+            Ok(Some(ResponsePayload::RepetitiveDivePlan { surface_interval_minutes, ndl_minutes })) => {
+                assert_eq!(surface_interval_minutes, 60);
+                assert!(ndl_minutes > 0 && ndl_minutes < 30, "NDL at 30 m on air: {}", ndl_minutes);
+                assert!(ndl_minutes < MAX_NDL_MINUTES);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
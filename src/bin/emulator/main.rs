@@ -0,0 +1,341 @@
+//! Dive computer emulator
+//!
+//! Emulates a dive computer that speaks the full protocol, so that apps can
+//! be developed without a physical unit. The emulator answers handshakes,
+//! pairs with apps and runs encrypted sessions, and answers every command
+//! from the simulated state in the `device` module.
+//!
+//! It listens on a TCP socket or serves a serial port or pseudo-terminal.
+//! A pair of connected pseudo-terminals can be created with socat:
+//!
+//! ```text
+//! socat -d -d pty,raw,echo=0,link=/tmp/dive-device pty,raw,echo=0,link=/tmp/dive-host
+//! dive-computer-emulator --serial /tmp/dive-device
+//! ```
+//!
+//! The app then opens `/tmp/dive-host`.
+
+mod device;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::process;
+use std::time::{Duration, Instant};
+
+use dive_computer_proto::commands::{Command, Privilege};
+use dive_computer_proto::dispatcher::Dispatcher;
+use dive_computer_proto::handshake::{self, Capabilities, Hello};
+use dive_computer_proto::protocol::{validate_frame, Message, MessageKind, ProtocolError, StreamDecoder, MAX_MESSAGE_SIZE};
+use dive_computer_proto::session::{BondKey, Pairing, Role, SecurityMessage, Session, KEY_SIZE, NONCE_SIZE};
+use dive_computer_proto::transport::{StreamTransport, Transport};
+
+use device::SimulatedDevice;
+
+/// Time a read waits for data before the simulation advances
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Size of the chunks read from the link
+const READ_CHUNK_SIZE: usize = 64;
+
+/// Command line help
+const USAGE: &str = "\
+Usage: dive-computer-emulator (--tcp ADDRESS | --serial PATH) [OPTIONS]
+
+Links:
+  --tcp ADDRESS              Listen for TCP connections, e.g. 127.0.0.1:4000
+  --serial PATH              Serve a serial port or pseudo-terminal
+
+Options:
+  --speed FACTOR             Run the simulation FACTOR times faster than real time [default: 1]
+  --battery PERCENT          Initial battery level [default: 100]
+  --plain-privilege LEVEL    Privilege of commands sent without a session [default: read-only]
+  --session-privilege LEVEL  Privilege of commands sent in a session [default: configuration]
+
+Privilege levels: read-only, configuration, destructive, firmware";
+
+/// Link to serve
+#[derive(Debug)]
+enum Link {
+    /// TCP address to listen on
+    Tcp(String),
+    /// Path of a serial port or pseudo-terminal
+    Serial(String),
+}
+
+/// Command line options
+#[derive(Debug)]
+struct Options {
+    /// Link to serve
+    link: Link,
+    /// Simulated time per real time
+    speed: u32,
+    /// Initial battery level in percent
+    battery: u8,
+    /// Privilege of commands sent without a session
+    plain_privilege: Privilege,
+    /// Privilege of commands sent in a session
+    session_privilege: Privilege,
+}
+
+impl Options {
+    /// Parses the command line arguments, without the program name
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut link = None;
+        let mut options = Options {
+            link: Link::Tcp(String::new()),
+            speed: 1,
+            battery: 100,
+            plain_privilege: Privilege::ReadOnly,
+            session_privilege: Privilege::Configuration,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--tcp" => link = Some(Link::Tcp(value()?)),
+                "--serial" => link = Some(Link::Serial(value()?)),
+                "--speed" => options.speed = parse_number(&value()?, 1, 10_000)?,
+                "--battery" => options.battery = parse_number(&value()?, 0, 100)?,
+                "--plain-privilege" => options.plain_privilege = parse_privilege(&value()?)?,
+                "--session-privilege" => options.session_privilege = parse_privilege(&value()?)?,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        options.link = link.ok_or("either --tcp or --serial is required")?;
+        Ok(options)
+    }
+}
+
+/// Parses a number within `min..=max`
+fn parse_number<T: TryFrom<u32>>(value: &str, min: u32, max: u32) -> Result<T, String> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("{} is not a number from {} to {}", value, min, max))
+}
+
+/// Parses a privilege level
+fn parse_privilege(value: &str) -> Result<Privilege, String> {
+    match value {
+        "read-only" => Ok(Privilege::ReadOnly),
+        "configuration" => Ok(Privilege::Configuration),
+        "destructive" => Ok(Privilege::Destructive),
+        "firmware" => Ok(Privilege::Firmware),
+        _ => Err(format!("unknown privilege level {}", value)),
+    }
+}
+
+/// Returns random bytes from the operating system
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Emulated device with its security state
+struct Emulator {
+    /// Command line options
+    options: Options,
+    /// Simulated device behind a dispatcher
+    dispatcher: Dispatcher<SimulatedDevice>,
+    /// Real time up to which the simulation has advanced
+    last_tick: Instant,
+    /// Key of the paired app, once pairing completed
+    bond: Option<BondKey>,
+    /// Pairing in progress
+    pairing: Option<Pairing>,
+    /// Session of the current connection
+    session: Option<Session>,
+}
+
+impl Emulator {
+    /// Creates the emulator
+    fn new(options: Options) -> Self {
+        let device = SimulatedDevice::new(options.battery);
+        Emulator {
+            options,
+            dispatcher: Dispatcher::new(device),
+            last_tick: Instant::now(),
+            bond: None,
+            pairing: None,
+            session: None,
+        }
+    }
+
+    /// Advances the simulation to the current time
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let elapsed_ms = now.duration_since(self.last_tick).as_millis() as u64;
+        self.last_tick = now;
+        self.dispatcher.handler_mut().advance(elapsed_ms * self.options.speed as u64);
+    }
+
+    /// Serves one connection until it fails or is closed
+    ///
+    /// The bond outlives the connection; a session has to be started again.
+    fn serve<T: Transport<Error = io::Error>>(&mut self, transport: &mut T) -> io::Result<()> {
+        self.pairing = None;
+        self.session = None;
+        let mut decoder = StreamDecoder::new();
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            let count = transport.read(&mut chunk)?;
+            self.tick();
+            let mut offset = 0;
+            while offset < count {
+                offset += decoder.push(&chunk[offset..count]);
+                while let Some(result) = decoder.next_frame() {
+                    let frame = match result {
+                        Ok(frame) => frame,
+                        Err(error) => {
+                            println!("discarded invalid frame: {:?}", error);
+                            continue;
+                        }
+                    };
+                    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+                    buffer[..frame.len()].copy_from_slice(frame);
+                    match self.handle(&mut buffer, frame.len()) {
+                        Ok(Some(length)) => transport.write_all(&buffer[..length])?,
+                        Ok(None) => {}
+                        Err(error) => println!("discarded message: {:?}", error),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a received message
+    ///
+    /// # Returns
+    ///
+    /// The length of the reply written to `buffer`, if there is one
+    fn handle(&mut self, buffer: &mut [u8; MAX_MESSAGE_SIZE], length: usize) -> Result<Option<usize>, ProtocolError> {
+        let (header, _) = validate_frame(&buffer[..length])?;
+        match header.kind {
+            MessageKind::Handshake => {
+                let remote = Message::<Hello>::try_from(&buffer[..length])?.payload;
+                let local = Hello::with_capabilities(Capabilities::ENCRYPTED_SESSIONS);
+                match handshake::negotiate(&local, &remote) {
+                    Ok(negotiated) => println!("handshake: version {}, {:?}", negotiated.version, negotiated.capabilities),
+                    Err(_) => println!("handshake: no common version with {:?}", remote),
+                }
+                local.to_message(header.sequence)?.serialize_into(buffer).map(Some)
+            }
+            MessageKind::Security => {
+                let message = Message::<SecurityMessage>::try_from(&buffer[..length])?.payload;
+                let reply = match self.handle_security(&message) {
+                    Ok(Some(reply)) => reply,
+                    Ok(None) => return Ok(None),
+                    Err(error) => {
+                        self.pairing = None;
+                        return Err(error);
+                    }
+                };
+                Message::with_version(header.version, MessageKind::Security, header.sequence, reply)?
+                    .serialize_into(buffer)
+                    .map(Some)
+            }
+            MessageKind::Command => {
+                let (length, privilege) = match &mut self.session {
+                    Some(session) => (session.open(&mut buffer[..length])?, session.privilege()),
+                    None => (length, self.options.plain_privilege),
+                };
+                let timestamp = self.dispatcher.handler().now_ms();
+                let response = match Message::<Command>::try_from(&buffer[..length]) {
+                    Ok(command) => {
+                        println!("command {}: {:?}", command.header.sequence, command.payload);
+                        self.dispatcher.dispatch(&command, privilege, timestamp)?
+                    }
+                    Err(_) => self.dispatcher.dispatch_frame(&buffer[..length], privilege, timestamp)?,
+                };
+                println!("response {}: {:?}", response.header.sequence, response.payload.payload);
+                match &mut self.session {
+                    Some(session) => session.seal_message(&response, buffer).map(Some),
+                    None => response.serialize_into(buffer).map(Some),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Handles pairing and session start messages
+    ///
+    /// There is no user on the emulated device, so the verification code is
+    /// printed and accepted automatically.
+    fn handle_security(&mut self, message: &SecurityMessage) -> Result<Option<SecurityMessage>, ProtocolError> {
+        match *message {
+            SecurityMessage::PairingRequest { .. } => {
+                let secret: [u8; KEY_SIZE] = random_bytes().map_err(|_| ProtocolError::InvalidFormat)?;
+                let nonce: [u8; NONCE_SIZE] = random_bytes().map_err(|_| ProtocolError::InvalidFormat)?;
+                let pairing = self.pairing.insert(Pairing::new(Role::Responder, secret, nonce));
+                pairing.handle(message)
+            }
+            SecurityMessage::SessionStart { nonce: peer_nonce } => {
+                let bond = self.bond.as_ref().ok_or(ProtocolError::AuthenticationFailed)?;
+                let nonce: [u8; NONCE_SIZE] = random_bytes().map_err(|_| ProtocolError::InvalidFormat)?;
+                let mut session = Session::new(Role::Responder, bond, nonce, peer_nonce);
+                session.grant(self.options.session_privilege);
+                self.session = Some(session);
+                println!("session started");
+                Ok(Some(SecurityMessage::SessionStart { nonce }))
+            }
+            _ => {
+                let pairing = self.pairing.as_mut().ok_or(ProtocolError::InvalidFormat)?;
+                let reply = pairing.handle(message)?;
+                if let Some(code) = pairing.verification_code() {
+                    println!("pairing: verification code {:06}, accepted", code);
+                    self.bond = Some(pairing.finish()?);
+                    self.pairing = None;
+                }
+                Ok(reply)
+            }
+        }
+    }
+}
+
+/// Serves the configured link
+fn run(options: Options) -> io::Result<()> {
+    match &options.link {
+        Link::Tcp(address) => {
+            let listener = TcpListener::bind(address)?;
+            println!("listening on {}", listener.local_addr()?);
+            let mut emulator = Emulator::new(options);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let peer = stream.peer_addr()?;
+                println!("connected: {}", peer);
+                let mut transport = StreamTransport::from_tcp(stream, READ_TIMEOUT)?;
+                if let Err(error) = emulator.serve(&mut transport) {
+                    println!("disconnected: {} ({})", peer, error);
+                }
+            }
+            Ok(())
+        }
+        Link::Serial(path) => {
+            let mut transport = StreamTransport::open_serial(path, READ_TIMEOUT)?;
+            println!("serving {}", path);
+            Emulator::new(options).serve(&mut transport)
+        }
+    }
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = run(options) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,496 @@
+//! Bühlmann ZH-L16C decompression model
+//!
+//! This module implements the 16-compartment Bühlmann ZH-L16C tissue model
+//! with separate nitrogen and helium loading. The tissue state is updated from
+//! dive profile samples and used to derive the no-decompression limit (NDL),
+//! the decompression ceiling and the saturation of each compartment.
+//!
+//! Conservatism is controlled with gradient factors: the allowed supersaturation
+//! is interpolated between GF-low at the first decompression stop and GF-high
+//! at the surface.
+//!
+//! All pressures handled by this module are absolute pressures in bar.
+
+use serde::{Serialize, Deserialize};
+use crate::dive_calc::GasType;
+use crate::real::{self, Real, ONE, ZERO};
+
+/// Number of tissue compartments in the ZH-L16C model
+pub const COMPARTMENT_COUNT: usize = 16;
+
+/// Partial pressure of water vapour in the lungs in bar
+pub const WATER_VAPOUR_PRESSURE_BAR: Real = real::lit(0.0627);
+
+/// Upper bound reported for the no-decompression limit in minutes
+///
+/// Depths where the tissues never reach their M-value report this value.
+pub const MAX_NDL_MINUTES: u16 = 999;
+
+/// Nitrogen half-times in minutes (compartment 1b is used for the first compartment)
+pub const NITROGEN_HALF_TIMES: [Real; COMPARTMENT_COUNT] = real::lits([
+    5.0, 8.0, 12.5, 18.5, 27.0, 38.3, 54.3, 77.0,
+    109.0, 146.0, 187.0, 239.0, 305.0, 390.0, 498.0, 635.0,
+]);
+
+/// Nitrogen `a` coefficients in bar
+pub const NITROGEN_A: [Real; COMPARTMENT_COUNT] = real::lits([
+    1.1696, 1.0000, 0.8618, 0.7562, 0.6200, 0.5043, 0.4410, 0.4000,
+    0.3750, 0.3500, 0.3295, 0.3065, 0.2835, 0.2610, 0.2480, 0.2327,
+]);
+
+/// Nitrogen `b` coefficients (dimensionless)
+pub const NITROGEN_B: [Real; COMPARTMENT_COUNT] = real::lits([
+    0.5578, 0.6514, 0.7222, 0.7825, 0.8126, 0.8434, 0.8693, 0.8910,
+    0.9092, 0.9222, 0.9319, 0.9403, 0.9477, 0.9544, 0.9602, 0.9653,
+]);
+
+/// Helium half-times in minutes
+pub const HELIUM_HALF_TIMES: [Real; COMPARTMENT_COUNT] = real::lits([
+    1.88, 3.02, 4.72, 6.99, 10.21, 14.48, 20.53, 29.11,
+    41.20, 55.19, 70.69, 90.34, 115.29, 147.42, 188.24, 240.03,
+]);
+
+/// Helium `a` coefficients in bar
+pub const HELIUM_A: [Real; COMPARTMENT_COUNT] = real::lits([
+    1.6189, 1.3830, 1.1919, 1.0458, 0.9220, 0.8205, 0.7305, 0.6502,
+    0.5950, 0.5545, 0.5333, 0.5189, 0.5181, 0.5176, 0.5172, 0.5119,
+]);
+
+/// Helium `b` coefficients (dimensionless)
+pub const HELIUM_B: [Real; COMPARTMENT_COUNT] = real::lits([
+    0.4770, 0.5747, 0.6527, 0.7223, 0.7582, 0.7957, 0.8279, 0.8553,
+    0.8757, 0.8903, 0.8997, 0.9073, 0.9122, 0.9171, 0.9217, 0.9267,
+]);
+
+/// Gradient factors used to reduce the M-values for added conservatism
+///
+/// Both values are percentages of the M-value gradient. GF-low applies at the
+/// first decompression stop and GF-high at the surface, with linear
+/// interpolation in between. Deserialization goes through
+/// `GradientFactors::new`, so out-of-range values are rejected.
+///
+/// ```
+/// use dive_computer_proto::buhlmann::GradientFactors;
+///
+/// let gradient_factors: GradientFactors = postcard::from_bytes(&[30, 85]).unwrap();
+/// assert_eq!(Some(gradient_factors), GradientFactors::new(30, 85));
+/// assert!(postcard::from_bytes::<GradientFactors>(&[90, 40]).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(try_from = "UncheckedGradientFactors")]
+pub struct GradientFactors {
+    /// Gradient factor at the first decompression stop in percent
+    pub low: u8,
+    /// Gradient factor at the surface in percent
+    pub high: u8,
+}
+
+/// Serialized form of `GradientFactors` before the range check
+#[derive(Deserialize)]
+#[serde(rename = "GradientFactors")]
+struct UncheckedGradientFactors {
+    low: u8,
+    high: u8,
+}
+
+impl TryFrom<UncheckedGradientFactors> for GradientFactors {
+    type Error = &'static str;
+
+    fn try_from(unchecked: UncheckedGradientFactors) -> Result<Self, Self::Error> {
+        GradientFactors::new(unchecked.low, unchecked.high).ok_or("gradient factors out of range")
+    }
+}
+
+impl GradientFactors {
+    /// Gradient factors of 100/100, i.e. the unmodified ZH-L16C M-values
+    pub const DEFAULT: GradientFactors = GradientFactors { low: 100, high: 100 };
+
+    /// Creates a new set of gradient factors
+    ///
+    /// Returns `None` unless `1 <= low <= high <= 100`.
+    pub fn new(low: u8, high: u8) -> Option<Self> {
+        if low == 0 || low > high || high > 100 {
+            return None;
+        }
+        Some(GradientFactors { low, high })
+    }
+
+    /// Returns GF-low as a fraction (0.0 - 1.0)
+    pub fn low_fraction(&self) -> Real {
+        real::ratio(self.low as i32, 100)
+    }
+
+    /// Returns GF-high as a fraction (0.0 - 1.0)
+    pub fn high_fraction(&self) -> Real {
+        real::ratio(self.high as i32, 100)
+    }
+
+    /// Returns the gradient factor (as a fraction) that applies at the given ambient pressure
+    ///
+    /// * `ambient_bar` - Ambient pressure at which the gradient factor is needed
+    /// * `first_stop_bar` - Ambient pressure of the first decompression stop
+    /// * `surface_bar` - Surface pressure
+    pub fn at_pressure(&self, ambient_bar: Real, first_stop_bar: Real, surface_bar: Real) -> Real {
+        if first_stop_bar <= surface_bar {
+            return self.high_fraction();
+        }
+        let position = ((ambient_bar - surface_bar) / (first_stop_bar - surface_bar)).clamp(ZERO, ONE);
+        self.high_fraction() + (self.low_fraction() - self.high_fraction()) * position
+    }
+}
+
+impl Default for GradientFactors {
+    fn default() -> Self {
+        GradientFactors::DEFAULT
+    }
+}
+
+/// Inert gas loading of all tissue compartments
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TissueState {
+    /// Nitrogen partial pressure of each compartment in bar
+    pub nitrogen_bar: [Real; COMPARTMENT_COUNT],
+    /// Helium partial pressure of each compartment in bar
+    pub helium_bar: [Real; COMPARTMENT_COUNT],
+}
+
+impl TissueState {
+    /// Creates a tissue state in equilibrium with air at the given surface pressure
+    pub fn surface_saturated(surface_pressure_bar: Real) -> Self {
+        let nitrogen = inspired_pressure(surface_pressure_bar, GasType::Air.nitrogen_fraction());
+        TissueState {
+            nitrogen_bar: [nitrogen; COMPARTMENT_COUNT],
+            helium_bar: [ZERO; COMPARTMENT_COUNT],
+        }
+    }
+
+    /// Updates the tissue loading for a segment spent at constant ambient pressure
+    pub fn update_constant(&mut self, ambient_bar: Real, gas: GasType, seconds: u32) {
+        self.update_linear(ambient_bar, ambient_bar, gas, seconds);
+    }
+
+    /// Updates the tissue loading for a segment with a linear change in ambient pressure
+    ///
+    /// Uses the Schreiner equation, which reduces to the Haldane equation when
+    /// the start and end pressures are equal.
+    pub fn update_linear(&mut self, start_bar: Real, end_bar: Real, gas: GasType, seconds: u32) {
+        if seconds == 0 {
+            return;
+        }
+        let minutes = real::ratio(seconds.min(i32::MAX as u32) as i32, 60);
+        let pressure_rate = (end_bar - start_bar) / minutes;
+
+        for i in 0..COMPARTMENT_COUNT {
+            self.nitrogen_bar[i] = schreiner(
+                self.nitrogen_bar[i],
+                start_bar,
+                pressure_rate,
+                gas.nitrogen_fraction(),
+                NITROGEN_HALF_TIMES[i],
+                minutes,
+            );
+            self.helium_bar[i] = schreiner(
+                self.helium_bar[i],
+                start_bar,
+                pressure_rate,
+                gas.helium_fraction(),
+                HELIUM_HALF_TIMES[i],
+                minutes,
+            );
+        }
+    }
+
+    /// Returns the combined inert gas pressure of a compartment in bar
+    pub fn inert_pressure(&self, compartment: usize) -> Real {
+        self.nitrogen_bar[compartment] + self.helium_bar[compartment]
+    }
+
+    /// Returns the `a` and `b` coefficients of a compartment, weighted by its
+    /// current nitrogen and helium loading
+    pub fn coefficients(&self, compartment: usize) -> (Real, Real) {
+        let nitrogen = self.nitrogen_bar[compartment];
+        let helium = self.helium_bar[compartment];
+        let total = nitrogen + helium;
+        if total <= ZERO {
+            return (NITROGEN_A[compartment], NITROGEN_B[compartment]);
+        }
+        let a = (NITROGEN_A[compartment] * nitrogen + HELIUM_A[compartment] * helium) / total;
+        let b = (NITROGEN_B[compartment] * nitrogen + HELIUM_B[compartment] * helium) / total;
+        (a, b)
+    }
+
+    /// Returns the lowest tolerated ambient pressure (the ceiling) in bar for a
+    /// fixed gradient factor
+    ///
+    /// * `gradient_factor` - Fraction of the M-value gradient allowed (1.0 = plain ZH-L16C)
+    pub fn ceiling_bar(&self, gradient_factor: Real) -> Real {
+        let mut ceiling = ZERO;
+        for i in 0..COMPARTMENT_COUNT {
+            let (a, b) = self.coefficients(i);
+            let tolerated = (self.inert_pressure(i) - a * gradient_factor)
+                / (gradient_factor / b + ONE - gradient_factor);
+            if tolerated > ceiling {
+                ceiling = tolerated;
+            }
+        }
+        ceiling
+    }
+
+    /// Returns the ceiling in bar with the gradient factor interpolated between
+    /// GF-low at `first_stop_bar` and GF-high at `surface_bar`
+    ///
+    /// For each compartment the tolerated pressure `p` is solved from
+    /// `P = p + gf(p) * (p / b + a - p)`, where `gf(p)` is the interpolated
+    /// gradient factor at that same pressure. The result never exceeds the
+    /// GF-low ceiling.
+    pub fn gradient_ceiling_bar(&self, gf: GradientFactors, first_stop_bar: Real, surface_bar: Real) -> Real {
+        let low_ceiling = self.ceiling_bar(gf.low_fraction());
+        if first_stop_bar <= surface_bar {
+            return self.ceiling_bar(gf.high_fraction()).min(low_ceiling);
+        }
+
+        // gf(p) = alpha + beta * p
+        let beta = (gf.low_fraction() - gf.high_fraction()) / (first_stop_bar - surface_bar);
+        let alpha = gf.high_fraction() - beta * surface_bar;
+
+        let mut ceiling = ZERO;
+        for i in 0..COMPARTMENT_COUNT {
+            let (a, b) = self.coefficients(i);
+            let c = ONE / b - ONE;
+            let quadratic = beta * c;
+            let linear = ONE + alpha * c + beta * a;
+            let constant = alpha * a - self.inert_pressure(i);
+
+            let discriminant = linear * linear - real::from_int(4) * quadratic * constant;
+            let tolerated = if discriminant < ZERO {
+                low_ceiling
+            } else {
+                // Smaller root of the (downward opening) parabola, in a form that
+                // stays accurate when the quadratic term vanishes
+                real::from_int(2) * constant / (-linear - real::sqrt(discriminant))
+            };
+            if tolerated > ceiling {
+                ceiling = tolerated;
+            }
+        }
+        ceiling.min(low_ceiling)
+    }
+
+    /// Returns the no-decompression limit in minutes for staying at the given
+    /// ambient pressure while breathing `gas`
+    ///
+    /// The limit is reached when a direct ascent to `surface_bar` would violate
+    /// the GF-high reduced M-value of any compartment. Returns 0 if that is
+    /// already the case and [`MAX_NDL_MINUTES`] if the limit is never reached.
+    pub fn ndl_minutes(&self, ambient_bar: Real, gas: GasType, gf: GradientFactors, surface_bar: Real) -> u16 {
+        let gradient_factor = gf.high_fraction();
+        if self.ceiling_bar(gradient_factor) > surface_bar {
+            return 0;
+        }
+
+        // Step the loading forward until the ceiling would prevent a direct ascent
+        match self.minutes_until(ambient_bar, gas, MAX_NDL_MINUTES, |tissues| {
+            tissues.ceiling_bar(gradient_factor) > surface_bar
+        }) {
+            Some(minutes) => minutes - 1,
+            None => MAX_NDL_MINUTES,
+        }
+    }
+
+    /// Returns the time in minutes until the tissues tolerate `target_bar` when
+    /// staying at `ambient_bar` while breathing `gas`
+    ///
+    /// Returns 0 if `target_bar` is already tolerated and `None` if it is not
+    /// tolerated within `max_minutes`.
+    pub fn desaturation_minutes(
+        &self,
+        ambient_bar: Real,
+        gas: GasType,
+        gradient_factor: Real,
+        target_bar: Real,
+        max_minutes: u16,
+    ) -> Option<u16> {
+        if self.ceiling_bar(gradient_factor) <= target_bar {
+            return Some(0);
+        }
+        self.minutes_until(ambient_bar, gas, max_minutes, |tissues| {
+            tissues.ceiling_bar(gradient_factor) <= target_bar
+        })
+    }
+
+    /// Steps a copy of the loading forward one minute at a time at constant
+    /// ambient pressure and returns the number of minutes after which
+    /// `condition` first holds, or `None` if it does not hold within `max_minutes`
+    fn minutes_until<F>(&self, ambient_bar: Real, gas: GasType, max_minutes: u16, mut condition: F) -> Option<u16>
+    where
+        F: FnMut(&TissueState) -> bool,
+    {
+        let nitrogen_inspired = inspired_pressure(ambient_bar, gas.nitrogen_fraction());
+        let helium_inspired = inspired_pressure(ambient_bar, gas.helium_fraction());
+        let mut nitrogen_factor = [ZERO; COMPARTMENT_COUNT];
+        let mut helium_factor = [ZERO; COMPARTMENT_COUNT];
+        for i in 0..COMPARTMENT_COUNT {
+            nitrogen_factor[i] = real::decay(ONE, NITROGEN_HALF_TIMES[i]);
+            helium_factor[i] = real::decay(ONE, HELIUM_HALF_TIMES[i]);
+        }
+
+        let mut tissues = *self;
+        for minute in 1..=max_minutes {
+            for i in 0..COMPARTMENT_COUNT {
+                tissues.nitrogen_bar[i] = nitrogen_inspired
+                    + (tissues.nitrogen_bar[i] - nitrogen_inspired) * nitrogen_factor[i];
+                tissues.helium_bar[i] = helium_inspired
+                    + (tissues.helium_bar[i] - helium_inspired) * helium_factor[i];
+            }
+            if condition(&tissues) {
+                return Some(minute);
+            }
+        }
+        None
+    }
+
+    /// Returns the saturation of each compartment as a percentage of its
+    /// M-value at the given ambient pressure
+    ///
+    /// Values above 100 mean the compartment exceeds its M-value.
+    pub fn saturation_percent(&self, ambient_bar: Real) -> [u8; COMPARTMENT_COUNT] {
+        let mut saturation = [0u8; COMPARTMENT_COUNT];
+        for (i, value) in saturation.iter_mut().enumerate() {
+            let (a, b) = self.coefficients(i);
+            let m_value = ambient_bar / b + a;
+            let percent = self.inert_pressure(i) / m_value * real::from_int(100);
+            *value = real::round(percent).clamp(0, u8::MAX as i32) as u8;
+        }
+        saturation
+    }
+}
+
+/// Returns the inspired partial pressure of an inert gas fraction at the given
+/// ambient pressure, accounting for water vapour in the lungs
+fn inspired_pressure(ambient_bar: Real, fraction: Real) -> Real {
+    let dry = ambient_bar - WATER_VAPOUR_PRESSURE_BAR;
+    if dry > ZERO { dry * fraction } else { ZERO }
+}
+
+/// Schreiner equation for a single compartment and inert gas
+///
+/// * `tissue_bar` - Initial compartment pressure
+/// * `start_bar` - Ambient pressure at the start of the segment
+/// * `pressure_rate` - Rate of ambient pressure change in bar/minute
+/// * `fraction` - Inert gas fraction of the breathing gas
+/// * `half_time` - Compartment half-time in minutes
+/// * `minutes` - Segment duration in minutes
+fn schreiner(
+    tissue_bar: Real,
+    start_bar: Real,
+    pressure_rate: Real,
+    fraction: Real,
+    half_time: Real,
+    minutes: Real,
+) -> Real {
+    // P = Pi + (P0 - Pi) * e^-kt + R * (t - (1 - e^-kt) / k), arranged so that
+    // no large intermediate terms cancel, which fixed-point cannot represent accurately
+    let inspired = inspired_pressure(start_bar, fraction);
+    let rate = pressure_rate * fraction;
+    inspired
+        + (tissue_bar - inspired) * real::decay(minutes, half_time)
+        + rate * (minutes - real::decay_integral(minutes, half_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dive_calc::{calculate_ndl_cm, PressureModel};
+
+    /// Returns true if `actual` is within `tolerance` of `expected`
+    fn close(actual: Real, expected: f32, tolerance: f32) -> bool {
+        (real::to_f32(actual) - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn ndl_on_air_at_30_m_matches_published_tables() {
+        // ZH-L16C with GF 100/100 and no descent time: 16 minutes
+        let ndl = calculate_ndl_cm(3000, GasType::Air, &PressureModel::STANDARD);
+        assert!(ndl.abs_diff(16) <= 1, "NDL {}", ndl);
+        // Shallower dives allow more time
+        assert!(calculate_ndl_cm(1800, GasType::Air, &PressureModel::STANDARD) > ndl);
+    }
+
+    #[test]
+    fn surface_saturated_tissues_have_no_ceiling() {
+        let surface_bar = PressureModel::STANDARD.surface_pressure_bar();
+        let tissues = TissueState::surface_saturated(surface_bar);
+        assert!(tissues.ceiling_bar(ONE) <= surface_bar);
+        assert!(tissues.ceiling_bar(real::ratio(30, 100)) <= surface_bar);
+        assert!(tissues.gradient_ceiling_bar(GradientFactors { low: 30, high: 85 }, ZERO, surface_bar) <= surface_bar);
+        assert_eq!(tissues.ndl_minutes(surface_bar, GasType::Air, GradientFactors::DEFAULT, surface_bar), MAX_NDL_MINUTES);
+    }
+
+    #[test]
+    fn gradient_factors_are_interpolated_between_first_stop_and_surface() {
+        let gf = GradientFactors::new(30, 85).unwrap();
+        let surface_bar = real::lit(1.0);
+        let first_stop_bar = real::lit(2.2);
+        assert!(close(gf.at_pressure(first_stop_bar, first_stop_bar, surface_bar), 0.30, 0.0001));
+        assert!(close(gf.at_pressure(surface_bar, first_stop_bar, surface_bar), 0.85, 0.0001));
+        assert!(close(gf.at_pressure(real::lit(1.6), first_stop_bar, surface_bar), 0.575, 0.0001));
+        // Outside the range, the nearest gradient factor applies
+        assert!(close(gf.at_pressure(real::lit(4.0), first_stop_bar, surface_bar), 0.30, 0.0001));
+        // Without a decompression stop, GF-high applies everywhere
+        assert!(close(gf.at_pressure(real::lit(4.0), surface_bar, surface_bar), 0.85, 0.0001));
+    }
+
+    #[test]
+    fn gradient_factors_out_of_range_are_rejected() {
+        assert_eq!(GradientFactors::new(30, 85), Some(GradientFactors { low: 30, high: 85 }));
+        assert_eq!(GradientFactors::new(100, 100), Some(GradientFactors::DEFAULT));
+        assert_eq!(GradientFactors::new(0, 85), None);
+        assert_eq!(GradientFactors::new(90, 40), None);
+        assert_eq!(GradientFactors::new(30, 101), None);
+    }
+
+    #[test]
+    fn deserialized_gradient_factors_are_checked() {
+        let mut buffer = [0u8; 2];
+        let bytes = postcard::to_slice(&GradientFactors { low: 30, high: 85 }, &mut buffer).unwrap();
+        assert_eq!(postcard::from_bytes::<GradientFactors>(bytes), Ok(GradientFactors { low: 30, high: 85 }));
+        for invalid in [[0, 85], [90, 40], [30, 101]] {
+            assert!(postcard::from_bytes::<GradientFactors>(&invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn linear_update_without_pressure_change_follows_haldane() {
+        let surface_bar = PressureModel::STANDARD.surface_pressure_bar();
+        let ambient_bar = real::lit(4.0);
+        let mut linear = TissueState::surface_saturated(surface_bar);
+        linear.update_linear(ambient_bar, ambient_bar, GasType::Air, 20 * 60);
+        let mut constant = TissueState::surface_saturated(surface_bar);
+        constant.update_constant(ambient_bar, GasType::Air, 20 * 60);
+
+        let start = real::to_f32(inspired_pressure(surface_bar, GasType::Air.nitrogen_fraction()));
+        let inspired = real::to_f32(inspired_pressure(ambient_bar, GasType::Air.nitrogen_fraction()));
+        for (i, half_time) in NITROGEN_HALF_TIMES.iter().enumerate() {
+            let remaining = libm::exp2f(-20.0 / real::to_f32(*half_time));
+            let expected = inspired + (start - inspired) * remaining;
+            assert!(close(linear.nitrogen_bar[i], expected, 0.0001), "compartment {}", i);
+            assert!(close(constant.nitrogen_bar[i], expected, 0.0001), "compartment {}", i);
+            assert_eq!(linear.helium_bar[i], ZERO);
+        }
+    }
+
+    #[test]
+    fn linear_updates_can_be_split() {
+        let surface_bar = PressureModel::STANDARD.surface_pressure_bar();
+        let gas = GasType::Trimix { oxygen_percent: 21, helium_percent: 35 };
+        let mut whole = TissueState::surface_saturated(surface_bar);
+        whole.update_linear(surface_bar, real::lit(5.0), gas, 240);
+        let mut halves = TissueState::surface_saturated(surface_bar);
+        halves.update_linear(surface_bar, real::lit(3.0065), gas, 120);
+        halves.update_linear(real::lit(3.0065), real::lit(5.0), gas, 120);
+        for i in 0..COMPARTMENT_COUNT {
+            assert!(close(halves.nitrogen_bar[i], real::to_f32(whole.nitrogen_bar[i]), 0.0001));
+            assert!(close(halves.helium_bar[i], real::to_f32(whole.helium_bar[i]), 0.0001));
+        }
+    }
+}
@@ -0,0 +1,524 @@
+//! Host-side client
+//!
+//! This module provides [`Client`], the counterpart of `dispatcher::Dispatcher`
+//! for apps and desktop tools. It sends commands over a [`Transport`], assigns
+//! sequence numbers, waits for the response with the matching sequence number
+//! and `command_id`, and returns the response data as typed results instead
+//! of a `ResponsePayload` that has to be matched by hand.
+//!
+//! The client handles one command at a time. Responses to earlier commands,
+//! notifications and other unrelated messages that arrive while it waits are
+//! discarded.
+
+use core::convert::TryFrom;
+use crate::commands::{Command, ErrorCode, Response, ResponsePayload, ResponseStatus};
+use crate::protocol::{validate_frame, Message, MessageKind, ProtocolError, StreamDecoder, MAX_MESSAGE_SIZE, PROTOCOL_VERSION};
+use crate::session::{is_protected, Session};
+use crate::transport::Transport;
+
+/// Size of the chunks read from the transport in bytes
+const READ_CHUNK_SIZE: usize = 64;
+
+/// Error types for client requests
+#[derive(Debug, Eq, PartialEq)]
+pub enum ClientError<E> {
+    /// The transport failed
+    Transport(E),
+    /// A message could not be encoded, or a sealed response with the
+    /// command's sequence number could not be opened (`AuthenticationFailed`
+    /// or `ReplayDetected`)
+    Protocol(ProtocolError),
+    /// No response arrived before the transport's read timeout
+    Timeout,
+    /// The device answered with an error response with this code
+    Device(ErrorCode),
+    /// The response data does not match the command
+    UnexpectedResponse,
+}
+
+impl<E> ClientError<E> {
+    /// Returns the protocol error code that describes the failure
+    ///
+    /// Errors reported by the device return their code and local protocol
+    /// errors the closest code; transport failures and unexpected responses
+    /// have none.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Device(code) => Some(*code),
+            ClientError::Protocol(error) => Some(ErrorCode::from(*error)),
+            ClientError::Timeout => Some(ErrorCode::Timeout),
+            ClientError::Transport(_) | ClientError::UnexpectedResponse => None,
+        }
+    }
+}
+
+impl<E> From<ProtocolError> for ClientError<E> {
+    fn from(error: ProtocolError) -> Self {
+        ClientError::Protocol(error)
+    }
+}
+
+/// Device identification, returned by `Client::device_info`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeviceInfo {
+    /// Unique identifier for the device
+    pub device_id: u32,
+    /// Firmware version as [major, minor, patch, build]
+    pub firmware_version: [u8; 4],
+    /// Hardware version as [major, minor, patch, revision]
+    pub hardware_version: [u8; 4],
+}
+
+/// Sensor reading, returned by `Client::read_sensor`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SensorData {
+    /// ID of the sensor that provided the reading
+    pub sensor_id: u8,
+    /// Type of reading (depth, temperature, etc.)
+    pub reading_type: u8,
+    /// Value of the reading (units depend on reading_type)
+    pub value: i32,
+}
+
+/// Dive parameters, returned by `Client::get_parameters`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DiveParameters {
+    /// Maximum depth setting in centimeters
+    pub max_depth: u16,
+    /// Maximum dive time setting in minutes
+    pub max_time: u16,
+    /// Current depth in centimeters
+    pub current_depth: u16,
+    /// Elapsed dive time in seconds
+    pub elapsed_time: u16,
+}
+
+/// Decompression model parameters, returned by `Client::get_deco_parameters`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecoParameters {
+    /// Gradient factor at the first decompression stop in percent
+    pub gf_low: u8,
+    /// Gradient factor at the surface in percent
+    pub gf_high: u8,
+    /// Water density (0 = salt, 1 = fresh, 2 = EN 13319)
+    pub water_density: u8,
+    /// Surface pressure measured at the start of the dive in millibars
+    pub surface_pressure: u16,
+}
+
+/// Gas slot configuration, returned by `Client::get_gas_configuration`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct GasConfiguration {
+    /// Gas slot index
+    pub slot: u8,
+    /// Oxygen percentage of the gas
+    pub oxygen_percent: u8,
+    /// Helium percentage of the gas
+    pub helium_percent: u8,
+    /// Whether the gas is carried and can be switched to
+    pub active: bool,
+    /// Whether the gas is currently being breathed
+    pub in_use: bool,
+}
+
+/// Dive log entry, returned by `Client::get_dive_log`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DiveLog {
+    /// Unique identifier for the dive
+    pub dive_id: u32,
+    /// Serialized dive data
+    pub data: [u8; 32],
+}
+
+/// Accumulated oxygen exposure, returned by `Client::get_oxygen_exposure`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OxygenExposure {
+    /// CNS oxygen toxicity in percent of the NOAA limit
+    pub cns_percent: u16,
+    /// Pulmonary oxygen toxicity in oxygen tolerance units (OTU)
+    pub otu: u16,
+}
+
+/// Repetitive dive plan, returned by `Client::plan_repetitive_dive`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RepetitiveDivePlan {
+    /// Surface interval the plan assumes in minutes
+    pub surface_interval_minutes: u16,
+    /// No-decompression limit of the planned dive in minutes
+    pub ndl_minutes: u16,
+}
+
+/// Battery status, returned by `Client::get_battery_status`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BatteryStatus {
+    /// Battery level as percentage (0-100)
+    pub level: u8,
+    /// Battery voltage in millivolts
+    pub voltage: u16,
+    /// Estimated time remaining in minutes
+    pub estimated_time_remaining: u16,
+}
+
+/// Diagnostic results, returned by `Client::run_diagnostic`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DiagnosticResults {
+    /// Overall status code (0 = all good, non-zero = issues detected)
+    pub status: u8,
+    /// Specific error codes for different subsystems
+    pub error_codes: [u8; 4],
+}
+
+/// Typed client for a dive computer
+pub struct Client<T> {
+    /// Link to the device
+    transport: T,
+    /// Decoder for received bytes
+    decoder: StreamDecoder,
+    /// Sequence number of the next command
+    next_sequence: u16,
+    /// Protocol version of sent commands
+    version: u8,
+    /// Session protecting the commands, if one is running
+    session: Option<Session>,
+}
+
+impl<T: Transport> Client<T> {
+    /// Creates a client that talks to the device over `transport`
+    pub fn new(transport: T) -> Self {
+        Client {
+            transport,
+            decoder: StreamDecoder::new(),
+            next_sequence: 0,
+            version: PROTOCOL_VERSION,
+            session: None,
+        }
+    }
+
+    /// Returns the transport
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns the transport, dropping the client
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Sets the protocol version of sent commands, e.g. the version agreed in a handshake
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    /// Seals all further commands with `session` and opens the responses
+    pub fn start_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// Stops sealing commands and returns the session
+    pub fn end_session(&mut self) -> Option<Session> {
+        self.session.take()
+    }
+
+    /// Sends a command and waits for its response
+    ///
+    /// Responses with `ResponseStatus::InProgress` or `ResponseStatus::Pending`
+    /// are skipped while waiting for the final one.
+    ///
+    /// # Returns
+    ///
+    /// The response payload of a successful command, `ClientError::Device`
+    /// with the error code of an error response, `ClientError::Protocol` if
+    /// a sealed response could not be opened, or `ClientError::Timeout` if
+    /// a read returned no data before the response was complete
+    pub fn request(&mut self, command: Command) -> Result<Option<ResponsePayload>, ClientError<T::Error>> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let message = Message::with_version(self.version, MessageKind::Command, sequence, command)?;
+        let mut length = message.serialize_into(&mut buffer)?;
+        if let Some(session) = &mut self.session {
+            length = session.seal(&mut buffer, length)?;
+        }
+        self.transport.write_all(&buffer[..length]).map_err(ClientError::Transport)?;
+
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            let count = self.transport.read(&mut chunk).map_err(ClientError::Transport)?;
+            if count == 0 {
+                return Err(ClientError::Timeout);
+            }
+            let mut offset = 0;
+            while offset < count {
+                offset += self.decoder.push(&chunk[offset..count]);
+                while let Some(result) = self.decoder.next_frame() {
+                    let Ok(frame) = result else { continue };
+                    let response = match decode_response(frame, sequence, self.session.as_mut())? {
+                        Some(response) => response,
+                        None => continue,
+                    };
+                    match response.status {
+                        ResponseStatus::Success => return Ok(response.payload),
+                        ResponseStatus::Error => {
+                            return match response.payload {
+                                Some(ResponsePayload::ErrorInfo { code }) => Err(ClientError::Device(code)),
+                                _ => Err(ClientError::UnexpectedResponse),
+                            };
+                        }
+                        ResponseStatus::InProgress | ResponseStatus::Pending => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a command whose response carries no data
+    fn execute(&mut self, command: Command) -> Result<(), ClientError<T::Error>> {
+        self.request(command).map(|_| ())
+    }
+
+    /// Requests the device identification (`Command::ID`)
+    pub fn device_info(&mut self) -> Result<DeviceInfo, ClientError<T::Error>> {
+        match self.request(Command::ID)? {
+            Some(ResponsePayload::DeviceInfo { device_id, firmware_version, hardware_version }) => {
+                Ok(DeviceInfo { device_id, firmware_version, hardware_version })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads a sensor (`Command::ReadSensor`)
+    pub fn read_sensor(&mut self, sensor_id: u16, reading_type: u8) -> Result<SensorData, ClientError<T::Error>> {
+        match self.request(Command::ReadSensor { sensor_id, reading_type })? {
+            Some(ResponsePayload::SensorData { sensor_id, reading_type, value }) => {
+                Ok(SensorData { sensor_id, reading_type, value })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Starts a dive (`Command::StartDive`)
+    pub fn start_dive(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::StartDive)
+    }
+
+    /// Ends the current dive (`Command::EndDive`)
+    pub fn end_dive(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::EndDive)
+    }
+
+    /// Sets the dive parameters (`Command::SetParameters`)
+    pub fn set_parameters(&mut self, max_depth: u16, max_time: u16) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::SetParameters { max_depth, max_time })
+    }
+
+    /// Requests the dive parameters (`Command::GetParameters`)
+    pub fn get_parameters(&mut self) -> Result<DiveParameters, ClientError<T::Error>> {
+        match self.request(Command::GetParameters)? {
+            Some(ResponsePayload::DiveParameters { max_depth, max_time, current_depth, elapsed_time }) => {
+                Ok(DiveParameters { max_depth, max_time, current_depth, elapsed_time })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Sets the decompression model parameters (`Command::SetDecoParameters`)
+    pub fn set_deco_parameters(&mut self, gf_low: u8, gf_high: u8, water_density: u8) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::SetDecoParameters { gf_low, gf_high, water_density })
+    }
+
+    /// Requests the decompression model parameters (`Command::GetDecoParameters`)
+    pub fn get_deco_parameters(&mut self) -> Result<DecoParameters, ClientError<T::Error>> {
+        match self.request(Command::GetDecoParameters)? {
+            Some(ResponsePayload::DecoParameters { gf_low, gf_high, water_density, surface_pressure }) => {
+                Ok(DecoParameters { gf_low, gf_high, water_density, surface_pressure })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Configures a gas slot (`Command::ConfigureGas`)
+    pub fn configure_gas(
+        &mut self,
+        slot: u8,
+        oxygen_percent: u8,
+        helium_percent: u8,
+        active: bool,
+    ) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::ConfigureGas { slot, oxygen_percent, helium_percent, active })
+    }
+
+    /// Switches to the gas in a slot (`Command::SwitchGas`)
+    pub fn switch_gas(&mut self, slot: u8) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::SwitchGas { slot })
+    }
+
+    /// Requests the configuration of a gas slot (`Command::GetGasConfiguration`)
+    pub fn get_gas_configuration(&mut self, slot: u8) -> Result<GasConfiguration, ClientError<T::Error>> {
+        match self.request(Command::GetGasConfiguration { slot })? {
+            Some(ResponsePayload::GasConfiguration { slot, oxygen_percent, helium_percent, active, in_use }) => {
+                Ok(GasConfiguration { slot, oxygen_percent, helium_percent, active, in_use })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Stores a dive log entry (`Command::LogDive`)
+    pub fn log_dive(&mut self, dive_id: u32, data: [u8; 32]) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::LogDive { dive_id, data })
+    }
+
+    /// Requests a dive log entry (`Command::GetDiveLog`)
+    pub fn get_dive_log(&mut self, dive_id: u32) -> Result<DiveLog, ClientError<T::Error>> {
+        match self.request(Command::GetDiveLog { dive_id })? {
+            Some(ResponsePayload::DiveLog { dive_id, data }) => Ok(DiveLog { dive_id, data }),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Requests the accumulated oxygen exposure (`Command::GetOxygenExposure`)
+    pub fn get_oxygen_exposure(&mut self) -> Result<OxygenExposure, ClientError<T::Error>> {
+        match self.request(Command::GetOxygenExposure)? {
+            Some(ResponsePayload::OxygenExposure { cns_percent, otu }) => Ok(OxygenExposure { cns_percent, otu }),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Plans a repetitive dive (`Command::PlanRepetitiveDive`)
+    pub fn plan_repetitive_dive(
+        &mut self,
+        surface_interval_minutes: u16,
+        depth: u16,
+        oxygen_percent: u8,
+        helium_percent: u8,
+    ) -> Result<RepetitiveDivePlan, ClientError<T::Error>> {
+        let command = Command::PlanRepetitiveDive { surface_interval_minutes, depth, oxygen_percent, helium_percent };
+        match self.request(command)? {
+            Some(ResponsePayload::RepetitiveDivePlan { surface_interval_minutes, ndl_minutes }) => {
+                Ok(RepetitiveDivePlan { surface_interval_minutes, ndl_minutes })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Requests the remaining no-fly time in minutes (`Command::GetNoFlyTime`)
+    pub fn get_no_fly_time(&mut self) -> Result<u16, ClientError<T::Error>> {
+        match self.request(Command::GetNoFlyTime)? {
+            Some(ResponsePayload::NoFlyTime { remaining_minutes }) => Ok(remaining_minutes),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Requests the battery status (`Command::GetBatteryStatus`)
+    pub fn get_battery_status(&mut self) -> Result<BatteryStatus, ClientError<T::Error>> {
+        match self.request(Command::GetBatteryStatus)? {
+            Some(ResponsePayload::BatteryStatus { level, voltage, estimated_time_remaining }) => {
+                Ok(BatteryStatus { level, voltage, estimated_time_remaining })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Enters low power mode (`Command::EnterLowPowerMode`)
+    pub fn enter_low_power_mode(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::EnterLowPowerMode)
+    }
+
+    /// Exits low power mode (`Command::ExitLowPowerMode`)
+    pub fn exit_low_power_mode(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::ExitLowPowerMode)
+    }
+
+    /// Calibrates the sensors (`Command::CalibrateSensors`)
+    pub fn calibrate_sensors(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::CalibrateSensors)
+    }
+
+    /// Runs the self-diagnostic (`Command::RunDiagnostic`)
+    pub fn run_diagnostic(&mut self) -> Result<DiagnosticResults, ClientError<T::Error>> {
+        match self.request(Command::RunDiagnostic)? {
+            Some(ResponsePayload::DiagnosticResults { status, error_codes }) => {
+                Ok(DiagnosticResults { status, error_codes })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Resets the device to factory settings (`Command::FactoryReset`)
+    pub fn factory_reset(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::FactoryReset)
+    }
+
+    /// Starts a firmware update (`Command::UpdateFirmwareStart`)
+    pub fn update_firmware_start(&mut self, version: [u8; 4], total_chunks: u16) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::UpdateFirmwareStart { version, total_chunks })
+    }
+
+    /// Sends a firmware data chunk (`Command::UpdateFirmwareChunk`)
+    pub fn update_firmware_chunk(&mut self, chunk_id: u16, data: [u8; 32]) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::UpdateFirmwareChunk { chunk_id, data })
+    }
+
+    /// Completes a firmware update (`Command::UpdateFirmwareComplete`)
+    pub fn update_firmware_complete(&mut self) -> Result<(), ClientError<T::Error>> {
+        self.execute(Command::UpdateFirmwareComplete)
+    }
+}
+
+/// Decodes a received frame if it is the response to the command with `sequence`
+///
+/// Sealed frames are opened with `session`. Returns `Ok(None)` for unrelated
+/// or undecodable frames, and the error of `Session::open` for a sealed
+/// response with the command's sequence number that cannot be opened.
+fn decode_response(frame: &[u8], sequence: u16, session: Option<&mut Session>) -> Result<Option<Response>, ProtocolError> {
+    let header = match validate_frame(frame) {
+        Ok((header, _)) => header,
+        Err(_) => return Ok(None),
+    };
+    if header.sequence != sequence || !matches!(header.kind, MessageKind::Response | MessageKind::Error) {
+        return Ok(None);
+    }
+
+    let response = match session {
+        Some(session) if is_protected(header.kind) => {
+            let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+            buffer[..frame.len()].copy_from_slice(frame);
+            let length = session.open(&mut buffer[..frame.len()])?;
+            Message::<Response>::try_from(&buffer[..length]).ok()
+        }
+        _ => Message::<Response>::try_from(frame).ok(),
+    };
+    Ok(response.map(|message| message.payload).filter(|response| response.command_id == sequence as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{BondKey, Role, KEY_SIZE, NONCE_SIZE};
+
+    /// Seals a successful response to the command with `sequence`, using
+    /// a device session derived from `bond`
+    fn sealed_response(bond: [u8; KEY_SIZE], sequence: u16) -> ([u8; MAX_MESSAGE_SIZE], usize) {
+        let mut device = Session::new(Role::Responder, &BondKey::from_bytes(bond), [2; NONCE_SIZE], [1; NONCE_SIZE]);
+        let response = Response::success(0, sequence as u32, 0, None);
+        let message = Message::new(MessageKind::Response, sequence, response).unwrap();
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let length = device.seal_message(&message, &mut buffer).unwrap();
+        (buffer, length)
+    }
+
+    #[test]
+    fn sealed_responses_that_cannot_be_opened_are_reported() {
+        let mut host = Session::new(Role::Initiator, &BondKey::from_bytes([5; KEY_SIZE]), [1; NONCE_SIZE], [2; NONCE_SIZE]);
+
+        let (frame, length) = sealed_response([5; KEY_SIZE], 3);
+        let response = decode_response(&frame[..length], 3, Some(&mut host)).unwrap().unwrap();
+        assert_eq!(response.status, ResponseStatus::Success);
+        assert_eq!(decode_response(&frame[..length], 3, Some(&mut host)).err(), Some(ProtocolError::ReplayDetected));
+
+        let (forged, length) = sealed_response([9; KEY_SIZE], 4);
+        assert_eq!(decode_response(&forged[..length], 4, Some(&mut host)).err(), Some(ProtocolError::AuthenticationFailed));
+        // Frames for other commands are skipped without opening them
+        assert!(matches!(decode_response(&forged[..length], 5, Some(&mut host)), Ok(None)));
+    }
+}
@@ -5,6 +5,8 @@
 //! It includes command types, response formats, and status codes.
 
 use serde::{Serialize, Deserialize};
+use crate::protocol::ProtocolError;
+use crate::sensor::SensorResponse;
 
 /// Types of messages that can be exchanged in the dive computer system
 ///
@@ -21,7 +23,8 @@ pub enum MessageType {
 /// Commands that can be sent to the dive computer
 ///
 /// This enum represents all possible operations that can be requested
-/// from the dive computer system.
+/// from the dive computer system. Postcard encodes variants by their index,
+/// so new commands are appended at the end and never inserted in between.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Command {
     /// Request device identification
@@ -74,7 +77,317 @@ pub enum Command {
     /// * `data` - Binary firmware data
     UpdateFirmwareChunk { chunk_id: u16, data: [u8; 32] },
     /// Complete firmware update process
-    UpdateFirmwareComplete
+    UpdateFirmwareComplete,
+    /// Configure a gas for the current or next dive
+    /// 
+    /// * `slot` - Gas slot to configure (0-4)
+    /// * `oxygen_percent` - Oxygen percentage of the gas
+    /// * `helium_percent` - Helium percentage of the gas
+    /// * `active` - Whether the gas is carried and can be switched to
+    ConfigureGas { slot: u8, oxygen_percent: u8, helium_percent: u8, active: bool },
+    /// Switch to the gas in the given slot
+    /// 
+    /// * `slot` - Gas slot to switch to
+    SwitchGas { slot: u8 },
+    /// Get the configuration of a gas slot
+    /// 
+    /// * `slot` - Gas slot to query
+    GetGasConfiguration { slot: u8 },
+    /// Get the accumulated CNS and OTU oxygen exposure
+    GetOxygenExposure,
+    /// Calculate the NDL of a repetitive dive after a further surface interval
+    /// 
+    /// * `surface_interval_minutes` - Additional surface interval in minutes
+    /// * `depth` - Planned depth in meters
+    /// * `oxygen_percent` - Oxygen percentage of the planned gas
+    /// * `helium_percent` - Helium percentage of the planned gas
+    PlanRepetitiveDive { surface_interval_minutes: u16, depth: u16, oxygen_percent: u8, helium_percent: u8 },
+    /// Get the remaining no-fly time after the last dive
+    GetNoFlyTime,
+    /// Set the decompression model parameters for the current or next dive
+    /// 
+    /// * `gf_low` - Gradient factor at the first decompression stop in percent
+    /// * `gf_high` - Gradient factor at the surface in percent
+    /// * `water_density` - Water density (0 = salt, 1 = fresh, 2 = EN 13319)
+    SetDecoParameters { gf_low: u8, gf_high: u8, water_density: u8 },
+    /// Get current decompression model parameters
+    GetDecoParameters
+}
+
+/// Privilege a command requires
+///
+/// Levels are ordered: a peer authorized at one level may also run the
+/// commands of all lower levels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Privilege {
+    /// Reads state without changing it; allowed without authentication
+    ReadOnly,
+    /// Changes settings or the state of the current dive
+    Configuration,
+    /// Erases stored data
+    Destructive,
+    /// Replaces the firmware
+    Firmware,
+}
+
+/// Error code reported in `ResponsePayload::ErrorInfo`
+///
+/// Codes 0x01 - 0x0A are defined by the protocol, codes from
+/// `ErrorCode::VENDOR_START` upwards are reserved for vendor-specific errors.
+/// Codes without a meaning in this library are kept as `Unknown`, so every
+/// `u16` converts to an `ErrorCode` and back unchanged. The values inside
+/// `Vendor` and `Unknown` can only be created from codes in their range, so
+/// each code has exactly one `ErrorCode`. On the wire the code is serialized
+/// as a `u16`.
+///
+/// ```
+/// use dive_computer_proto::commands::{ErrorCode, VendorCode};
+///
+/// assert_eq!(ErrorCode::from_code(0x08), ErrorCode::InsufficientPrivilege);
+/// assert_eq!(ErrorCode::from_code(0x8001), ErrorCode::Vendor(VendorCode::new(0x8001).unwrap()));
+/// assert_eq!(ErrorCode::from_code(0x0042).code(), 0x0042);
+/// assert_eq!(VendorCode::new(0x08), None);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[serde(from = "u16", into = "u16")]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// The command is unknown or not supported by the device
+    InvalidCommand = 0x01,
+    /// The addressed sensor does not exist
+    SensorNotFound = 0x02,
+    /// The sensor does not provide the requested reading type
+    ReadingTypeNotSupported = 0x03,
+    /// The sensor did not answer or reported a fault
+    SensorFailure = 0x04,
+    /// The device cannot run the command right now
+    DeviceBusy = 0x05,
+    /// A command parameter is out of range
+    InvalidParameters = 0x06,
+    /// The operation did not complete in time
+    Timeout = 0x07,
+    /// The sender is not authorized for the command
+    InsufficientPrivilege = 0x08,
+    /// The battery is too low to run the command
+    LowBattery = 0x09,
+    /// An unexpected error occurred on the device
+    Internal = 0x0A,
+    /// Vendor-specific error, `ErrorCode::VENDOR_START` or higher
+    Vendor(VendorCode),
+    /// Code not defined by the protocol, e.g. one added in a newer revision
+    Unknown(UnknownCode),
+}
+
+/// Vendor-specific error code, `ErrorCode::VENDOR_START` or higher
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct VendorCode(u16);
+
+impl VendorCode {
+    /// Creates a vendor-specific code; returns `None` below `ErrorCode::VENDOR_START`
+    pub const fn new(code: u16) -> Option<Self> {
+        if code >= ErrorCode::VENDOR_START {
+            Some(VendorCode(code))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the wire value
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+/// Error code below the vendor range that this library does not define
+///
+/// Only created by `ErrorCode::from_code`, so it never holds a code that has
+/// a named `ErrorCode` variant.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct UnknownCode(u16);
+
+impl UnknownCode {
+    /// Returns the wire value
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl ErrorCode {
+    /// First code reserved for vendor-specific errors
+    pub const VENDOR_START: u16 = 0x8000;
+
+    /// Converts a wire value to an error code
+    pub const fn from_code(code: u16) -> Self {
+        match code {
+            0x01 => ErrorCode::InvalidCommand,
+            0x02 => ErrorCode::SensorNotFound,
+            0x03 => ErrorCode::ReadingTypeNotSupported,
+            0x04 => ErrorCode::SensorFailure,
+            0x05 => ErrorCode::DeviceBusy,
+            0x06 => ErrorCode::InvalidParameters,
+            0x07 => ErrorCode::Timeout,
+            0x08 => ErrorCode::InsufficientPrivilege,
+            0x09 => ErrorCode::LowBattery,
+            0x0A => ErrorCode::Internal,
+            ErrorCode::VENDOR_START..=u16::MAX => ErrorCode::Vendor(VendorCode(code)),
+            _ => ErrorCode::Unknown(UnknownCode(code)),
+        }
+    }
+
+    /// Returns the wire value
+    pub const fn code(self) -> u16 {
+        match self {
+            ErrorCode::InvalidCommand => 0x01,
+            ErrorCode::SensorNotFound => 0x02,
+            ErrorCode::ReadingTypeNotSupported => 0x03,
+            ErrorCode::SensorFailure => 0x04,
+            ErrorCode::DeviceBusy => 0x05,
+            ErrorCode::InvalidParameters => 0x06,
+            ErrorCode::Timeout => 0x07,
+            ErrorCode::InsufficientPrivilege => 0x08,
+            ErrorCode::LowBattery => 0x09,
+            ErrorCode::Internal => 0x0A,
+            ErrorCode::Vendor(VendorCode(code)) | ErrorCode::Unknown(UnknownCode(code)) => code,
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        ErrorCode::from_code(code)
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        code.code()
+    }
+}
+
+impl From<CommandError> for ErrorCode {
+    fn from(error: CommandError) -> Self {
+        error.code()
+    }
+}
+
+/// Reports errors of received messages, e.g. a command that could not be
+/// decoded or opened, with the closest protocol error code
+impl From<ProtocolError> for ErrorCode {
+    fn from(error: ProtocolError) -> Self {
+        match error {
+            ProtocolError::InvalidFormat
+            | ProtocolError::ChecksumMismatch
+            | ProtocolError::InvalidMagic
+            | ProtocolError::UnsupportedVersion
+            | ProtocolError::DeserializationError => ErrorCode::InvalidCommand,
+            ProtocolError::MessageTooLarge => ErrorCode::InvalidParameters,
+            ProtocolError::WindowFull => ErrorCode::DeviceBusy,
+            ProtocolError::ReassemblyTimeout => ErrorCode::Timeout,
+            ProtocolError::AuthenticationFailed | ProtocolError::ReplayDetected => ErrorCode::InsufficientPrivilege,
+            ProtocolError::SerializationError => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Error types for command processing
+///
+/// Each error is reported to the sender as the matching [`ErrorCode`].
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CommandError {
+    /// The command is unknown or not supported by the device
+    InvalidCommand,
+    /// The addressed sensor does not exist
+    SensorNotFound,
+    /// The sensor does not provide the requested reading type
+    ReadingTypeNotSupported,
+    /// The sensor did not answer or reported a fault
+    SensorFailure,
+    /// The device cannot run the command right now
+    DeviceBusy,
+    /// A command parameter is out of range
+    InvalidParameters,
+    /// The operation did not complete in time
+    Timeout,
+    /// The command needs a higher privilege than the sender is authorized for
+    InsufficientPrivilege {
+        /// Privilege the command requires
+        required: Privilege,
+        /// Privilege the sender is authorized for
+        granted: Privilege,
+    },
+    /// The battery is too low to run the command
+    LowBattery,
+    /// An unexpected error occurred on the device
+    Internal,
+}
+
+impl CommandError {
+    /// Returns the error code to report in `ResponsePayload::ErrorInfo`
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommandError::InvalidCommand => ErrorCode::InvalidCommand,
+            CommandError::SensorNotFound => ErrorCode::SensorNotFound,
+            CommandError::ReadingTypeNotSupported => ErrorCode::ReadingTypeNotSupported,
+            CommandError::SensorFailure => ErrorCode::SensorFailure,
+            CommandError::DeviceBusy => ErrorCode::DeviceBusy,
+            CommandError::InvalidParameters => ErrorCode::InvalidParameters,
+            CommandError::Timeout => ErrorCode::Timeout,
+            CommandError::InsufficientPrivilege { .. } => ErrorCode::InsufficientPrivilege,
+            CommandError::LowBattery => ErrorCode::LowBattery,
+            CommandError::Internal => ErrorCode::Internal,
+        }
+    }
+}
+
+impl Command {
+    /// Returns the privilege required to run the command
+    pub fn required_privilege(&self) -> Privilege {
+        match self {
+            Command::ID
+            | Command::ReadSensor { .. }
+            | Command::GetParameters
+            | Command::GetGasConfiguration { .. }
+            | Command::GetDiveLog { .. }
+            | Command::GetOxygenExposure
+            | Command::PlanRepetitiveDive { .. }
+            | Command::GetNoFlyTime
+            | Command::GetDecoParameters
+            | Command::GetBatteryStatus
+            | Command::RunDiagnostic => Privilege::ReadOnly,
+            Command::StartDive
+            | Command::EndDive
+            | Command::SetParameters { .. }
+            | Command::SetDecoParameters { .. }
+            | Command::ConfigureGas { .. }
+            | Command::SwitchGas { .. }
+            | Command::LogDive { .. }
+            | Command::EnterLowPowerMode
+            | Command::ExitLowPowerMode
+            | Command::CalibrateSensors => Privilege::Configuration,
+            Command::FactoryReset => Privilege::Destructive,
+            Command::UpdateFirmwareStart { .. }
+            | Command::UpdateFirmwareChunk { .. }
+            | Command::UpdateFirmwareComplete => Privilege::Firmware,
+        }
+    }
+
+    /// Checks that a sender authorized at `granted` may run the command
+    ///
+    /// # Arguments
+    ///
+    /// * `granted` - Privilege of the sender, e.g. `Session::privilege` for
+    ///   messages that arrived sealed and `Privilege::ReadOnly` otherwise
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the command may run, or `InsufficientPrivilege`
+    pub fn authorize(&self, granted: Privilege) -> Result<(), CommandError> {
+        let required = self.required_privilege();
+        if granted < required {
+            return Err(CommandError::InsufficientPrivilege { required, granted });
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -148,7 +461,7 @@ impl Response {
     /// # Returns
     ///
     /// A new `Response` instance with Error status and ErrorInfo payload
-    pub fn error(id: u32, command_id: u32, timestamp: u64, error_code: u16) -> Self {
+    pub fn error(id: u32, command_id: u32, timestamp: u64, error_code: ErrorCode) -> Self {
         Response::new(
             id, 
             command_id, 
@@ -174,7 +487,8 @@ pub enum ResponseStatus {
 /// Payload data for responses
 ///
 /// This enum represents the different types of data that can be included
-/// in a response from the dive computer.
+/// in a response from the dive computer. Like `Command`, new payloads are
+/// appended at the end so the encoded index of existing ones stays the same.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ResponsePayload {
     /// Device identification information
@@ -232,8 +546,106 @@ pub enum ResponsePayload {
     /// Error information
     ErrorInfo {
         /// Specific error code
-        code: u16,
+        code: ErrorCode,
     },
     /// Acknowledgment with no data
     Ack,
+    /// Gas slot configuration
+    GasConfiguration {
+        /// Gas slot index
+        slot: u8,
+        /// Oxygen percentage of the gas
+        oxygen_percent: u8,
+        /// Helium percentage of the gas
+        helium_percent: u8,
+        /// Whether the gas is carried and can be switched to
+        active: bool,
+        /// Whether the gas is currently being breathed
+        in_use: bool,
+    },
+    /// Accumulated oxygen exposure
+    OxygenExposure {
+        /// CNS oxygen toxicity in percent of the NOAA limit
+        cns_percent: u16,
+        /// Pulmonary oxygen toxicity in oxygen tolerance units (OTU)
+        otu: u16,
+    },
+    /// Repetitive dive planning result
+    RepetitiveDivePlan {
+        /// Surface interval the plan assumes in minutes
+        surface_interval_minutes: u16,
+        /// No-decompression limit of the planned dive in minutes
+        ndl_minutes: u16,
+    },
+    /// Remaining no-fly time
+    NoFlyTime {
+        /// Minutes until flying is considered safe (0 = no restriction)
+        remaining_minutes: u16,
+    },
+    /// Current decompression model parameters
+    DecoParameters {
+        /// Gradient factor at the first decompression stop in percent
+        gf_low: u8,
+        /// Gradient factor at the surface in percent
+        gf_high: u8,
+        /// Water density (0 = salt, 1 = fresh, 2 = EN 13319)
+        water_density: u8,
+        /// Surface pressure measured at the start of the dive in millibars
+        surface_pressure: u16,
+    },
+}
+
+/// Payload of `MessageKind::Notification` messages sent by the dive computer
+/// without a command
+///
+/// Names and log data borrow from the buffer the message was decoded from,
+/// so frequent notifications are decoded without copying. Decode them with
+/// `Message::try_from` on a frame, e.g. from `StreamDecoder::next_frame`.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub enum Notification<'a> {
+    /// A new sensor reading
+    SensorReading(SensorResponse),
+    /// Name of a sensor
+    SensorName {
+        /// ID of the sensor
+        sensor_id: u16,
+        /// Name of the sensor
+        name: &'a str,
+    },
+    /// A chunk of dive log data
+    LogData {
+        /// Unique identifier for the dive
+        dive_id: u32,
+        /// Position of the chunk in the dive log in bytes
+        offset: u32,
+        /// Serialized dive data
+        data: &'a [u8],
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes_round_trip() {
+        for code in 0..=u16::MAX {
+            let error = ErrorCode::from_code(code);
+            assert_eq!(error.code(), code);
+            assert_eq!(ErrorCode::from_code(error.code()), error);
+
+            let mut buffer = [0u8; 3];
+            let bytes = postcard::to_slice(&error, &mut buffer).unwrap();
+            assert_eq!(postcard::from_bytes::<ErrorCode>(bytes).unwrap(), error);
+        }
+    }
+
+    #[test]
+    fn error_codes_have_one_representation() {
+        assert!(matches!(ErrorCode::from_code(0x0042), ErrorCode::Unknown(_)));
+        assert!(matches!(ErrorCode::from_code(ErrorCode::VENDOR_START), ErrorCode::Vendor(_)));
+        assert_eq!(VendorCode::new(ErrorCode::VENDOR_START - 1), None);
+        assert_eq!(VendorCode::new(0xFFFF).map(VendorCode::get), Some(0xFFFF));
+        assert_eq!(ErrorCode::from(CommandError::Internal).code(), 0x0A);
+    }
 }
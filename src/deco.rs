@@ -0,0 +1,309 @@
+//! Decompression schedule planning
+//!
+//! This module turns the tissue state of a dive into a full ascent plan with
+//! stop depths, stop times, the gas to breathe at each stop and the total time
+//! to surface. The plan is stored in a fixed-capacity list so it can be kept on
+//! the stack and shown on the device without allocation.
+
+use serde::{Serialize, Deserialize};
+use crate::buhlmann::{GradientFactors, TissueState};
+use crate::dive_calc::{GasType, PressureModel};
+use crate::real::{self, Real};
+
+/// Maximum number of stops a decompression plan can hold
+pub const MAX_DECO_STOPS: usize = 32;
+
+/// Maximum time in minutes spent at a single stop before planning is aborted
+pub const MAX_STOP_MINUTES: u16 = 999;
+
+/// Error types for decompression planning
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DecoError {
+    /// The ascent needs more stops than a plan can hold
+    TooManyStops,
+    /// A single stop would exceed `MAX_STOP_MINUTES`
+    StopTooLong,
+    /// The settings are not usable (e.g. zero ascent rate or stop interval)
+    InvalidSettings,
+}
+
+/// Settings that control how an ascent is planned
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecoSettings {
+    /// Ascent rate between stops in cm/minute
+    pub ascent_rate_cm_per_min: u16,
+    /// Distance between consecutive stops in centimeters
+    pub stop_interval_cm: u16,
+    /// Depth of the last stop in centimeters
+    pub last_stop_cm: u16,
+    /// Highest PPO2 allowed when selecting a gas (multiplied by 100, e.g. 160 = 1.6 bar)
+    pub max_ppo2_x100: u16,
+}
+
+impl Default for DecoSettings {
+    fn default() -> Self {
+        DecoSettings {
+            ascent_rate_cm_per_min: 1000, // 10 m/min
+            stop_interval_cm: 300,        // 3 m
+            last_stop_cm: 300,            // 3 m
+            max_ppo2_x100: 160,           // 1.6 bar
+        }
+    }
+}
+
+/// A single decompression stop
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecoStop {
+    /// Stop depth in centimeters
+    pub depth_cm: u16,
+    /// Time to spend at the stop in minutes
+    pub duration_minutes: u16,
+    /// Gas to breathe during the stop
+    pub gas: GasType,
+}
+
+/// A complete ascent plan
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecoPlan {
+    /// Stops in ascent order, only the first `stop_count` entries are valid
+    stops: [DecoStop; MAX_DECO_STOPS],
+    /// Number of valid stops
+    stop_count: u8,
+    /// Total time to surface in minutes, including ascent and all stops
+    pub total_time_to_surface_minutes: u16,
+}
+
+impl DecoPlan {
+    /// Creates an empty plan with no stops
+    pub fn new() -> Self {
+        DecoPlan {
+            stops: [DecoStop { depth_cm: 0, duration_minutes: 0, gas: GasType::Air }; MAX_DECO_STOPS],
+            stop_count: 0,
+            total_time_to_surface_minutes: 0,
+        }
+    }
+
+    /// Returns the planned stops in ascent order
+    pub fn stops(&self) -> &[DecoStop] {
+        &self.stops[..self.stop_count as usize]
+    }
+
+    /// Returns true if the ascent needs no decompression stops
+    pub fn is_no_decompression(&self) -> bool {
+        self.stop_count == 0
+    }
+
+    /// Appends a stop to the plan
+    fn push(&mut self, stop: DecoStop) -> Result<(), DecoError> {
+        if self.stop_count as usize >= MAX_DECO_STOPS {
+            return Err(DecoError::TooManyStops);
+        }
+        self.stops[self.stop_count as usize] = stop;
+        self.stop_count += 1;
+        Ok(())
+    }
+}
+
+impl Default for DecoPlan {
+    fn default() -> Self {
+        DecoPlan::new()
+    }
+}
+
+/// Plans the ascent from `depth_cm` to the surface
+///
+/// The first stop is placed at the GF-low ceiling; from there the gradient
+/// factor is interpolated towards GF-high at the surface. At every depth the
+/// gas with the highest oxygen fraction whose PPO2 stays within
+/// `settings.max_ppo2_x100` is selected from `current_gas` and `gases`.
+///
+/// # Arguments
+///
+/// * `tissues` - Tissue state at the start of the ascent
+/// * `depth_cm` - Current depth in centimeters
+/// * `current_gas` - Gas breathed at the start of the ascent
+/// * `gases` - Other gases available for the ascent
+/// * `gradient_factors` - Gradient factors for the ceiling calculations
+/// * `pressure` - Surface pressure and water density of the dive
+/// * `settings` - Ascent rate and stop layout
+///
+/// # Returns
+///
+/// The ascent plan, or a `DecoError` if it cannot be represented
+pub fn plan_decompression(
+    tissues: &TissueState,
+    depth_cm: u16,
+    current_gas: GasType,
+    gases: &[GasType],
+    gradient_factors: GradientFactors,
+    pressure: &PressureModel,
+    settings: &DecoSettings,
+) -> Result<DecoPlan, DecoError> {
+    if settings.ascent_rate_cm_per_min == 0 || settings.stop_interval_cm == 0 {
+        return Err(DecoError::InvalidSettings);
+    }
+
+    let mut plan = DecoPlan::new();
+    let mut tissues = *tissues;
+    let mut depth = depth_cm;
+    let mut gas = best_gas(depth, current_gas, gases, pressure, settings.max_ppo2_x100);
+    let mut first_stop_bar: Option<Real> = None;
+    let mut total_seconds: u32 = 0;
+    let surface_bar = pressure.surface_pressure_bar();
+
+    while depth > 0 {
+        let next_depth = next_stop_depth(depth, settings);
+        let next_bar = pressure.depth_to_pressure(next_depth);
+
+        // Wait at the current depth until the ceiling allows the next stop
+        let mut stop_minutes: u16 = 0;
+        loop {
+            let gradient_factor = match first_stop_bar {
+                Some(first_stop) => gradient_factors.at_pressure(next_bar, first_stop, surface_bar),
+                None => gradient_factors.low_fraction(),
+            };
+            if tissues.ceiling_bar(gradient_factor) <= next_bar {
+                break;
+            }
+            if first_stop_bar.is_none() {
+                // This is the first stop; re-check with the interpolated gradient factor
+                first_stop_bar = Some(pressure.depth_to_pressure(depth));
+                continue;
+            }
+            if stop_minutes >= MAX_STOP_MINUTES {
+                return Err(DecoError::StopTooLong);
+            }
+            tissues.update_constant(pressure.depth_to_pressure(depth), gas, 60);
+            stop_minutes += 1;
+        }
+
+        if stop_minutes > 0 {
+            plan.push(DecoStop { depth_cm: depth, duration_minutes: stop_minutes, gas })?;
+            total_seconds += stop_minutes as u32 * 60;
+        }
+
+        // Ascend to the next stop and switch to the best gas available there
+        let ascent_seconds = ((depth - next_depth) as u32 * 60)
+            .div_ceil(settings.ascent_rate_cm_per_min as u32);
+        tissues.update_linear(pressure.depth_to_pressure(depth), next_bar, gas, ascent_seconds);
+        total_seconds += ascent_seconds;
+        depth = next_depth;
+        gas = best_gas(depth, gas, gases, pressure, settings.max_ppo2_x100);
+    }
+
+    plan.total_time_to_surface_minutes = total_seconds.div_ceil(60).min(u16::MAX as u32) as u16;
+    Ok(plan)
+}
+
+/// Returns the next depth on the stop grid above `depth_cm`, or 0 for the surface
+fn next_stop_depth(depth_cm: u16, settings: &DecoSettings) -> u16 {
+    let next = (depth_cm - 1) / settings.stop_interval_cm * settings.stop_interval_cm;
+    if next < settings.last_stop_cm { 0 } else { next }
+}
+
+/// Selects the gas with the highest oxygen fraction that is breathable at `depth_cm`
+///
+/// Falls back to `current_gas` if no other gas is within the PPO2 limit.
+fn best_gas(
+    depth_cm: u16,
+    current_gas: GasType,
+    gases: &[GasType],
+    pressure: &PressureModel,
+    max_ppo2_x100: u16,
+) -> GasType {
+    let ambient_bar = pressure.depth_to_pressure(depth_cm);
+    let max_ppo2 = real::ratio(max_ppo2_x100 as i32, 100);
+    let mut best = current_gas;
+    for &candidate in gases {
+        let ppo2 = ambient_bar * candidate.oxygen_fraction();
+        if ppo2 <= max_ppo2 && candidate.oxygen_fraction() > best.oxygen_fraction() {
+            best = candidate;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EAN50: GasType = GasType::Nitrox { oxygen_percent: 50 };
+
+    /// Returns the tissues after `minutes` at `depth_cm` on air, reached from the surface
+    fn loaded_tissues(depth_cm: u16, minutes: u32) -> TissueState {
+        let pressure = PressureModel::STANDARD;
+        let mut tissues = TissueState::surface_saturated(pressure.surface_pressure_bar());
+        tissues.update_constant(pressure.depth_to_pressure(depth_cm), GasType::Air, minutes * 60);
+        tissues
+    }
+
+    /// Plans the ascent from `depth_cm` on air with GF 30/85
+    fn plan(tissues: &TissueState, depth_cm: u16, gases: &[GasType], settings: &DecoSettings) -> Result<DecoPlan, DecoError> {
+        let gradient_factors = GradientFactors::new(30, 85).unwrap();
+        plan_decompression(tissues, depth_cm, GasType::Air, gases, gradient_factors, &PressureModel::STANDARD, settings)
+    }
+
+    #[test]
+    fn short_dives_need_no_stops() {
+        let plan = plan(&loaded_tissues(1800, 20), 1800, &[], &DecoSettings::default()).unwrap();
+        assert!(plan.is_no_decompression());
+        // 18 m at 10 m/min
+        assert_eq!(plan.total_time_to_surface_minutes, 2);
+    }
+
+    #[test]
+    fn stops_lie_on_the_stop_grid() {
+        for last_stop_cm in [300, 600] {
+            let settings = DecoSettings { last_stop_cm, ..DecoSettings::default() };
+            let plan = plan(&loaded_tissues(4500, 30), 4500, &[], &settings).unwrap();
+            let stops = plan.stops();
+            assert!(stops.len() > 1);
+            for stop in stops {
+                assert_eq!(stop.depth_cm % settings.stop_interval_cm, 0);
+                assert!(stop.depth_cm >= last_stop_cm && stop.depth_cm < 4500);
+                assert!(stop.duration_minutes > 0);
+            }
+            assert!(stops.windows(2).all(|pair| pair[0].depth_cm > pair[1].depth_cm));
+            assert_eq!(stops.last().unwrap().depth_cm, last_stop_cm);
+            let stop_minutes: u16 = stops.iter().map(|stop| stop.duration_minutes).sum();
+            assert!(plan.total_time_to_surface_minutes > stop_minutes);
+        }
+    }
+
+    #[test]
+    fn richer_gas_is_used_from_its_maximum_operating_depth() {
+        let tissues = loaded_tissues(4500, 30);
+        let settings = DecoSettings::default();
+        let with_switch = plan(&tissues, 4500, &[EAN50], &settings).unwrap();
+        let pressure = PressureModel::STANDARD;
+        for stop in with_switch.stops() {
+            // EAN50 stays within 1.6 bar down to 22 m
+            let breathable = pressure.depth_to_pressure(stop.depth_cm) * EAN50.oxygen_fraction() <= real::lit(1.6);
+            assert_eq!(stop.gas, if breathable { EAN50 } else { GasType::Air }, "stop at {} cm", stop.depth_cm);
+        }
+        assert!(with_switch.stops().iter().any(|stop| stop.gas == EAN50));
+
+        let air_only = plan(&tissues, 4500, &[], &settings).unwrap();
+        assert!(with_switch.total_time_to_surface_minutes < air_only.total_time_to_surface_minutes);
+    }
+
+    #[test]
+    fn unusable_settings_are_rejected() {
+        let tissues = loaded_tissues(3000, 20);
+        let settings = DecoSettings { ascent_rate_cm_per_min: 0, ..DecoSettings::default() };
+        assert_eq!(plan(&tissues, 3000, &[], &settings), Err(DecoError::InvalidSettings));
+        let settings = DecoSettings { stop_interval_cm: 0, ..DecoSettings::default() };
+        assert_eq!(plan(&tissues, 3000, &[], &settings), Err(DecoError::InvalidSettings));
+    }
+
+    #[test]
+    fn plans_that_cannot_be_represented_are_rejected() {
+        // Stops every 10 cm from deep down need more entries than a plan holds
+        let settings = DecoSettings { stop_interval_cm: 10, last_stop_cm: 10, ..DecoSettings::default() };
+        assert_eq!(plan(&loaded_tissues(6000, 40), 6000, &[], &settings), Err(DecoError::TooManyStops));
+
+        // A saturation dive cannot reach the next stop 30 m up within the stop time limit
+        let settings = DecoSettings { stop_interval_cm: 3000, last_stop_cm: 3000, ..DecoSettings::default() };
+        assert_eq!(plan(&loaded_tissues(9000, 48 * 60), 9000, &[], &settings), Err(DecoError::StopTooLong));
+    }
+}
@@ -0,0 +1,300 @@
+//! Device-side command dispatching
+//!
+//! This module turns received commands into responses. Firmware implements
+//! [`CommandHandler`], which has one method per `Command`, and a
+//! [`Dispatcher`] decodes incoming command messages, checks the sender's
+//! privilege, calls the matching handler method and builds the response
+//! message with the matching sequence number and `command_id`. Handler
+//! errors are reported to the sender with `Response::error`.
+
+use core::convert::TryFrom;
+use crate::commands::{Command, CommandError, Privilege, Response, ResponsePayload};
+use crate::protocol::{validate_frame, Message, MessageKind, ProtocolError};
+
+/// Result of a handler method: the response payload, if any, or the error to report
+pub type HandlerResult = Result<Option<ResponsePayload>, CommandError>;
+
+/// Device-side implementation of the commands
+///
+/// Every method defaults to `CommandError::InvalidCommand`, so a handler
+/// only implements the commands the device supports. Methods are only called
+/// after the sender has been authorized for the command's privilege.
+pub trait CommandHandler {
+    /// Handles `Command::ID`
+    fn device_info(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::ReadSensor`
+    fn read_sensor(&mut self, sensor_id: u16, reading_type: u8) -> HandlerResult {
+        let _ = (sensor_id, reading_type);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::StartDive`
+    fn start_dive(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::EndDive`
+    fn end_dive(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::SetParameters`
+    fn set_parameters(&mut self, max_depth: u16, max_time: u16) -> HandlerResult {
+        let _ = (max_depth, max_time);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetParameters`
+    fn get_parameters(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::LogDive`
+    fn log_dive(&mut self, dive_id: u32, data: &[u8; 32]) -> HandlerResult {
+        let _ = (dive_id, data);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetDiveLog`
+    fn get_dive_log(&mut self, dive_id: u32) -> HandlerResult {
+        let _ = dive_id;
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetBatteryStatus`
+    fn get_battery_status(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::EnterLowPowerMode`
+    fn enter_low_power_mode(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::ExitLowPowerMode`
+    fn exit_low_power_mode(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::CalibrateSensors`
+    fn calibrate_sensors(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::RunDiagnostic`
+    fn run_diagnostic(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::FactoryReset`
+    fn factory_reset(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::UpdateFirmwareStart`
+    fn update_firmware_start(&mut self, version: [u8; 4], total_chunks: u16) -> HandlerResult {
+        let _ = (version, total_chunks);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::UpdateFirmwareChunk`
+    fn update_firmware_chunk(&mut self, chunk_id: u16, data: &[u8; 32]) -> HandlerResult {
+        let _ = (chunk_id, data);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::UpdateFirmwareComplete`
+    fn update_firmware_complete(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::ConfigureGas`
+    fn configure_gas(&mut self, slot: u8, oxygen_percent: u8, helium_percent: u8, active: bool) -> HandlerResult {
+        let _ = (slot, oxygen_percent, helium_percent, active);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::SwitchGas`
+    fn switch_gas(&mut self, slot: u8) -> HandlerResult {
+        let _ = slot;
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetGasConfiguration`
+    fn get_gas_configuration(&mut self, slot: u8) -> HandlerResult {
+        let _ = slot;
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetOxygenExposure`
+    fn get_oxygen_exposure(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::PlanRepetitiveDive`
+    ///
+    /// `depth` is in meters; convert it to centimeters for
+    /// `SurfaceState::ndl_after_surface_interval`.
+    fn plan_repetitive_dive(
+        &mut self,
+        surface_interval_minutes: u16,
+        depth: u16,
+        oxygen_percent: u8,
+        helium_percent: u8,
+    ) -> HandlerResult {
+        let _ = (surface_interval_minutes, depth, oxygen_percent, helium_percent);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetNoFlyTime`
+    fn get_no_fly_time(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::SetDecoParameters`
+    fn set_deco_parameters(&mut self, gf_low: u8, gf_high: u8, water_density: u8) -> HandlerResult {
+        let _ = (gf_low, gf_high, water_density);
+        Err(CommandError::InvalidCommand)
+    }
+
+    /// Handles `Command::GetDecoParameters`
+    fn get_deco_parameters(&mut self) -> HandlerResult {
+        Err(CommandError::InvalidCommand)
+    }
+}
+
+/// Routes received commands to a [`CommandHandler`] and builds the responses
+#[derive(Debug)]
+pub struct Dispatcher<H> {
+    /// Implementation of the commands
+    handler: H,
+    /// ID of the next response
+    next_response_id: u32,
+}
+
+impl<H: CommandHandler> Dispatcher<H> {
+    /// Creates a dispatcher for the given handler
+    pub fn new(handler: H) -> Self {
+        Dispatcher { handler, next_response_id: 0 }
+    }
+
+    /// Returns the handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Returns the handler for modification
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Decodes a command message and dispatches it
+    ///
+    /// A command the device cannot decode, e.g. one added in a newer protocol
+    /// revision, is answered with `CommandError::InvalidCommand`.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - A complete `MessageKind::Command` message, opened if it arrived sealed
+    /// * `granted` - Privilege of the sender, see `Command::authorize`
+    /// * `timestamp` - Timestamp for the response
+    ///
+    /// # Returns
+    ///
+    /// The response message to send, or `InvalidFormat` if the frame is not a
+    /// command message
+    pub fn dispatch_frame(&mut self, frame: &[u8], granted: Privilege, timestamp: u64) -> Result<Message<Response>, ProtocolError> {
+        let (header, _) = validate_frame(frame)?;
+        if header.kind != MessageKind::Command {
+            return Err(ProtocolError::InvalidFormat);
+        }
+        match Message::<Command>::try_from(frame) {
+            Ok(message) => self.dispatch(&message, granted, timestamp),
+            Err(ProtocolError::DeserializationError) => {
+                let response = self.error_response(header.sequence, CommandError::InvalidCommand, timestamp);
+                Message::with_version(header.version, MessageKind::Error, header.sequence, response)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Dispatches a decoded command message
+    ///
+    /// The response uses the sequence number and version of the request, and
+    /// its `command_id` is the request's sequence number. Successful commands
+    /// are answered with a `MessageKind::Response` message; errors, including
+    /// an insufficient privilege, with a `MessageKind::Error` message carrying
+    /// `Response::error`.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The received command message
+    /// * `granted` - Privilege of the sender, see `Command::authorize`
+    /// * `timestamp` - Timestamp for the response
+    pub fn dispatch(&mut self, message: &Message<Command>, granted: Privilege, timestamp: u64) -> Result<Message<Response>, ProtocolError> {
+        let sequence = message.header.sequence;
+        let result = message.payload.authorize(granted).and_then(|_| self.route(&message.payload));
+        let (kind, response) = match result {
+            Ok(payload) => {
+                let id = self.next_id();
+                (MessageKind::Response, Response::success(id, sequence as u32, timestamp, payload))
+            }
+            Err(error) => (MessageKind::Error, self.error_response(sequence, error, timestamp)),
+        };
+        Message::with_version(message.header.version, kind, sequence, response)
+    }
+
+    /// Calls the handler method for a command
+    fn route(&mut self, command: &Command) -> HandlerResult {
+        let handler = &mut self.handler;
+        match *command {
+            Command::ID => handler.device_info(),
+            Command::ReadSensor { sensor_id, reading_type } => handler.read_sensor(sensor_id, reading_type),
+            Command::StartDive => handler.start_dive(),
+            Command::EndDive => handler.end_dive(),
+            Command::SetParameters { max_depth, max_time } => handler.set_parameters(max_depth, max_time),
+            Command::GetParameters => handler.get_parameters(),
+            Command::LogDive { dive_id, ref data } => handler.log_dive(dive_id, data),
+            Command::GetDiveLog { dive_id } => handler.get_dive_log(dive_id),
+            Command::GetBatteryStatus => handler.get_battery_status(),
+            Command::EnterLowPowerMode => handler.enter_low_power_mode(),
+            Command::ExitLowPowerMode => handler.exit_low_power_mode(),
+            Command::CalibrateSensors => handler.calibrate_sensors(),
+            Command::RunDiagnostic => handler.run_diagnostic(),
+            Command::FactoryReset => handler.factory_reset(),
+            Command::UpdateFirmwareStart { version, total_chunks } => handler.update_firmware_start(version, total_chunks),
+            Command::UpdateFirmwareChunk { chunk_id, ref data } => handler.update_firmware_chunk(chunk_id, data),
+            Command::UpdateFirmwareComplete => handler.update_firmware_complete(),
+            Command::ConfigureGas { slot, oxygen_percent, helium_percent, active } => {
+                handler.configure_gas(slot, oxygen_percent, helium_percent, active)
+            }
+            Command::SwitchGas { slot } => handler.switch_gas(slot),
+            Command::GetGasConfiguration { slot } => handler.get_gas_configuration(slot),
+            Command::GetOxygenExposure => handler.get_oxygen_exposure(),
+            Command::PlanRepetitiveDive { surface_interval_minutes, depth, oxygen_percent, helium_percent } => {
+                handler.plan_repetitive_dive(surface_interval_minutes, depth, oxygen_percent, helium_percent)
+            }
+            Command::GetNoFlyTime => handler.get_no_fly_time(),
+            Command::SetDecoParameters { gf_low, gf_high, water_density } => {
+                handler.set_deco_parameters(gf_low, gf_high, water_density)
+            }
+            Command::GetDecoParameters => handler.get_deco_parameters(),
+        }
+    }
+
+    /// Builds the error response for a command
+    fn error_response(&mut self, sequence: u16, error: CommandError, timestamp: u64) -> Response {
+        let id = self.next_id();
+        Response::error(id, sequence as u32, timestamp, error.code())
+    }
+
+    /// Returns the next response ID
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_response_id;
+        self.next_response_id = self.next_response_id.wrapping_add(1);
+        id
+    }
+}
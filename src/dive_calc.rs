@@ -4,6 +4,113 @@
 //! such as decompression limits, gas consumption, and other diving metrics.
 
 use serde::{Serialize, Deserialize};
+use crate::buhlmann::{GradientFactors, TissueState};
+use crate::deco::{DecoError, DecoPlan, DecoSettings, plan_decompression};
+use crate::oxygen::OxygenExposure;
+use crate::real::{self, Real, ONE, ZERO};
+
+/// Maximum number of gases that can be configured for a dive
+pub const MAX_GASES: usize = 5;
+
+/// Maximum number of gas switches recorded per dive
+pub const MAX_GAS_SWITCHES: usize = 8;
+
+/// Standard gravity in m/s²
+const GRAVITY: Real = real::lit(9.80665);
+
+/// Standard atmospheric pressure at sea level in millibar
+pub const SEA_LEVEL_PRESSURE_MBAR: u16 = 1013;
+
+/// Water density used to convert between depth and pressure
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WaterDensity {
+    /// Sea water (1025 kg/m³)
+    Salt = 0,
+    /// Fresh water (1000 kg/m³)
+    Fresh = 1,
+    /// EN 13319 standard density used by most dive computers (1020 kg/m³)
+    En13319 = 2,
+}
+
+impl WaterDensity {
+    /// Returns the density in kg/m³
+    pub fn kg_per_m3(&self) -> u16 {
+        match self {
+            WaterDensity::Salt => 1025,
+            WaterDensity::Fresh => 1000,
+            WaterDensity::En13319 => 1020,
+        }
+    }
+
+    /// Converts a wire value (as sent in `Command::SetDecoParameters`) to a water density
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(WaterDensity::Salt),
+            1 => Some(WaterDensity::Fresh),
+            2 => Some(WaterDensity::En13319),
+            _ => None,
+        }
+    }
+}
+
+/// Model used for every conversion between depth and absolute pressure
+///
+/// Combines the surface pressure measured at the start of the dive with the
+/// density of the water, so dives at altitude and in fresh water are handled
+/// correctly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PressureModel {
+    /// Surface pressure in millibar
+    pub surface_pressure_mbar: u16,
+    /// Density of the water
+    pub water: WaterDensity,
+}
+
+impl PressureModel {
+    /// Sea level surface pressure with EN 13319 water density
+    pub const STANDARD: PressureModel = PressureModel {
+        surface_pressure_mbar: SEA_LEVEL_PRESSURE_MBAR,
+        water: WaterDensity::En13319,
+    };
+
+    /// Creates a new pressure model
+    ///
+    /// * `surface_pressure_mbar` - Surface pressure measured at the start of the dive
+    /// * `water` - Density of the water
+    pub fn new(surface_pressure_mbar: u16, water: WaterDensity) -> Self {
+        PressureModel { surface_pressure_mbar, water }
+    }
+
+    /// Returns the surface pressure in bar
+    pub fn surface_pressure_bar(&self) -> Real {
+        real::ratio(self.surface_pressure_mbar as i32, 1000)
+    }
+
+    /// Returns the pressure increase per meter of water in bar
+    pub fn bar_per_meter(&self) -> Real {
+        real::ratio(self.water.kg_per_m3() as i32, 1000) * GRAVITY / real::from_int(100)
+    }
+
+    /// Converts a depth in centimeters to absolute ambient pressure in bar
+    pub fn depth_to_pressure(&self, depth_cm: u16) -> Real {
+        self.surface_pressure_bar() + real::ratio(depth_cm as i32, 100) * self.bar_per_meter()
+    }
+
+    /// Converts an absolute ambient pressure in bar to a depth in centimeters
+    ///
+    /// The depth is rounded up; pressures at or below the surface pressure
+    /// return a depth of 0.
+    pub fn pressure_to_depth(&self, pressure_bar: Real) -> u16 {
+        let depth_meters = (pressure_bar - self.surface_pressure_bar()) / self.bar_per_meter();
+        real::ceil_u16(depth_meters * real::from_int(100))
+    }
+}
+
+impl Default for PressureModel {
+    fn default() -> Self {
+        PressureModel::STANDARD
+    }
+}
 
 /// Gas type used in diving
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
@@ -16,6 +123,98 @@ pub enum GasType {
     Trimix { oxygen_percent: u8, helium_percent: u8 },
 }
 
+impl GasType {
+    /// Creates a gas from its oxygen and helium percentages
+    ///
+    /// Returns `None` if the oxygen percentage is zero or the percentages add up
+    /// to more than 100.
+    pub fn from_percentages(oxygen_percent: u8, helium_percent: u8) -> Option<Self> {
+        if oxygen_percent == 0 || oxygen_percent as u16 + helium_percent as u16 > 100 {
+            return None;
+        }
+        match (oxygen_percent, helium_percent) {
+            (21, 0) => Some(GasType::Air),
+            (oxygen_percent, 0) => Some(GasType::Nitrox { oxygen_percent }),
+            (oxygen_percent, helium_percent) => Some(GasType::Trimix { oxygen_percent, helium_percent }),
+        }
+    }
+
+    /// Returns the oxygen percentage of the gas
+    pub fn oxygen_percent(&self) -> u8 {
+        match *self {
+            GasType::Air => 21,
+            GasType::Nitrox { oxygen_percent } => oxygen_percent,
+            GasType::Trimix { oxygen_percent, helium_percent: _ } => oxygen_percent,
+        }
+    }
+
+    /// Returns the helium percentage of the gas
+    pub fn helium_percent(&self) -> u8 {
+        match *self {
+            GasType::Trimix { oxygen_percent: _, helium_percent } => helium_percent,
+            _ => 0,
+        }
+    }
+
+    /// Returns the fraction of oxygen in the gas (0.0 - 1.0)
+    pub fn oxygen_fraction(&self) -> Real {
+        match *self {
+            GasType::Air => real::lit(0.21),
+            GasType::Nitrox { oxygen_percent } => real::ratio(oxygen_percent as i32, 100),
+            GasType::Trimix { oxygen_percent, helium_percent: _ } => real::ratio(oxygen_percent as i32, 100),
+        }
+    }
+
+    /// Returns the fraction of helium in the gas (0.0 - 1.0)
+    pub fn helium_fraction(&self) -> Real {
+        match *self {
+            GasType::Trimix { oxygen_percent: _, helium_percent } => real::ratio(helium_percent as i32, 100),
+            _ => ZERO,
+        }
+    }
+
+    /// Returns the fraction of nitrogen in the gas (0.0 - 1.0)
+    pub fn nitrogen_fraction(&self) -> Real {
+        match *self {
+            GasType::Air => real::lit(0.79),
+            _ => (ONE - self.oxygen_fraction() - self.helium_fraction()).max(ZERO),
+        }
+    }
+}
+
+/// Error types for gas configuration
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum GasError {
+    /// Slot index is outside the gas list
+    InvalidSlot,
+    /// No gas is configured in the slot
+    EmptySlot,
+    /// The gas in the slot is marked inactive
+    InactiveGas,
+    /// The gas currently being breathed cannot be deactivated or removed
+    GasInUse,
+}
+
+/// A gas configured for the dive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct GasSlot {
+    /// Gas mixture
+    pub gas: GasType,
+    /// Whether the gas is carried on this dive and can be switched to
+    pub active: bool,
+}
+
+/// A recorded switch from one gas to another
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct GasSwitch {
+    /// Dive time of the switch in seconds
+    pub time_seconds: u32,
+    /// Depth of the switch in centimeters
+    pub depth_cm: u16,
+    /// Slot of the gas switched to
+    pub slot: u8,
+}
+
 /// Represents a dive profile with depth and time information
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DiveProfile {
@@ -25,28 +224,229 @@ pub struct DiveProfile {
     pub current_depth_cm: u16,
     /// Dive duration in seconds
     pub duration_seconds: u32,
-    /// Gas type being used
+    /// Gas currently being breathed
     pub gas: GasType,
+    /// Configured gases, `None` for empty slots
+    pub gases: [Option<GasSlot>; MAX_GASES],
+    /// Slot of the gas currently being breathed
+    pub current_gas_slot: u8,
+    /// Recorded gas switches, only the first `gas_switch_count` entries are valid
+    pub gas_switches: [GasSwitch; MAX_GAS_SWITCHES],
+    /// Number of recorded gas switches
+    pub gas_switch_count: u8,
     /// Water temperature in degrees Celsius (scaled by 10, e.g., 215 = 21.5°C)
     pub temperature_celsius_x10: i16,
     /// Ascent rate in cm/minute
     pub ascent_rate_cm_per_min: u16,
     /// Descent rate in cm/minute
     pub descent_rate_cm_per_min: u16,
+    /// Bühlmann ZH-L16C tissue loading
+    pub tissues: TissueState,
+    /// Gradient factors applied to the decompression calculations
+    pub gradient_factors: GradientFactors,
+    /// Surface pressure and water density used for depth/pressure conversions
+    pub pressure: PressureModel,
+    /// Deepest GF-low ceiling seen during the dive in bar, anchoring the GF interpolation
+    pub first_stop_bar: Real,
+    /// Accumulated CNS and OTU oxygen exposure
+    pub oxygen: OxygenExposure,
 }
 
 impl DiveProfile {
     /// Creates a new dive profile with default values
+    ///
+    /// `gas` is configured as the active gas in slot 0 and breathed from the start.
+    /// The standard pressure model is used; see [`DiveProfile::with_pressure_model`].
     pub fn new(gas: GasType) -> Self {
+        DiveProfile::with_pressure_model(gas, PressureModel::STANDARD)
+    }
+
+    /// Creates a new dive profile using the surface pressure and water density
+    /// captured at the start of the dive
+    ///
+    /// The tissues start in equilibrium with air at the given surface pressure.
+    pub fn with_pressure_model(gas: GasType, pressure: PressureModel) -> Self {
+        let mut gases = [None; MAX_GASES];
+        gases[0] = Some(GasSlot { gas, active: true });
         DiveProfile {
             max_depth_cm: 0,
             current_depth_cm: 0,
             duration_seconds: 0,
             gas,
+            gases,
+            current_gas_slot: 0,
+            gas_switches: [GasSwitch { time_seconds: 0, depth_cm: 0, slot: 0 }; MAX_GAS_SWITCHES],
+            gas_switch_count: 0,
             temperature_celsius_x10: 200, // 20.0°C
             ascent_rate_cm_per_min: 0,
             descent_rate_cm_per_min: 0,
+            tissues: TissueState::surface_saturated(pressure.surface_pressure_bar()),
+            gradient_factors: GradientFactors::DEFAULT,
+            pressure,
+            first_stop_bar: ZERO,
+            oxygen: OxygenExposure::new(),
+        }
+    }
+
+    /// Configures the gas in a slot
+    ///
+    /// The gas currently being breathed cannot be changed or deactivated.
+    pub fn configure_gas(&mut self, slot: u8, gas: GasType, active: bool) -> Result<(), GasError> {
+        if slot as usize >= MAX_GASES {
+            return Err(GasError::InvalidSlot);
+        }
+        if slot == self.current_gas_slot && (gas != self.gas || !active) {
+            return Err(GasError::GasInUse);
+        }
+        self.gases[slot as usize] = Some(GasSlot { gas, active });
+        Ok(())
+    }
+
+    /// Removes the gas from a slot
+    pub fn remove_gas(&mut self, slot: u8) -> Result<(), GasError> {
+        if slot as usize >= MAX_GASES {
+            return Err(GasError::InvalidSlot);
+        }
+        if slot == self.current_gas_slot {
+            return Err(GasError::GasInUse);
+        }
+        self.gases[slot as usize] = None;
+        Ok(())
+    }
+
+    /// Switches to the gas in `slot` at the current time and depth
+    ///
+    /// The switch is recorded in `gas_switches`. Once the record is full,
+    /// further switches still take effect but are no longer recorded.
+    pub fn switch_gas(&mut self, slot: u8) -> Result<(), GasError> {
+        let gas_slot = self.gases
+            .get(slot as usize)
+            .ok_or(GasError::InvalidSlot)?
+            .ok_or(GasError::EmptySlot)?;
+        if !gas_slot.active {
+            return Err(GasError::InactiveGas);
+        }
+
+        self.gas = gas_slot.gas;
+        self.current_gas_slot = slot;
+        if (self.gas_switch_count as usize) < MAX_GAS_SWITCHES {
+            self.gas_switches[self.gas_switch_count as usize] = GasSwitch {
+                time_seconds: self.duration_seconds,
+                depth_cm: self.current_depth_cm,
+                slot,
+            };
+            self.gas_switch_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the recorded gas switches in the order they happened
+    pub fn recorded_gas_switches(&self) -> &[GasSwitch] {
+        &self.gas_switches[..self.gas_switch_count as usize]
+    }
+
+    /// Returns the active gases and how many of the returned entries are valid
+    pub fn active_gases(&self) -> ([GasType; MAX_GASES], usize) {
+        let mut gases = [GasType::Air; MAX_GASES];
+        let mut count = 0;
+        for gas_slot in self.gases.iter().flatten() {
+            if gas_slot.active {
+                gases[count] = gas_slot.gas;
+                count += 1;
+            }
+        }
+        (gases, count)
+    }
+
+    /// Returns the PPO2 of the gas currently being breathed at the current depth
+    ///
+    /// Returns the PPO2 value multiplied by 100 (e.g., 121 = 1.21 bar)
+    pub fn ppo2(&self) -> u16 {
+        let ppo2 = self.pressure.depth_to_pressure(self.current_depth_cm) * self.gas.oxygen_fraction();
+        real::round_u16(ppo2 * real::from_int(100))
+    }
+
+    /// Sets the gradient factors used for the NDL and ceiling calculations
+    pub fn set_gradient_factors(&mut self, gradient_factors: GradientFactors) {
+        self.gradient_factors = gradient_factors;
+    }
+
+    /// Records a depth sample taken `seconds` after the previous one
+    ///
+    /// The tissue loading is updated assuming a linear depth change between the
+    /// previous and the new depth, and oxygen exposure is accumulated at the
+    /// average PPO2 of the segment. Duration, depth and ascent/descent rates are
+    /// updated accordingly.
+    pub fn record_sample(&mut self, depth_cm: u16, seconds: u32) {
+        let previous_depth_cm = self.current_depth_cm;
+        let start_bar = self.pressure.depth_to_pressure(previous_depth_cm);
+        let end_bar = self.pressure.depth_to_pressure(depth_cm);
+        self.tissues.update_linear(start_bar, end_bar, self.gas, seconds);
+        self.oxygen.update((start_bar + end_bar) / real::from_int(2) * self.gas.oxygen_fraction(), seconds);
+        let low_ceiling = self.tissues.ceiling_bar(self.gradient_factors.low_fraction());
+        if low_ceiling > self.first_stop_bar {
+            self.first_stop_bar = low_ceiling;
+        }
+
+        if let Some(rate) = (depth_cm.abs_diff(previous_depth_cm) as u32 * 60).checked_div(seconds) {
+            let rate = rate.min(u16::MAX as u32) as u16;
+            if depth_cm < previous_depth_cm {
+                self.ascent_rate_cm_per_min = rate;
+                self.descent_rate_cm_per_min = 0;
+            } else {
+                self.ascent_rate_cm_per_min = 0;
+                self.descent_rate_cm_per_min = rate;
+            }
         }
+
+        self.update_depth(depth_cm);
+        self.increment_duration(seconds);
+    }
+
+    /// Returns the no-decompression limit in minutes at the current depth
+    pub fn ndl_minutes(&self) -> u16 {
+        self.tissues.ndl_minutes(
+            self.pressure.depth_to_pressure(self.current_depth_cm),
+            self.gas,
+            self.gradient_factors,
+            self.pressure.surface_pressure_bar(),
+        )
+    }
+
+    /// Returns the current decompression ceiling in centimeters (0 = direct ascent possible)
+    ///
+    /// The gradient factor is interpolated between GF-low at the deepest ceiling
+    /// seen during the dive and GF-high at the surface.
+    pub fn ceiling_cm(&self) -> u16 {
+        self.pressure.pressure_to_depth(self.tissues.gradient_ceiling_bar(
+            self.gradient_factors,
+            self.first_stop_bar,
+            self.pressure.surface_pressure_bar(),
+        ))
+    }
+
+    /// Plans the ascent from the current depth using the profile's tissue state
+    ///
+    /// All active gases are considered for the ascent.
+    ///
+    /// * `settings` - Ascent rate and stop layout
+    pub fn decompression_plan(&self, settings: &DecoSettings) -> Result<DecoPlan, DecoError> {
+        let (gases, count) = self.active_gases();
+        plan_decompression(
+            &self.tissues,
+            self.current_depth_cm,
+            self.gas,
+            &gases[..count],
+            self.gradient_factors,
+            &self.pressure,
+            settings,
+        )
+    }
+
+    /// Returns the saturation of each tissue compartment as a percentage of its
+    /// M-value at the current depth
+    pub fn tissue_saturation(&self) -> [u8; crate::buhlmann::COMPARTMENT_COUNT] {
+        self.tissues.saturation_percent(self.pressure.depth_to_pressure(self.current_depth_cm))
     }
 
     /// Updates the current depth and recalculates max depth if needed
@@ -68,93 +468,109 @@ impl DiveProfile {
     }
 }
 
-/// Calculates the no-decompression limit (NDL) in minutes for a given depth and gas
+/// Calculates the no-decompression limit (NDL) in minutes for a given depth in centimeters and gas
 ///
-/// This is a simplified calculation and should not be used for actual dive planning.
-/// Real dive computers use complex algorithms and tables for NDL calculations.
-pub fn calculate_ndl(depth_meters: u16, gas: GasType) -> u16 {
-    // Very simplified NDL calculation
-    // In a real dive computer, this would use decompression models like Bühlmann ZH-L16
-    match gas {
-        GasType::Air => {
-            // Simple formula for air: NDL decreases with depth
-            if depth_meters < 10 {
-                return 219; // Essentially unlimited for recreational diving
-            } else if depth_meters < 15 {
-                return 150;
-            } else if depth_meters < 20 {
-                return 75;
-            } else if depth_meters < 25 {
-                return 40;
-            } else if depth_meters < 30 {
-                return 25;
-            } else if depth_meters < 35 {
-                return 20;
-            } else if depth_meters < 40 {
-                return 15;
-            } else {
-                return 10;
-            }
-        }
-        GasType::Nitrox { oxygen_percent } => {
-            // Nitrox extends NDL due to lower nitrogen content
-            let air_ndl = calculate_ndl(depth_meters, GasType::Air);
-            let extension_factor = (100 - oxygen_percent) as f32 / 79.0;
-            (air_ndl as f32 / extension_factor) as u16
-        }
-        GasType::Trimix { oxygen_percent, helium_percent: _ } => {
-            // Simplified calculation for trimix
-            // In reality, this would be much more complex
-            let air_ndl = calculate_ndl(depth_meters, GasType::Air);
-            let extension_factor = (100 - oxygen_percent) as f32 / 79.0;
-            (air_ndl as f32 / extension_factor) as u16
-        }
-    }
+/// The limit is computed with the Bühlmann ZH-L16C model for a diver whose
+/// tissues are in equilibrium with air at the surface (i.e. no previous dives),
+/// using the default gradient factors.
+/// Use [`DiveProfile::ndl_minutes`] to account for the actual dive history.
+pub fn calculate_ndl_cm(depth_cm: u16, gas: GasType, pressure: &PressureModel) -> u16 {
+    TissueState::surface_saturated(pressure.surface_pressure_bar()).ndl_minutes(
+        pressure.depth_to_pressure(depth_cm),
+        gas,
+        GradientFactors::DEFAULT,
+        pressure.surface_pressure_bar(),
+    )
 }
 
-/// Calculates the partial pressure of oxygen (PPO2) for a given depth and gas
+/// Calculates the partial pressure of oxygen (PPO2) for a given depth in centimeters and gas
 ///
-/// Returns the PPO2 value multiplied by 100 (e.g., 121 = 1.21 bar)
-pub fn calculate_ppo2(depth_meters: u16, gas: GasType) -> u16 {
-    let ambient_pressure = (depth_meters as f32 / 10.0) + 1.0; // in bar
+/// Returns the PPO2 value multiplied by 100 and rounded (e.g., 121 = 1.21 bar)
+pub fn calculate_ppo2_cm(depth_cm: u16, gas: GasType, pressure: &PressureModel) -> u16 {
+    let ambient_pressure = pressure.depth_to_pressure(depth_cm); // in bar
     
-    let oxygen_fraction = match gas {
-        GasType::Air => 0.21,
-        GasType::Nitrox { oxygen_percent } => oxygen_percent as f32 / 100.0,
-        GasType::Trimix { oxygen_percent, helium_percent: _ } => oxygen_percent as f32 / 100.0,
-    };
+    let oxygen_fraction = gas.oxygen_fraction();
     
-    (ambient_pressure * oxygen_fraction * 100.0) as u16
+    real::round_u16(ambient_pressure * oxygen_fraction * real::from_int(100))
 }
 
 /// Calculates estimated gas consumption in liters
 ///
-/// * `depth_meters` - Average depth in meters
+/// * `depth_cm` - Average depth in centimeters
 /// * `duration_minutes` - Dive duration in minutes
 /// * `sac_rate` - Surface Air Consumption rate in liters/minute
-pub fn calculate_gas_consumption(depth_meters: u16, duration_minutes: u16, sac_rate: f32) -> f32 {
-    let ambient_pressure = (depth_meters as f32 / 10.0) + 1.0; // in bar
-    sac_rate * ambient_pressure * duration_minutes as f32
+/// * `pressure` - Pressure model for the dive site
+pub fn calculate_gas_consumption_cm(depth_cm: u16, duration_minutes: u16, sac_rate: Real, pressure: &PressureModel) -> Real {
+    let ambient_pressure = pressure.depth_to_pressure(depth_cm); // in bar
+    sac_rate * ambient_pressure * real::from_int(duration_minutes as i32)
 }
 
-/// Calculates the equivalent air depth (EAD) for nitrox diving
+/// Calculates the equivalent air depth (EAD) for nitrox diving from a depth in centimeters
 ///
-/// Returns the equivalent air depth in meters
-pub fn calculate_ead(depth_meters: u16, gas: GasType) -> Option<u16> {
+/// Returns the equivalent air depth in centimeters, rounded to the nearest centimeter
+pub fn calculate_ead_cm(depth_cm: u16, gas: GasType, pressure: &PressureModel) -> Option<u16> {
     match gas {
-        GasType::Air => Some(depth_meters), // EAD is the same as actual depth for air
-        GasType::Nitrox { oxygen_percent } => {
-            let nitrogen_fraction = (100 - oxygen_percent) as f32 / 100.0;
-            let air_nitrogen_fraction = 0.79;
-            let ead = ((depth_meters as f32 + 10.0) * nitrogen_fraction / air_nitrogen_fraction) - 10.0;
-            Some(ead as u16)
-        }
-        GasType::Trimix { oxygen_percent, helium_percent } => {
-            let nitrogen_percent = 100 - oxygen_percent - helium_percent;
-            let nitrogen_fraction = nitrogen_percent as f32 / 100.0;
-            let air_nitrogen_fraction = 0.79;
-            let ead = ((depth_meters as f32 + 10.0) * nitrogen_fraction / air_nitrogen_fraction) - 10.0;
-            Some(ead as u16)
-        }
-    }
-}
\ No newline at end of file
+        GasType::Air => Some(depth_cm), // EAD is the same as actual depth for air
+        GasType::Nitrox { .. } | GasType::Trimix { .. } => {
+            let ambient_pressure = pressure.depth_to_pressure(depth_cm);
+            let air_nitrogen_fraction = GasType::Air.nitrogen_fraction();
+            let equivalent_pressure = ambient_pressure * gas.nitrogen_fraction() / air_nitrogen_fraction;
+            let ead_meters = (equivalent_pressure - pressure.surface_pressure_bar()) / pressure.bar_per_meter();
+            Some(real::round_u16(ead_meters * real::from_int(100)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns true if `actual` is within `tolerance` of `expected`
+    fn close(actual: Real, expected: f32, tolerance: f32) -> bool {
+        (real::to_f32(actual) - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn depth_and_pressure_round_trip() {
+        for surface_pressure_mbar in [SEA_LEVEL_PRESSURE_MBAR, 890, 700] {
+            for water in [WaterDensity::Salt, WaterDensity::Fresh, WaterDensity::En13319] {
+                let pressure = PressureModel::new(surface_pressure_mbar, water);
+                for depth_cm in [0, 1, 150, 1830, 4000, 10000, 30000] {
+                    let round_trip = pressure.pressure_to_depth(pressure.depth_to_pressure(depth_cm));
+                    // Rounding up may add at most a centimeter
+                    assert!(round_trip == depth_cm || round_trip == depth_cm + 1,
+                        "{} cm became {} cm at {} mbar in {:?}", depth_cm, round_trip, surface_pressure_mbar, water);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fresh_water_is_deeper_at_the_same_pressure() {
+        let salt = PressureModel::new(SEA_LEVEL_PRESSURE_MBAR, WaterDensity::Salt);
+        let fresh = PressureModel::new(SEA_LEVEL_PRESSURE_MBAR, WaterDensity::Fresh);
+        assert!(close(salt.depth_to_pressure(1000), 1.013 + 1.0052, 0.0005));
+        assert!(close(fresh.depth_to_pressure(1000), 1.013 + 0.9807, 0.0005));
+
+        let ambient = real::lit(3.0);
+        let salt_depth = salt.pressure_to_depth(ambient);
+        let fresh_depth = fresh.pressure_to_depth(ambient);
+        // 1.987 bar of water: 19.77 m of sea water, 20.26 m of fresh water
+        assert!(salt_depth.abs_diff(1977) <= 1, "{} cm", salt_depth);
+        assert!(fresh_depth.abs_diff(2027) <= 1, "{} cm", fresh_depth);
+    }
+
+    #[test]
+    fn depth_is_measured_from_the_surface_pressure_at_altitude() {
+        // A lake at roughly 1000 m altitude
+        let pressure = PressureModel::new(890, WaterDensity::Fresh);
+        assert!(close(pressure.surface_pressure_bar(), 0.89, 0.0001));
+        assert!(close(pressure.depth_to_pressure(0), 0.89, 0.0001));
+        assert!(close(pressure.depth_to_pressure(1000), 0.89 + 0.9807, 0.0005));
+        // Pressures at or below the surface are at the surface
+        assert_eq!(pressure.pressure_to_depth(real::lit(0.89)), 0);
+        assert_eq!(pressure.pressure_to_depth(real::lit(0.5)), 0);
+        // Sea level pressure is reached 1.25 m below the lake surface
+        assert!(pressure.pressure_to_depth(real::lit(1.013)).abs_diff(126) <= 1);
+    }
+}
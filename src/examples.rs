@@ -2,11 +2,25 @@
 //!
 //! This module contains examples demonstrating how to use the main functionality
 //! of the dive computer prototype library.
+//!
+//! The examples build values without consuming them, so unused bindings are allowed.
+#![allow(unused_variables)]
 
-use crate::sensor::{Sensor, SensorResponse, ReadingType};
-use crate::commands::{Command, Response, ResponseStatus, ResponsePayload, MessageType};
-use crate::dive_calc::{GasType, DiveProfile, calculate_ndl, calculate_ppo2};
-use crate::protocol::{Message, MessageKind, ProtocolError};
+use crate::sensor::{Sensor, SensorResponse, ReadingType, DepthConverter};
+use crate::commands::{Command, ErrorCode, Notification, Privilege, Response, ResponsePayload};
+use crate::dive_calc::{GasType, DiveProfile, PressureModel, WaterDensity, calculate_ndl_cm, calculate_ppo2_cm};
+use crate::buhlmann::GradientFactors;
+use crate::deco::DecoSettings;
+use crate::surface::SurfaceState;
+use crate::protocol::{Message, MessageKind, ProtocolError, StreamDecoder, MAX_MESSAGE_SIZE};
+use crate::framing::FrameDecoder;
+use crate::reliable::{LinkEvent, Received, ReliableLink};
+use crate::fragment::{self, Fragment, Reassembler};
+use crate::handshake::{self, Capabilities, Hello};
+use crate::session::{Pairing, Role, SecurityMessage, Session};
+use crate::dispatcher::{CommandHandler, Dispatcher, HandlerResult};
+use crate::client::{Client, ClientError};
+use crate::transport::Transport;
 
 /// Example of creating and using sensors
 pub fn sensor_example() {
@@ -32,6 +46,17 @@ pub fn sensor_example() {
         1234567890,             // timestamp
     );
     
+    // Derive depth from the pressure sensor instead of a dedicated depth sensor
+    let mut depth_converter = DepthConverter::new(PressureModel::new(1013, WaterDensity::Salt));
+    
+    // Capture the surface pressure when the sensors are calibrated
+    let surface_reading = SensorResponse::new(3, ReadingType::Pressure, 1008, 1234560000);
+    let _ = depth_converter.calibrate_surface(&surface_reading);
+    
+    // Convert a reading taken at depth (2.53 bar absolute)
+    let pressure_reading = SensorResponse::new(3, ReadingType::Pressure, 2530, 1234567890);
+    let derived_depth = depth_converter.convert(&pressure_reading);
+    
     // Process the readings (in a real application)
     // ...
 }
@@ -56,6 +81,13 @@ pub fn command_example() {
         max_time: 45,   // 45 minutes
     };
     
+    // Create a command to set the decompression model parameters
+    let set_deco_command = Command::SetDecoParameters {
+        gf_low: 30,     // GF 30/85
+        gf_high: 85,
+        water_density: WaterDensity::Fresh as u8,
+    };
+    
     // Example of creating a response to the ID command
     let id_response = Response::success(
         1,              // response id
@@ -73,37 +105,98 @@ pub fn command_example() {
         2,              // response id
         2,              // command id
         1234567890,     // timestamp
-        101,            // error code
+        ErrorCode::SensorFailure, // error code
     );
     
+    // Commands are classified by privilege; a command that arrived without a
+    // session runs at the read-only level and is rejected if it needs more
+    let factory_reset = Command::FactoryReset;
+    if let Err(error) = factory_reset.authorize(Privilege::ReadOnly) {
+        let denied_response = Response::error(3, 3, 1234567890, error.code());
+    }
+    
     // Process the responses (in a real application)
     // ...
 }
 
 /// Example of using dive calculations
 pub fn dive_calc_example() {
-    // Create a dive profile with air
-    let mut dive_profile = DiveProfile::new(GasType::Air);
+    // Create a dive profile with air for a lake dive at altitude,
+    // using the surface pressure measured at the start of the dive
+    let pressure = PressureModel::new(890, WaterDensity::Fresh);  // 890 mbar
+    let mut dive_profile = DiveProfile::with_pressure_model(GasType::Air, pressure);
+    
+    // Use gradient factors 30/85 for added conservatism
+    if let Some(gradient_factors) = GradientFactors::new(30, 85) {
+        dive_profile.set_gradient_factors(gradient_factors);
+    }
     
-    // Update the depth
-    dive_profile.update_depth(1830);  // 18.3 meters
+    // Descend to 18.3 meters over one minute
+    dive_profile.record_sample(1830, 60);
     
-    // Increment the dive time
-    dive_profile.increment_duration(600);  // 10 minutes
+    // Stay at depth for 10 minutes
+    dive_profile.record_sample(1830, 600);
     
     // Update the temperature
     dive_profile.update_temperature(182);  // 18.2°C
     
-    // Calculate no-decompression limit for current depth
-    let depth_meters = dive_profile.current_depth_cm / 100;
-    let ndl = calculate_ndl(depth_meters as u16, dive_profile.gas);
+    // Calculate no-decompression limit from the tissue loading
+    let ndl = dive_profile.ndl_minutes();
+    
+    // Inspect the decompression ceiling and per-tissue saturation
+    let ceiling_cm = dive_profile.ceiling_cm();
+    let saturation = dive_profile.tissue_saturation();
+    
+    // Carry EAN50 as a decompression gas in slot 1
+    let _ = dive_profile.configure_gas(1, GasType::Nitrox { oxygen_percent: 50 }, true);
+    
+    // Plan the ascent; all active gases are considered
+    if let Ok(plan) = dive_profile.decompression_plan(&DecoSettings::default()) {
+        for stop in plan.stops() {
+            // Show stop.depth_cm, stop.duration_minutes and stop.gas on the display
+        }
+        let time_to_surface = plan.total_time_to_surface_minutes;
+    }
     
     // Calculate PPO2 for current depth
-    let ppo2 = calculate_ppo2(depth_meters as u16, dive_profile.gas);
+    let ppo2 = calculate_ppo2_cm(dive_profile.current_depth_cm, dive_profile.gas, &dive_profile.pressure);
+    
+    // Ascend to 6 meters and switch to EAN50; the switch is recorded in the profile
+    dive_profile.record_sample(600, 75);
+    if dive_profile.switch_gas(1).is_ok() {
+        let ppo2_after_switch = dive_profile.ppo2();
+    }
+    
+    // Report the accumulated oxygen exposure, e.g. in a GetOxygenExposure response
+    let oxygen_payload = ResponsePayload::OxygenExposure {
+        cns_percent: dive_profile.oxygen.cns_percent_rounded(),
+        otu: dive_profile.oxygen.otu_rounded(),
+    };
+    
+    // Keep the residual loading after the dive and spend an hour at the surface
+    let mut surface_state = SurfaceState::new();
+    surface_state.end_dive(&dive_profile);
+    surface_state.surface_interval(3600);
+    
+    // Remaining no-fly time, counting down with the surface interval
+    let no_fly = ResponsePayload::NoFlyTime {
+        remaining_minutes: surface_state.no_fly_remaining_minutes(),
+    };
+    
+    // What would the NDL at 18 meters be after another 30 minutes at the surface?
+    let repetitive_ndl = surface_state.ndl_after_surface_interval(
+        30,
+        1800,
+        GasType::Air,
+        dive_profile.gradient_factors,
+    );
+    
+    // Start the next dive with the residual loading
+    let repetitive_profile = surface_state.start_dive(GasType::Air);
     
     // Example with nitrox
     let nitrox_profile = DiveProfile::new(GasType::Nitrox { oxygen_percent: 32 });
-    let nitrox_ndl = calculate_ndl(18, GasType::Nitrox { oxygen_percent: 32 });
+    let nitrox_ndl = calculate_ndl_cm(1800, GasType::Nitrox { oxygen_percent: 32 }, &PressureModel::STANDARD);
     
     // Process the calculations (in a real application)
     // ...
@@ -122,6 +215,10 @@ pub fn protocol_example() -> Result<(), ProtocolError> {
     // Serialize the message
     let (size, buffer) = command_message.serialize()?;
     
+    // Peers that only support protocol version 1 get the same message with
+    // the 8-bit payload checksum instead of the CRC-16
+    let legacy_message = Message::with_version(1, MessageKind::Command, 123, Command::GetBatteryStatus)?;
+    
     // In a real application, the buffer would be sent to the device
     // ...
     
@@ -151,5 +248,265 @@ pub fn protocol_example() -> Result<(), ProtocolError> {
     // In a real application, the response buffer would be sent back
     // ...
     
+    // On the receiving side, bytes arrive in arbitrary chunks; feed them to a
+    // stream decoder and take out complete messages as they become available
+    let mut decoder = StreamDecoder::new();
+    for chunk in response_buffer[..response_size].chunks(5) {
+        let mut offset = 0;
+        while offset < chunk.len() {
+            offset += decoder.push(&chunk[offset..]);
+            while let Some(result) = decoder.next_message::<Response>() {
+                match result {
+                    Ok(message) => {
+                        // Handle message.payload
+                    }
+                    Err(error) => {
+                        // Corrupted frame; the decoder continues with the next one
+                    }
+                }
+            }
+        }
+    }
+    
+    // Messages can also be written straight into a transmit buffer, e.g. one
+    // used for DMA, without an intermediate copy
+    let mut dma_buffer = [0u8; 512];
+    let dma_size = response_message.serialize_into(&mut dma_buffer)?;
+    
+    // Notifications borrow names and log data from the received frame, so
+    // they are decoded in place from next_frame instead of next_message
+    let notification = Notification::SensorName { sensor_id: 1, name: "Depth" };
+    let notification_size = Message::new(MessageKind::Notification, 125, notification)?.serialize_into(&mut dma_buffer)?;
+    let mut decoder = StreamDecoder::new();
+    decoder.push(&dma_buffer[..notification_size]);
+    if let Some(Ok(frame)) = decoder.next_frame() {
+        let message = Message::<Notification>::try_from(frame)?;
+        if let Notification::SensorName { sensor_id, name } = message.payload {
+            // name points into the decoder buffer
+        }
+    }
+    
+    // On noisy serial links, wrap messages in COBS frames instead; every frame
+    // ends with a zero byte, so the receiver resynchronizes after each frame
+    let (frame_size, frame_buffer) = response_message.serialize_framed()?;
+    let mut frame_decoder = FrameDecoder::new();
+    let mut offset = 0;
+    while offset < frame_size {
+        offset += frame_decoder.push(&frame_buffer[offset..frame_size]);
+        if let Some(result) = frame_decoder.next_message::<Response>() {
+            // Handle Ok(message) or Err(error)
+        }
+    }
+    
+    // Before anything else, agree on a protocol version and the optional
+    // features with a handshake; the peer replies with its own hello
+    let local_hello = Hello::new();
+    let (hello_size, hello_buffer) = local_hello.to_message(0)?.serialize()?;
+    // Send hello_buffer to the peer and receive its reply ...
+    let remote_hello = Message::<Hello>::try_from(&hello_buffer[..hello_size])?.payload;
+    let session = handshake::negotiate(&local_hello, &remote_hello)?;
+    let battery_message = session.message(MessageKind::Command, 124, Command::GetBatteryStatus)?;
+    let use_framing = session.capabilities.contains(Capabilities::COBS_FRAMING);
+    
+    // Pair once to establish a bond key; secret keys and nonces must come
+    // from a secure random source in a real application
+    let mut app = Pairing::new(Role::Initiator, [0x11; 32], [0x22; 16]);
+    let mut device = Pairing::new(Role::Responder, [0x33; 32], [0x44; 16]);
+    let request = app.start()?;
+    if let Some(response) = device.handle(&request)? {
+        if let Some(app_nonce) = app.handle(&response)? {
+            if let Some(device_nonce) = device.handle(&app_nonce)? {
+                app.handle(&device_nonce)?;
+            }
+        }
+    }
+    // Both sides show the code; the user confirms that they match
+    let code = app.verification_code();
+    let bond = app.finish()?;
+    
+    // Every connection starts a session with fresh nonces exchanged in
+    // SessionStart messages, then protected messages are sealed
+    let app_start = SecurityMessage::SessionStart { nonce: [0x55; 16] };
+    let mut secure_session = Session::new(Role::Initiator, &bond, [0x55; 16], [0x66; 16]);
+    let mut sealed_buffer = [0u8; 256];
+    let sealed_size = secure_session.seal_message(&command_message, &mut sealed_buffer)?;
+    // The receiver opens sealed messages in place before decoding them:
+    // let plain_size = secure_session.open(&mut frame)?;
+    
+    // Over lossy links, send through a reliable link, which keeps each
+    // message until the peer acknowledges it
+    let mut link = ReliableLink::default();
+    link.set_version(session.version)?;
+    let mut now_ms = 0;
+    let (sequence, bytes) = link.send(MessageKind::Command, Command::GetBatteryStatus, now_ms)?;
+    // Send bytes to the peer ...
+    
+    // Protected messages are sealed before the link keeps them
+    let (sequence, bytes) = link.send_sealed(MessageKind::Command, Command::FactoryReset, &mut secure_session, now_ms)?;
+    
+    // Pass every received frame to the link; it completes acknowledged
+    // messages and tells new messages from retransmitted duplicates. Within
+    // a session, receive_sealed opens frames in place, so copy them first
+    let mut decoder = StreamDecoder::new();
+    let mut frame_buffer = [0u8; MAX_MESSAGE_SIZE];
+    while let Some(Ok(received)) = decoder.next_frame() {
+        let frame = &mut frame_buffer[..received.len()];
+        frame.copy_from_slice(received);
+        match link.receive_sealed(frame, &mut secure_session)? {
+            Received::Message { frame, ack } => {
+                // Decode the opened frame with Message::try_from and send ack.as_bytes()
+            }
+            Received::Duplicate { ack } => {
+                // Already handled; only send ack.as_bytes()
+            }
+            Received::Ack { sequence, .. } => {
+                // The peer received the message with this sequence number
+            }
+        }
+    }
+    
+    // Check for missing acknowledgments regularly
+    now_ms += 500;
+    while let Some(event) = link.poll(now_ms) {
+        match event {
+            LinkEvent::Retransmit(bytes) => {
+                // Send bytes to the peer again
+            }
+            LinkEvent::Failed { sequence } => {
+                // The message was dropped; resend it later if still needed
+            }
+        }
+    }
+    
+    // Payloads larger than a message, e.g. a dive log serialized into a
+    // buffer of its own, are sent as a series of fragments
+    let dive_log = [0u8; 1000];
+    let mut reassembly_buffer = [0u8; 2048];
+    let mut reassembler = Reassembler::new(&mut reassembly_buffer, 5000);
+    for fragment in fragment::fragments(1, &dive_log)? {
+        let (size, buffer) = Message::new(MessageKind::Fragment, 0, fragment)?.serialize()?;
+        // Send through the reliable link in a real application ...
+        
+        // The receiving side decodes each fragment message and hands the
+        // fragment to the reassembler, which returns the complete payload
+        // once every fragment has arrived
+        let message = Message::<Fragment>::try_from(&buffer[..size])?;
+        if let Some(payload) = reassembler.push(&message.payload, now_ms)? {
+            // Deserialize the payload
+        }
+    }
+    
+    // A transfer that stops receiving fragments is abandoned
+    if let Err(ProtocolError::ReassemblyTimeout) = reassembler.poll(now_ms + 5000) {
+        // Request the transfer again
+    }
+    
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Device-side handler used by `dispatcher_example`
+///
+/// Only the implemented commands are supported; all others are answered with
+/// `CommandError::InvalidCommand`.
+struct ExampleDevice {
+    /// Battery level in percent
+    battery_level: u8,
+}
+
+impl CommandHandler for ExampleDevice {
+    fn get_battery_status(&mut self) -> HandlerResult {
+        Ok(Some(ResponsePayload::BatteryStatus {
+            level: self.battery_level,
+            voltage: 3720,
+            estimated_time_remaining: 120,
+        }))
+    }
+
+    fn factory_reset(&mut self) -> HandlerResult {
+        // Erase settings and logs ...
+        Ok(None)
+    }
+}
+
+/// Example of answering commands on the device
+pub fn dispatcher_example() -> Result<(), ProtocolError> {
+    let mut dispatcher = Dispatcher::new(ExampleDevice { battery_level: 85 });
+    
+    // A received command message, e.g. from StreamDecoder::next_frame
+    let (size, buffer) = Message::new(MessageKind::Command, 7, Command::GetBatteryStatus)?.serialize()?;
+    
+    // Commands that arrived in plain text run at the read-only level; use
+    // Session::privilege for commands that arrived sealed
+    let response = dispatcher.dispatch_frame(&buffer[..size], Privilege::ReadOnly, 1234567890)?;
+    // response has sequence number 7 and command_id 7; serialize and send it
+    
+    // A factory reset from an unauthenticated sender is answered with an
+    // error response (0x08, insufficient permissions) without reaching the handler
+    let reset = Message::new(MessageKind::Command, 8, Command::FactoryReset)?;
+    let denied = dispatcher.dispatch(&reset, Privilege::ReadOnly, 1234567890)?;
+    
+    Ok(())
+}
+
+/// Example of sending commands from the host
+///
+/// `transport` is any link to the device, e.g. a serial port or BLE characteristic.
+pub fn client_example<T: Transport>(transport: T) -> Result<(), ClientError<T::Error>> {
+    let mut client = Client::new(transport);
+    
+    // Each call sends the command, waits for the matching response and
+    // returns the typed result
+    let battery = client.get_battery_status()?;
+    let low_battery = battery.level < 20;
+    
+    // Errors reported by the device arrive as ClientError::Device
+    match client.factory_reset() {
+        Ok(()) => {}
+        Err(ClientError::Device(ErrorCode::InsufficientPrivilege)) => {
+            // A session with a sufficient privilege is required
+        }
+        Err(ClientError::Device(ErrorCode::Vendor(code))) => {
+            // Look up vendor-specific codes, code.get(), in the device documentation
+        }
+        Err(error) => return Err(error),
+    }
+    
+    Ok(())
+}
+
+/// Example of a complete host-to-device conversation without hardware
+///
+/// The device side runs on a second thread and answers a single command.
+#[cfg(feature = "std")]
+pub fn loopback_example() -> Result<(), ClientError<core::convert::Infallible>> {
+    let (host, mut device) = crate::transport::loopback(std::time::Duration::from_millis(500));
+    
+    let device_thread = std::thread::spawn(move || {
+        let mut dispatcher = Dispatcher::new(ExampleDevice { battery_level: 85 });
+        let mut decoder = StreamDecoder::new();
+        let mut chunk = [0u8; 64];
+        loop {
+            let Ok(count) = device.read(&mut chunk);
+            if count == 0 {
+                return;
+            }
+            decoder.push(&chunk[..count]);
+            if let Some(Ok(frame)) = decoder.next_frame() {
+                let Ok(response) = dispatcher.dispatch_frame(frame, Privilege::ReadOnly, 1234567890) else {
+                    return;
+                };
+                if let Ok((size, buffer)) = response.serialize() {
+                    let Ok(()) = device.write_all(&buffer[..size]);
+                }
+                return;
+            }
+        }
+    });
+    
+    let mut client = Client::new(host);
+    let battery = client.get_battery_status()?;
+    // battery.level is 85
+    
+    let _ = device_thread.join();
+    Ok(())
+}
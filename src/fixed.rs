@@ -0,0 +1,412 @@
+//! Fixed-point arithmetic
+//!
+//! This module provides a signed Q32.32 fixed-point number for targets without
+//! a floating point unit. It implements the arithmetic and the few
+//! transcendental functions (exponential, logarithm, power, square root) the
+//! dive calculations need, using integer operations only, so results are
+//! identical on every target.
+//!
+//! The representable range is about ±2.1e9 with a resolution of 2^-32. The
+//! fine resolution matters for the slow tissue compartments, which change by
+//! less than 0.00002 bar per second. Arithmetic saturates at the ends of the
+//! range instead of wrapping, and division by zero saturates towards the sign
+//! of the dividend.
+//!
+//! # Accuracy
+//!
+//! Basic arithmetic is exact to within half a unit in the last place.
+//! `exp`, `ln`, `powf` and `sqrt` have a relative error below 0.0001% over the
+//! ranges used by the dive calculations. Running the dive calculations on
+//! `Fixed` instead of `f32` (the `fixed-point` feature) keeps tissue pressures
+//! within 0.001 bar and CNS and OTU within 0.1 of the floating point reference.
+//! Values that are rounded to whole units (NDL, stop and no-fly minutes,
+//! saturation percentages) may differ by one where the reference lies close to
+//! a rounding boundary.
+
+use core::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use serde::{Serialize, Deserialize};
+
+/// Number of fractional bits
+const FRACTION_BITS: u32 = 32;
+
+/// Raw value of 1.0
+const ONE_RAW: i64 = 1 << FRACTION_BITS;
+
+/// Raw value of ln(2)
+const LN_2_RAW: i64 = 2_977_044_472;
+
+/// Signed Q32.32 fixed-point number
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    /// 0.0
+    pub const ZERO: Fixed = Fixed(0);
+    /// 1.0
+    pub const ONE: Fixed = Fixed(ONE_RAW);
+    /// Natural logarithm of 2
+    pub const LN_2: Fixed = Fixed(LN_2_RAW);
+    /// Largest representable value
+    pub const MAX: Fixed = Fixed(i64::MAX);
+    /// Smallest representable value
+    pub const MIN: Fixed = Fixed(i64::MIN);
+
+    /// Creates a number from its raw Q32.32 representation
+    pub const fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+
+    /// Returns the raw Q32.32 representation
+    pub const fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    /// Converts a floating point value, rounding to the nearest representable value
+    ///
+    /// Intended for constants; evaluating it at run time needs floating point
+    /// support.
+    pub const fn from_f32(value: f32) -> Self {
+        let scaled = value as f64 * ONE_RAW as f64;
+        let rounded = if scaled >= 0.0 { scaled + 0.5 } else { scaled - 0.5 };
+        Fixed(rounded as i64)
+    }
+
+    /// Converts an integer
+    pub const fn from_int(value: i32) -> Self {
+        Fixed((value as i64) << FRACTION_BITS)
+    }
+
+    /// Returns `numerator / denominator`, rounded to the nearest representable value
+    pub const fn from_ratio(numerator: i32, denominator: i32) -> Self {
+        Fixed::saturate(div_scaled(numerator as i64, denominator as i64))
+    }
+
+    /// Converts to a floating point value
+    pub fn to_f32(self) -> f32 {
+        (self.0 as f64 / ONE_RAW as f64) as f32
+    }
+
+    /// Rounds to the nearest integer, with halves rounded away from zero
+    ///
+    /// The result saturates at the ends of the `i32` range.
+    pub const fn round_to_int(self) -> i32 {
+        to_int(div_round(self.0 as i128, ONE_RAW as i128))
+    }
+
+    /// Returns the smallest integer greater than or equal to the value
+    ///
+    /// The result saturates at the ends of the `i32` range.
+    pub const fn ceil_to_int(self) -> i32 {
+        to_int((self.0 as i128 + ONE_RAW as i128 - 1) >> FRACTION_BITS)
+    }
+
+    /// Returns the largest integer less than or equal to the value
+    ///
+    /// The result saturates at the ends of the `i32` range.
+    pub const fn floor_to_int(self) -> i32 {
+        to_int((self.0 >> FRACTION_BITS) as i128)
+    }
+
+    /// Returns the absolute value
+    pub const fn abs(self) -> Self {
+        Fixed(self.0.saturating_abs())
+    }
+
+    /// Returns the larger of two values
+    pub fn max(self, other: Self) -> Self {
+        Ord::max(self, other)
+    }
+
+    /// Returns the smaller of two values
+    pub fn min(self, other: Self) -> Self {
+        Ord::min(self, other)
+    }
+
+    /// Restricts the value to the range `min..=max`
+    pub fn clamp(self, min: Self, max: Self) -> Self {
+        Ord::clamp(self, min, max)
+    }
+
+    /// Returns e raised to the power of the value
+    pub fn exp(self) -> Self {
+        // e^-23 is below the resolution, e^21.5 close to the end of the range
+        if self.0 < -23 * ONE_RAW {
+            return Fixed::ZERO;
+        }
+        if self.0 > 21 * ONE_RAW + ONE_RAW / 2 {
+            return Fixed::MAX;
+        }
+        Fixed::saturate(exp_raw(self.0))
+    }
+
+    /// Returns the fraction remaining after exponential decay for the value
+    /// with the given half-time, i.e. `2^(-self / half_time)`
+    ///
+    /// Returns zero for half-times less than or equal to zero.
+    pub fn decay(self, half_time: Self) -> Self {
+        if half_time.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        (-decay_exponent(self, half_time)).exp()
+    }
+
+    /// Returns the integral of the decay curve from zero to the value, i.e.
+    /// `half_time / ln(2) * (1 - 2^(-self / half_time))`
+    ///
+    /// Returns zero for half-times less than or equal to zero.
+    pub fn decay_integral(self, half_time: Self) -> Self {
+        if half_time.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        let decayed = ONE_RAW as i128 - self.decay(half_time).0 as i128;
+        Fixed::saturate(half_time.0 as i128 * decayed / LN_2_RAW as i128)
+    }
+
+    /// Returns the natural logarithm of the value
+    ///
+    /// Returns `Fixed::MIN` for values less than or equal to zero.
+    pub fn ln(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::MIN;
+        }
+
+        // Normalize to m * 2^k with m in [1, 2)
+        let one = ONE_RAW as i128;
+        let mut mantissa = self.0 as i128;
+        let mut k: i128 = 0;
+        while mantissa >= 2 * one {
+            mantissa >>= 1;
+            k += 1;
+        }
+        while mantissa < one {
+            mantissa <<= 1;
+            k -= 1;
+        }
+
+        // ln(m) = 2 * atanh(s) with s = (m - 1) / (m + 1) <= 1/3
+        let s = ((mantissa - one) << FRACTION_BITS) / (mantissa + one);
+        let s_squared = (s * s) >> FRACTION_BITS;
+        let mut power = s;
+        let mut sum = 0i128;
+        for n in (1..=19).step_by(2) {
+            sum += power / n;
+            power = (power * s_squared) >> FRACTION_BITS;
+        }
+
+        Fixed::saturate(2 * sum + k * LN_2_RAW as i128)
+    }
+
+    /// Raises the value to a fractional power
+    ///
+    /// Returns zero for values less than or equal to zero.
+    pub fn powf(self, exponent: Self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        (self.ln() * exponent).exp()
+    }
+
+    /// Returns the square root of the value
+    ///
+    /// Returns zero for negative values.
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        // sqrt(x / 2^32) * 2^32 = sqrt(x * 2^32)
+        let root = ((self.0 as u128) << FRACTION_BITS).isqrt();
+        Fixed::saturate(root as i128)
+    }
+
+    /// Clamps a raw 128-bit value into the representable range
+    const fn saturate(raw: i128) -> Self {
+        if raw > i64::MAX as i128 {
+            Fixed::MAX
+        } else if raw < i64::MIN as i128 {
+            Fixed::MIN
+        } else {
+            Fixed(raw as i64)
+        }
+    }
+}
+
+/// Returns e^x for a raw argument as a raw value, which may exceed the range
+///
+/// The argument is reduced to `k * ln(2) + r` with `|r| <= ln(2) / 2` and
+/// `e^r` is evaluated with a Taylor series.
+fn exp_raw(x: i64) -> i128 {
+    let k = div_round(x as i128, LN_2_RAW as i128);
+    let r = x as i128 - k * LN_2_RAW as i128;
+
+    let mut term = ONE_RAW as i128;
+    let mut sum = term;
+    for n in 1..=12 {
+        term = ((term * r) >> FRACTION_BITS) / n;
+        sum += term;
+    }
+
+    if k >= 0 { sum << k } else { div_round(sum, 1i128 << -k) }
+}
+
+/// Returns `ln(2) * time / half_time`
+fn decay_exponent(time: Fixed, half_time: Fixed) -> Fixed {
+    Fixed::saturate(time.0 as i128 * LN_2_RAW as i128 / half_time.0 as i128)
+}
+
+/// Divides two raw values, keeping the result in Q32.32
+const fn div_scaled(numerator: i64, denominator: i64) -> i128 {
+    div_round((numerator as i128) << FRACTION_BITS, denominator as i128)
+}
+
+/// Divides with rounding to the nearest integer, saturating on division by zero
+///
+/// Halves are rounded away from zero.
+const fn div_round(numerator: i128, denominator: i128) -> i128 {
+    if denominator == 0 {
+        return if numerator >= 0 { i128::MAX } else { i128::MIN };
+    }
+    let divisor = denominator.unsigned_abs();
+    let magnitude = numerator.unsigned_abs().saturating_add(divisor / 2) / divisor;
+    let magnitude = if magnitude > i128::MAX as u128 { i128::MAX } else { magnitude as i128 };
+    if (numerator < 0) != (denominator < 0) { -magnitude } else { magnitude }
+}
+
+/// Clamps an integer into the `i32` range
+const fn to_int(value: i128) -> i32 {
+    if value > i32::MAX as i128 {
+        i32::MAX
+    } else if value < i32::MIN as i128 {
+        i32::MIN
+    } else {
+        value as i32
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed::saturate(div_round(self.0 as i128 * other.0 as i128, ONE_RAW as i128))
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, other: Fixed) -> Fixed {
+        Fixed::saturate(div_scaled(self.0, other.0))
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, other: Fixed) {
+        *self = *self * other;
+    }
+}
+
+impl DivAssign for Fixed {
+    fn div_assign(&mut self, other: Fixed) {
+        *self = *self / other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dive_calc::{DiveProfile, GasType};
+    use crate::real;
+
+    /// Runs the reference dive on trimix 21/35: descent to 45 m at 20 m/min,
+    /// 20 minutes at the bottom, ascent at 9 m/min and stops from 21 m to 3 m
+    fn reference_dive() -> DiveProfile {
+        let mut profile = DiveProfile::new(GasType::Trimix { oxygen_percent: 21, helium_percent: 35 });
+        profile.record_sample(4500, 135);
+        profile.record_sample(4500, 20 * 60);
+        profile.record_sample(2100, 160);
+        for (depth_cm, minutes) in [(2100, 1), (1800, 1), (1500, 2), (1200, 3), (900, 4), (600, 8), (300, 15)] {
+            profile.record_sample(depth_cm, 20);
+            profile.record_sample(depth_cm, minutes * 60);
+        }
+        profile.record_sample(0, 20);
+        profile
+    }
+
+    /// Tissue pressures and oxygen exposure after `reference_dive`, computed on `f32`
+    const REFERENCE_NITROGEN_BAR: [f32; 16] = [
+        0.57905823, 0.66334397, 0.7999206, 0.918241, 0.9889043, 1.0077263, 0.99255437, 0.95889544,
+        0.9196267, 0.88786423, 0.86374164, 0.8429251, 0.825358, 0.81060624, 0.79856384, 0.7888303,
+    ];
+    const REFERENCE_HELIUM_BAR: [f32; 16] = [
+        0.43180484, 0.4376088, 0.4560288, 0.5011056, 0.5747248, 0.6428808, 0.6754104, 0.65489745,
+        0.58899516, 0.5129694, 0.44358256, 0.37620774, 0.31412446, 0.2584178, 0.2105735, 0.17035663,
+    ];
+    const REFERENCE_CNS_PERCENT: f32 = 10.832968;
+    const REFERENCE_OTU: f32 = 29.448324;
+
+    #[test]
+    fn dive_calculations_match_the_f32_reference() {
+        let profile = reference_dive();
+        let pressures = profile.tissues.nitrogen_bar.iter().chain(&profile.tissues.helium_bar);
+        let references = REFERENCE_NITROGEN_BAR.iter().chain(&REFERENCE_HELIUM_BAR);
+        for (pressure, reference) in pressures.zip(references) {
+            assert!((real::to_f32(*pressure) - reference).abs() <= 0.001, "{:?} vs {}", pressure, reference);
+        }
+        assert!((real::to_f32(profile.oxygen.cns_percent) - REFERENCE_CNS_PERCENT).abs() <= 0.1);
+        assert!((real::to_f32(profile.oxygen.otu) - REFERENCE_OTU).abs() <= 0.1);
+    }
+
+    #[test]
+    fn transcendental_functions_are_accurate() {
+        let relative_error = |actual: Fixed, expected: f64| ((actual.to_f32() as f64 - expected) / expected).abs();
+        for step in 0..=100 {
+            let x = -5.0 + step as f64 * 0.08;
+            assert!(relative_error(Fixed::from_f32(x as f32).exp(), libm::exp(x as f32 as f64)) < 1e-6, "exp({})", x);
+        }
+        for step in 1..=100 {
+            let x = step as f64 * 0.1;
+            let fixed = Fixed::from_f32(x as f32);
+            let x = x as f32 as f64;
+            assert!(relative_error(fixed.sqrt(), libm::sqrt(x)) < 1e-6, "sqrt({})", x);
+            if x != 1.0 {
+                assert!((fixed.ln().to_f32() as f64 - libm::log(x)).abs() < 1e-6, "ln({})", x);
+            }
+            assert!(relative_error(fixed.powf(Fixed::from_f32(0.75)), libm::pow(x, 0.75)) < 1e-6, "powf({})", x);
+        }
+    }
+}
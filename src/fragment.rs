@@ -0,0 +1,338 @@
+//! Fragmentation of payloads larger than a single message
+//!
+//! This module splits a large serialized payload, such as a dive log or a
+//! settings blob, into numbered fragments that each fit into a message of
+//! `MessageKind::Fragment`, and reassembles them on the receiving side into a
+//! caller-provided buffer. Reassembly needs no memory beyond that buffer and a
+//! fixed-size record of the received fragments.
+
+use serde::{Serialize, Deserialize};
+use crate::protocol::ProtocolError;
+
+/// Number of payload bytes carried by every fragment except the last one
+///
+/// Chosen so that a fragment message with the largest possible header fields
+/// still fits into `MAX_MESSAGE_SIZE`.
+pub const FRAGMENT_DATA_SIZE: usize = 224;
+
+/// Maximum number of fragments in one transfer
+pub const MAX_FRAGMENTS: usize = 512;
+
+/// Maximum size of a fragmented payload in bytes
+pub const MAX_TRANSFER_SIZE: usize = FRAGMENT_DATA_SIZE * MAX_FRAGMENTS;
+
+/// One fragment of a large payload, sent as the payload of a
+/// `MessageKind::Fragment` message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Fragment<'a> {
+    /// Identifies the transfer the fragment belongs to
+    pub transfer_id: u16,
+    /// Position of the fragment in the transfer, starting at 0
+    pub index: u16,
+    /// Number of fragments in the transfer
+    pub count: u16,
+    /// Size of the complete payload in bytes
+    pub total_length: u32,
+    /// Fragment data; `FRAGMENT_DATA_SIZE` bytes except in the last fragment
+    pub data: &'a [u8],
+}
+
+/// Splits `data` into fragments
+///
+/// # Arguments
+///
+/// * `transfer_id` - Identifier of the transfer, e.g. a counter per peer
+/// * `data` - Serialized payload to send
+///
+/// # Returns
+///
+/// An iterator over the fragments in order, or `MessageTooLarge` if `data`
+/// is larger than `MAX_TRANSFER_SIZE`
+pub fn fragments(transfer_id: u16, data: &[u8]) -> Result<Fragments<'_>, ProtocolError> {
+    if data.len() > MAX_TRANSFER_SIZE {
+        return Err(ProtocolError::MessageTooLarge);
+    }
+    // An empty payload is still sent as one empty fragment
+    let count = data.len().div_ceil(FRAGMENT_DATA_SIZE).max(1) as u16;
+    Ok(Fragments { transfer_id, data, count, next_index: 0 })
+}
+
+/// Iterator over the fragments of a payload, created by [`fragments`]
+#[derive(Debug, Clone)]
+pub struct Fragments<'a> {
+    /// Identifier of the transfer
+    transfer_id: u16,
+    /// Complete payload
+    data: &'a [u8],
+    /// Number of fragments
+    count: u16,
+    /// Index of the next fragment to return
+    next_index: u16,
+}
+
+impl<'a> Fragments<'a> {
+    /// Returns the number of fragments in the transfer
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    /// Returns the fragment with the given index, e.g. to resend it
+    pub fn get(&self, index: u16) -> Option<Fragment<'a>> {
+        if index >= self.count {
+            return None;
+        }
+        let start = index as usize * FRAGMENT_DATA_SIZE;
+        let end = (start + FRAGMENT_DATA_SIZE).min(self.data.len());
+        Some(Fragment {
+            transfer_id: self.transfer_id,
+            index,
+            count: self.count,
+            total_length: self.data.len() as u32,
+            data: &self.data[start..end],
+        })
+    }
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Fragment<'a>> {
+        let fragment = self.get(self.next_index)?;
+        self.next_index += 1;
+        Some(fragment)
+    }
+}
+
+/// Transfer that is being reassembled
+#[derive(Debug, Clone, Copy)]
+struct Transfer {
+    /// Identifier of the transfer
+    transfer_id: u16,
+    /// Number of fragments
+    count: u16,
+    /// Size of the complete payload in bytes
+    total_length: usize,
+    /// Number of different fragments received
+    received_count: u16,
+    /// Bit `n % 32` of word `n / 32` is set if fragment `n` was received
+    received: [u32; MAX_FRAGMENTS / 32],
+    /// Time the last fragment was received in milliseconds
+    last_fragment_ms: u32,
+}
+
+/// Reassembles fragments into a caller-provided buffer
+///
+/// Fragments may arrive in any order and more than once. A transfer that
+/// receives no fragment for `timeout_ms` is abandoned and reported as
+/// `ProtocolError::ReassemblyTimeout`.
+#[derive(Debug)]
+pub struct Reassembler<'b> {
+    /// Buffer for the complete payload
+    buffer: &'b mut [u8],
+    /// Time without fragments after which a transfer is abandoned in milliseconds
+    timeout_ms: u32,
+    /// Transfer in progress
+    transfer: Option<Transfer>,
+}
+
+impl<'b> Reassembler<'b> {
+    /// Creates a reassembler that writes payloads into `buffer`
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Buffer for the complete payload; limits the transfer size
+    /// * `timeout_ms` - Time without fragments after which a transfer is abandoned
+    pub fn new(buffer: &'b mut [u8], timeout_ms: u32) -> Self {
+        Reassembler { buffer, timeout_ms, transfer: None }
+    }
+
+    /// Adds a received fragment
+    ///
+    /// A fragment of a different transfer than the one in progress abandons
+    /// the current transfer and starts the new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `fragment` - The received fragment
+    /// * `now_ms` - Current time in milliseconds
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(payload))` once the last missing fragment has arrived
+    /// * `Ok(None)` while fragments are missing
+    /// * `Err(ReassemblyTimeout)` if the fragment belongs to a transfer that
+    ///   has already timed out
+    /// * `Err(MessageTooLarge)` if the payload does not fit into the buffer
+    /// * `Err(InvalidFormat)` if the fragment is inconsistent with its transfer
+    pub fn push(&mut self, fragment: &Fragment, now_ms: u32) -> Result<Option<&[u8]>, ProtocolError> {
+        let timeout_ms = self.timeout_ms;
+        if let Some(transfer) = &self.transfer {
+            if transfer.transfer_id == fragment.transfer_id
+                && now_ms.wrapping_sub(transfer.last_fragment_ms) >= timeout_ms
+            {
+                self.transfer = None;
+                return Err(ProtocolError::ReassemblyTimeout);
+            }
+        }
+
+        let total_length = fragment.total_length as usize;
+        if total_length > self.buffer.len() || fragment.count as usize > MAX_FRAGMENTS {
+            return Err(ProtocolError::MessageTooLarge);
+        }
+        if fragment.count as usize != total_length.div_ceil(FRAGMENT_DATA_SIZE).max(1) {
+            return Err(ProtocolError::InvalidFormat);
+        }
+        let start = fragment.index as usize * FRAGMENT_DATA_SIZE;
+        if fragment.index >= fragment.count
+            || fragment.data.len() != (total_length - start).min(FRAGMENT_DATA_SIZE)
+        {
+            return Err(ProtocolError::InvalidFormat);
+        }
+
+        let transfer = match &mut self.transfer {
+            Some(transfer) if transfer.transfer_id == fragment.transfer_id => {
+                if transfer.count != fragment.count || transfer.total_length != total_length {
+                    return Err(ProtocolError::InvalidFormat);
+                }
+                transfer
+            }
+            transfer => transfer.insert(Transfer {
+                transfer_id: fragment.transfer_id,
+                count: fragment.count,
+                total_length,
+                received_count: 0,
+                received: [0; MAX_FRAGMENTS / 32],
+                last_fragment_ms: now_ms,
+            }),
+        };
+
+        transfer.last_fragment_ms = now_ms;
+        let word = fragment.index as usize / 32;
+        let bit = 1u32 << (fragment.index % 32);
+        if transfer.received[word] & bit == 0 {
+            transfer.received[word] |= bit;
+            transfer.received_count += 1;
+            self.buffer[start..start + fragment.data.len()].copy_from_slice(fragment.data);
+        }
+
+        if transfer.received_count < transfer.count {
+            return Ok(None);
+        }
+        self.transfer = None;
+        Ok(Some(&self.buffer[..total_length]))
+    }
+
+    /// Checks the transfer in progress for a timeout
+    ///
+    /// # Arguments
+    ///
+    /// * `now_ms` - Current time in milliseconds
+    ///
+    /// # Returns
+    ///
+    /// `Err(ReassemblyTimeout)` once if the transfer in progress received no
+    /// fragment for `timeout_ms`; the transfer is then abandoned
+    pub fn poll(&mut self, now_ms: u32) -> Result<(), ProtocolError> {
+        if let Some(transfer) = &self.transfer {
+            if now_ms.wrapping_sub(transfer.last_fragment_ms) >= self.timeout_ms {
+                self.transfer = None;
+                return Err(ProtocolError::ReassemblyTimeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the identifier and progress (received and total fragments) of
+    /// the transfer in progress
+    pub fn progress(&self) -> Option<(u16, u16, u16)> {
+        self.transfer
+            .as_ref()
+            .map(|transfer| (transfer.transfer_id, transfer.received_count, transfer.count))
+    }
+
+    /// Abandons the transfer in progress
+    pub fn reset(&mut self) {
+        self.transfer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Payload spanning three fragments, the last one partial
+    fn payload() -> [u8; 2 * FRAGMENT_DATA_SIZE + 10] {
+        core::array::from_fn(|i| i as u8)
+    }
+
+    #[test]
+    fn fragments_reassemble_in_any_order() {
+        let data = payload();
+        let parts = fragments(1, &data).unwrap();
+        assert_eq!(Fragments::count(&parts), 3);
+        let mut buffer = [0u8; 3 * FRAGMENT_DATA_SIZE];
+        let mut reassembler = Reassembler::new(&mut buffer, 1000);
+
+        assert_eq!(reassembler.push(&parts.get(2).unwrap(), 0), Ok(None));
+        assert_eq!(reassembler.push(&parts.get(0).unwrap(), 10), Ok(None));
+        assert_eq!(reassembler.progress(), Some((1, 2, 3)));
+        assert_eq!(reassembler.push(&parts.get(1).unwrap(), 20), Ok(Some(&data[..])));
+        assert_eq!(reassembler.progress(), None);
+    }
+
+    #[test]
+    fn duplicate_fragments_are_ignored() {
+        let data = payload();
+        let parts = fragments(1, &data).unwrap();
+        let mut buffer = [0u8; 3 * FRAGMENT_DATA_SIZE];
+        let mut reassembler = Reassembler::new(&mut buffer, 1000);
+
+        assert_eq!(reassembler.push(&parts.get(0).unwrap(), 0), Ok(None));
+        assert_eq!(reassembler.push(&parts.get(0).unwrap(), 0), Ok(None));
+        assert_eq!(reassembler.push(&parts.get(1).unwrap(), 0), Ok(None));
+        assert_eq!(reassembler.progress(), Some((1, 2, 3)));
+        assert_eq!(reassembler.push(&parts.get(2).unwrap(), 0), Ok(Some(&data[..])));
+    }
+
+    #[test]
+    fn transfers_without_fragments_time_out() {
+        let data = payload();
+        let parts = fragments(1, &data).unwrap();
+        let mut buffer = [0u8; 3 * FRAGMENT_DATA_SIZE];
+        let mut reassembler = Reassembler::new(&mut buffer, 1000);
+
+        // Every fragment restarts the timeout
+        assert_eq!(reassembler.push(&parts.get(0).unwrap(), 0), Ok(None));
+        assert_eq!(reassembler.push(&parts.get(1).unwrap(), 999), Ok(None));
+        assert_eq!(reassembler.poll(1998), Ok(()));
+        assert_eq!(reassembler.poll(1999), Err(ProtocolError::ReassemblyTimeout));
+        assert_eq!(reassembler.progress(), None);
+        assert_eq!(reassembler.poll(5000), Ok(()));
+
+        // A late fragment of an abandoned transfer starts it again
+        assert_eq!(reassembler.push(&parts.get(2).unwrap(), 5000), Ok(None));
+        assert_eq!(
+            reassembler.push(&parts.get(0).unwrap(), 6000),
+            Err(ProtocolError::ReassemblyTimeout)
+        );
+    }
+
+    #[test]
+    fn inconsistent_fragments_are_rejected() {
+        let data = payload();
+        let parts = fragments(1, &data).unwrap();
+        let mut buffer = [0u8; 3 * FRAGMENT_DATA_SIZE];
+        let mut reassembler = Reassembler::new(&mut buffer, 1000);
+
+        let mut fragment = parts.get(0).unwrap();
+        fragment.index = 3;
+        assert_eq!(reassembler.push(&fragment, 0), Err(ProtocolError::InvalidFormat));
+        fragment.total_length = 200;
+        fragment.index = 0;
+        assert_eq!(reassembler.push(&fragment, 0), Err(ProtocolError::InvalidFormat));
+
+        let mut small = [0u8; FRAGMENT_DATA_SIZE];
+        let mut reassembler = Reassembler::new(&mut small, 1000);
+        assert_eq!(reassembler.push(&parts.get(0).unwrap(), 0), Err(ProtocolError::MessageTooLarge));
+    }
+}
@@ -0,0 +1,281 @@
+//! COBS framing for serial links
+//!
+//! This module wraps serialized messages in Consistent Overhead Byte Stuffing
+//! (COBS) frames terminated by a zero byte. COBS removes every zero from the
+//! encoded message, so the delimiter marks frame boundaries unambiguously: a
+//! dropped or corrupted byte only affects the frame it belongs to, and the
+//! receiver is back in sync at the next delimiter.
+//!
+//! Framing is optional; links that already provide message boundaries can
+//! keep sending plain `Message::serialize` output.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use core::convert::TryFrom;
+use crate::protocol::{validate_frame, Message, ProtocolError, MAX_MESSAGE_SIZE};
+
+/// Byte that terminates every frame
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+/// Maximum size of an encoded frame in bytes, including the delimiter
+///
+/// COBS adds one byte per started block of 254 bytes.
+pub const MAX_FRAME_SIZE: usize = MAX_MESSAGE_SIZE + MAX_MESSAGE_SIZE / 254 + 1 + 1;
+
+/// Encodes a serialized message into a COBS frame
+///
+/// # Arguments
+///
+/// * `message` - Serialized message, e.g. from `Message::serialize`
+/// * `frame` - Output buffer for the frame
+///
+/// # Returns
+///
+/// The frame length including the delimiter, or `MessageTooLarge` if `frame`
+/// is too small
+pub fn encode_frame(message: &[u8], frame: &mut [u8]) -> Result<usize, ProtocolError> {
+    let length = cobs::try_encode(message, frame).map_err(|_| ProtocolError::MessageTooLarge)?;
+    if length >= frame.len() {
+        return Err(ProtocolError::MessageTooLarge);
+    }
+    frame[length] = FRAME_DELIMITER;
+    Ok(length + 1)
+}
+
+impl<T: Serialize> Message<T> {
+    /// Serializes the message into a COBS frame
+    pub fn serialize_framed(&self) -> Result<(usize, [u8; MAX_FRAME_SIZE]), ProtocolError> {
+        let (length, buffer) = self.serialize()?;
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let frame_length = encode_frame(&buffer[..length], &mut frame)?;
+        Ok((frame_length, frame))
+    }
+}
+
+/// Incremental decoder for COBS framed messages
+///
+/// Works like `StreamDecoder`, but relies on the frame delimiter instead of
+/// searching for `MESSAGE_MAGIC`: bytes are collected until a delimiter
+/// arrives, and the completed frame is then decoded by `next_message`.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    /// Encoded bytes of the current frame
+    buffer: [u8; MAX_FRAME_SIZE],
+    /// Number of valid bytes in `buffer`
+    length: usize,
+    /// The delimiter of the current frame has been received
+    complete: bool,
+    /// The current frame did not fit in `buffer` and is being skipped
+    overflow: bool,
+    /// `buffer` holds the decoded frame returned by `next_frame`
+    returned: bool,
+}
+
+impl FrameDecoder {
+    /// Creates an empty decoder
+    pub const fn new() -> Self {
+        FrameDecoder {
+            buffer: [0u8; MAX_FRAME_SIZE],
+            length: 0,
+            complete: false,
+            overflow: false,
+            returned: false,
+        }
+    }
+
+    /// Appends received bytes to the decoder
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Bytes as received from the transport
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes taken from `data`. Reading stops after a frame
+    /// delimiter; call `next_message` to decode the frame and push the
+    /// remaining bytes again.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        if self.returned {
+            self.reset();
+        }
+        if self.complete {
+            return 0;
+        }
+        for (index, &byte) in data.iter().enumerate() {
+            if byte == FRAME_DELIMITER {
+                self.complete = true;
+                return index + 1;
+            }
+            if self.length == self.buffer.len() {
+                self.overflow = true;
+            }
+            if !self.overflow {
+                self.buffer[self.length] = byte;
+                self.length += 1;
+            }
+        }
+        data.len()
+    }
+
+    /// Decodes the frame completed by the last delimiter
+    ///
+    /// # Returns
+    ///
+    /// * `None` if no frame is complete yet, or the frame is empty
+    /// * `Some(Ok(message))` for a decoded message
+    /// * `Some(Err(error))` for an invalid frame
+    pub fn next_message<T: DeserializeOwned>(&mut self) -> Option<Result<Message<T>, ProtocolError>> {
+        Some(self.next_frame()?.and_then(Message::try_from))
+    }
+
+    /// Returns the message bytes of the completed frame without decoding the payload
+    ///
+    /// Works like `StreamDecoder::next_frame`: the header and checksum are
+    /// validated, and the returned bytes stay valid until the decoder is used
+    /// again.
+    pub fn next_frame(&mut self) -> Option<Result<&[u8], ProtocolError>> {
+        if self.returned {
+            self.reset();
+        }
+        if !self.complete {
+            return None;
+        }
+
+        if self.overflow {
+            self.reset();
+            return Some(Err(ProtocolError::MessageTooLarge));
+        }
+        if self.length == 0 {
+            // Consecutive delimiters, e.g. sent to flush the receiver
+            self.reset();
+            return None;
+        }
+
+        let result = match cobs::decode_in_place(&mut self.buffer[..self.length]) {
+            Ok(length) => validate_frame(&self.buffer[..length]).map(|_| length),
+            Err(_) => Err(ProtocolError::InvalidFormat),
+        };
+        match result {
+            Ok(length) => {
+                self.length = length;
+                self.returned = true;
+                Some(Ok(&self.buffer[..length]))
+            }
+            Err(error) => {
+                self.reset();
+                Some(Err(error))
+            }
+        }
+    }
+
+    /// Discards the current frame
+    pub fn reset(&mut self) {
+        self.length = 0;
+        self.complete = false;
+        self.overflow = false;
+        self.returned = false;
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::protocol::MessageKind;
+
+    /// Returns a COBS frame holding a gas switch command with the given sequence number
+    fn framed(sequence: u16) -> ([u8; MAX_FRAME_SIZE], usize) {
+        let (length, frame) = Message::new(MessageKind::Command, sequence, Command::SwitchGas { slot: 3 })
+            .unwrap()
+            .serialize_framed()
+            .unwrap();
+        (frame, length)
+    }
+
+    /// Encodes `message` and decodes the frame again
+    fn round_trip(message: &[u8]) {
+        let mut frame = [0xAAu8; MAX_FRAME_SIZE];
+        let length = encode_frame(message, &mut frame).unwrap();
+        assert!(length <= MAX_FRAME_SIZE);
+        assert_eq!(frame[length - 1], FRAME_DELIMITER);
+        assert!(!frame[..length - 1].contains(&FRAME_DELIMITER));
+        let decoded = cobs::decode_in_place(&mut frame[..length - 1]).unwrap();
+        assert_eq!(&frame[..decoded], message);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        round_trip(&[]);
+        round_trip(&[0x00]);
+        round_trip(&[0x00, 0x00, 0x11, 0x00]);
+
+        // Runs of non-zero bytes just below, at and above the 254 byte COBS block length
+        let mut message = [0u8; MAX_MESSAGE_SIZE];
+        for run in [253, 254, 255, MAX_MESSAGE_SIZE] {
+            for (index, byte) in message.iter_mut().enumerate() {
+                *byte = if index < run { index as u8 | 0x01 } else { 0x00 };
+            }
+            round_trip(&message);
+            round_trip(&message[..run]);
+        }
+    }
+
+    #[test]
+    fn messages_round_trip_through_the_decoder() {
+        let (frame, length) = framed(0x0100);
+        // The header holds zero bytes that COBS has to remove
+        assert!(!frame[..length - 1].contains(&FRAME_DELIMITER));
+
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decoder.push(&frame[..length]), length);
+        let message = decoder.next_message::<Command>().unwrap().unwrap();
+        assert_eq!(message.header.sequence, 0x0100);
+        assert!(matches!(message.payload, Command::SwitchGas { slot: 3 }));
+        assert!(decoder.next_message::<Command>().is_none());
+    }
+
+    #[test]
+    fn oversize_frames_are_dropped_until_the_next_delimiter() {
+        let (frame, length) = framed(9);
+        let mut decoder = FrameDecoder::new();
+        for _ in 0..3 {
+            assert_eq!(decoder.push(&[0x55; MAX_FRAME_SIZE]), MAX_FRAME_SIZE);
+            assert!(decoder.next_message::<Command>().is_none());
+        }
+        assert_eq!(decoder.push(&[FRAME_DELIMITER]), 1);
+        assert_eq!(decoder.next_message::<Command>().unwrap().err(), Some(ProtocolError::MessageTooLarge));
+
+        assert_eq!(decoder.push(&frame[..length]), length);
+        assert_eq!(decoder.next_message::<Command>().unwrap().unwrap().header.sequence, 9);
+    }
+
+    #[test]
+    fn push_stops_after_a_delimiter() {
+        let (first, first_length) = framed(1);
+        let (second, second_length) = framed(2);
+        let mut stream = [0u8; 2 * MAX_FRAME_SIZE + 1];
+        // A leading delimiter flushes the receiver and yields no frame
+        stream[1..1 + first_length].copy_from_slice(&first[..first_length]);
+        stream[1 + first_length..1 + first_length + second_length].copy_from_slice(&second[..second_length]);
+        let mut remaining = &stream[..1 + first_length + second_length];
+
+        let mut decoder = FrameDecoder::new();
+        let mut sequences = [0u16; 2];
+        let mut count = 0;
+        while !remaining.is_empty() {
+            let taken = decoder.push(remaining);
+            remaining = &remaining[taken..];
+            if let Some(message) = decoder.next_message::<Command>() {
+                sequences[count] = message.unwrap().header.sequence;
+                count += 1;
+            }
+        }
+        assert_eq!(&sequences[..count], &[1, 2]);
+    }
+}
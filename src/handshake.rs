@@ -0,0 +1,235 @@
+//! Protocol version negotiation
+//!
+//! Before other messages are exchanged, both peers send a [`Hello`] in a
+//! `MessageKind::Handshake` message advertising the range of protocol versions
+//! they support and the optional features they implement. Each side then runs
+//! [`negotiate`] on the two hellos, which picks the highest version both
+//! understand and the capabilities both implement, so firmware and app can be
+//! upgraded independently.
+//!
+//! Handshake messages are always sent with `MIN_PROTOCOL_VERSION`, so every
+//! peer that supports the handshake can decode them regardless of the
+//! versions it supports. Version 1 peers from before the handshake reject
+//! the unknown message kind and send no hello back.
+
+use serde::{Serialize, Deserialize};
+use crate::protocol::{Message, MessageHeader, MessageKind, ProtocolError, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// Set of optional protocol features
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No optional features
+    pub const NONE: Capabilities = Capabilities(0);
+    /// COBS framing (`framing` module)
+    pub const COBS_FRAMING: Capabilities = Capabilities(1 << 0);
+    /// Acknowledgments and retransmission (`reliable` module)
+    pub const RELIABLE_DELIVERY: Capabilities = Capabilities(1 << 1);
+    /// Fragmented transfers (`fragment` module)
+    pub const FRAGMENTATION: Capabilities = Capabilities(1 << 2);
+    /// Pairing and encrypted sessions (`session` module)
+    pub const ENCRYPTED_SESSIONS: Capabilities = Capabilities(1 << 3);
+    /// All features implemented by this library
+    pub const ALL: Capabilities = Capabilities(
+        Capabilities::COBS_FRAMING.0
+            | Capabilities::RELIABLE_DELIVERY.0
+            | Capabilities::FRAGMENTATION.0
+            | Capabilities::ENCRYPTED_SESSIONS.0,
+    );
+
+    /// Creates a set from its wire representation
+    ///
+    /// Unknown bits are kept, so that they can be passed on unchanged.
+    pub const fn from_bits(bits: u32) -> Self {
+        Capabilities(bits)
+    }
+
+    /// Returns the wire representation
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every feature in `other` is also in the set
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features in both sets
+    pub const fn intersection(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+
+    /// Returns the features in either set
+    pub const fn union(self, other: Capabilities) -> Self {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+/// Payload of a `MessageKind::Handshake` message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Hello {
+    /// Oldest protocol version the sender supports
+    pub min_version: u8,
+    /// Newest protocol version the sender supports
+    pub max_version: u8,
+    /// Optional features the sender implements
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    /// Creates a hello advertising every version and feature of this library
+    pub const fn new() -> Self {
+        Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::ALL,
+        }
+    }
+
+    /// Creates a hello advertising a subset of the features of this library
+    ///
+    /// Use this on devices that do not enable every feature.
+    pub const fn with_capabilities(capabilities: Capabilities) -> Self {
+        Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+
+    /// Wraps the hello in a handshake message
+    ///
+    /// The message uses `MIN_PROTOCOL_VERSION`, so that peers of any version
+    /// with handshake support can decode it. The responder replies with its
+    /// own hello and the same sequence number.
+    pub fn to_message(self, sequence: u16) -> Result<Message<Hello>, ProtocolError> {
+        Message::with_version(MIN_PROTOCOL_VERSION, MessageKind::Handshake, sequence, self)
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Hello::new()
+    }
+}
+
+/// Outcome of a handshake
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Negotiated {
+    /// Protocol version to use for all further messages
+    pub version: u8,
+    /// Optional features both peers implement
+    pub capabilities: Capabilities,
+}
+
+impl Negotiated {
+    /// Creates a message with the negotiated version
+    pub fn message<T: Serialize>(&self, kind: MessageKind, sequence: u16, payload: T) -> Result<Message<T>, ProtocolError> {
+        Message::with_version(self.version, kind, sequence, payload)
+    }
+
+    /// Checks that a received header uses the negotiated version
+    ///
+    /// Handshake messages are accepted with any supported version, so that
+    /// a peer can start a new handshake at any time.
+    pub fn validate(&self, header: &MessageHeader) -> Result<(), ProtocolError> {
+        if header.kind != MessageKind::Handshake && header.version != self.version {
+            return Err(ProtocolError::UnsupportedVersion);
+        }
+        Ok(())
+    }
+}
+
+/// Determines the version and features to use from both hellos
+///
+/// # Arguments
+///
+/// * `local` - Hello sent by this side
+/// * `remote` - Hello received from the peer
+///
+/// # Returns
+///
+/// The highest version within both ranges and the capabilities both peers
+/// implement, or `UnsupportedVersion` if the ranges do not overlap
+pub fn negotiate(local: &Hello, remote: &Hello) -> Result<Negotiated, ProtocolError> {
+    let version = local.max_version.min(remote.max_version);
+    if version < local.min_version.max(remote.min_version) {
+        return Err(ProtocolError::UnsupportedVersion);
+    }
+    Ok(Negotiated {
+        version,
+        capabilities: local.capabilities.intersection(remote.capabilities),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryFrom;
+
+    /// Creates a hello for the versions `min_version..=max_version`
+    fn hello(min_version: u8, max_version: u8, capabilities: Capabilities) -> Hello {
+        Hello { min_version, max_version, capabilities }
+    }
+
+    #[test]
+    fn highest_common_version_is_used() {
+        let old = hello(1, 3, Capabilities::ALL);
+        let new = hello(2, 5, Capabilities::ALL);
+        assert_eq!(negotiate(&old, &new).unwrap().version, 3);
+        assert_eq!(negotiate(&new, &old).unwrap().version, 3);
+
+        // Ranges touching in a single version
+        let newest = hello(3, 7, Capabilities::ALL);
+        assert_eq!(negotiate(&old, &newest).unwrap().version, 3);
+
+        assert_eq!(negotiate(&Hello::new(), &Hello::new()).unwrap().version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn disjoint_versions_are_rejected() {
+        let old = hello(1, 2, Capabilities::ALL);
+        let new = hello(3, 4, Capabilities::ALL);
+        assert_eq!(negotiate(&old, &new), Err(ProtocolError::UnsupportedVersion));
+        assert_eq!(negotiate(&new, &old), Err(ProtocolError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn only_common_capabilities_are_used() {
+        let device = hello(1, 2, Capabilities::COBS_FRAMING.union(Capabilities::RELIABLE_DELIVERY));
+        // The app also advertises a feature unknown to this library
+        let app = hello(1, 2, Capabilities::ALL.union(Capabilities::from_bits(1 << 31)));
+        let negotiated = negotiate(&device, &app).unwrap();
+        assert_eq!(negotiated, negotiate(&app, &device).unwrap());
+        assert_eq!(negotiated.capabilities, Capabilities::COBS_FRAMING.union(Capabilities::RELIABLE_DELIVERY));
+        assert!(!negotiated.capabilities.contains(Capabilities::FRAGMENTATION));
+
+        let plain = hello(1, 2, Capabilities::NONE);
+        assert_eq!(negotiate(&plain, &app).unwrap().capabilities, Capabilities::NONE);
+    }
+
+    #[test]
+    fn negotiated_version_is_enforced() {
+        let negotiated = negotiate(&Hello::new(), &hello(1, 1, Capabilities::NONE)).unwrap();
+        let message = negotiated.message(MessageKind::Command, 1, 0u8).unwrap();
+        assert_eq!(message.header.version, 1);
+        assert_eq!(negotiated.validate(&message.header), Ok(()));
+
+        let newer = MessageHeader::with_version(2, MessageKind::Command, 2, 0);
+        assert_eq!(negotiated.validate(&newer), Err(ProtocolError::UnsupportedVersion));
+        // A new handshake is accepted in any version
+        let handshake = MessageHeader::with_version(2, MessageKind::Handshake, 3, 0);
+        assert_eq!(negotiated.validate(&handshake), Ok(()));
+    }
+
+    #[test]
+    fn hellos_are_sent_with_the_oldest_version() {
+        let sent = Hello::with_capabilities(Capabilities::FRAGMENTATION);
+        let (length, bytes) = sent.to_message(5).unwrap().serialize().unwrap();
+        let received = Message::<Hello>::try_from(&bytes[..length]).unwrap();
+        assert_eq!(received.header.version, MIN_PROTOCOL_VERSION);
+        assert_eq!(received.header.kind, MessageKind::Handshake);
+        assert_eq!(received.payload, sent);
+    }
+}
@@ -10,8 +10,33 @@
 //! * `sensor` - Defines sensor types and sensor data handling
 //! * `commands` - Defines command and response structures for dive computer operations
 //! * `dive_calc` - Implements dive-related calculations and algorithms
+//! * `buhlmann` - Implements the Bühlmann ZH-L16C decompression model
+//! * `deco` - Plans decompression schedules from the tissue state
+//! * `oxygen` - Tracks CNS and OTU oxygen toxicity
+//! * `surface` - Keeps residual loading between dives for repetitive dive planning
+//! * `fixed` - Provides a fixed-point number type for targets without an FPU
+//! * `real` - Selects the number type used by all dive calculations
 //! * `protocol` - Provides serialization/deserialization for communication
+//! * `framing` - Wraps messages in COBS frames for serial links
+//! * `reliable` - Adds acknowledgments, retransmission and duplicate suppression
+//! * `fragment` - Splits payloads larger than a message into fragments and reassembles them
+//! * `handshake` - Negotiates the protocol version and optional features with a peer
+//! * `session` - Pairs devices and encrypts and authenticates messages
+//! * `dispatcher` - Routes received commands to a device-side handler
+//! * `transport` - Moves bytes between host and device over loopback, serial and embedded links
+//! * `client` - Sends commands from the host and returns typed results
 //! * `examples` - Contains usage examples for the main functionality
+//!
+//! # Features
+//!
+//! * `fixed-point` - Runs all dive calculations on a fixed-point number type
+//! * `std` - Adds transports for hosts with an operating system and the
+//!   `dive-computer-emulator` binary
+//! * `embedded-io` - Adds a transport adapter for `embedded-io` drivers
+//! * `async` - Adds an async transport trait for `embedded-io-async` drivers
+
+#[cfg(feature = "std")]
+extern crate std;
 
 /// Sensor types and data handling
 pub mod sensor;
@@ -22,8 +47,50 @@ pub mod commands;
 /// Dive-related calculations and algorithms
 pub mod dive_calc;
 
+/// Bühlmann ZH-L16C decompression model
+pub mod buhlmann;
+
+/// Decompression schedule planning
+pub mod deco;
+
+/// Oxygen toxicity tracking
+pub mod oxygen;
+
+/// Surface interval and repetitive dive state
+pub mod surface;
+
+/// Fixed-point number type
+pub mod fixed;
+
+/// Number type used by the dive calculations
+pub mod real;
+
 /// Serialization/deserialization for communication
 pub mod protocol;
 
+/// COBS framing for serial links
+pub mod framing;
+
+/// Reliable delivery on top of the message protocol
+pub mod reliable;
+
+/// Fragmentation of payloads larger than a single message
+pub mod fragment;
+
+/// Protocol version negotiation
+pub mod handshake;
+
+/// Authenticated and encrypted sessions
+pub mod session;
+
+/// Device-side command dispatching
+pub mod dispatcher;
+
+/// Byte transports
+pub mod transport;
+
+/// Host-side client
+pub mod client;
+
 /// Usage examples for the main functionality
 pub mod examples;
@@ -0,0 +1,168 @@
+//! Oxygen toxicity tracking
+//!
+//! This module accumulates oxygen exposure over a dive as a CNS percentage
+//! (based on the NOAA single exposure limits) and as oxygen tolerance units
+//! (OTU, also known as UPTD). Both values decay during surface intervals.
+
+use serde::{Serialize, Deserialize};
+use crate::real::{self, Real, ONE, ZERO};
+
+/// Half-time of the CNS percentage during surface intervals in minutes
+pub const CNS_HALF_TIME_MINUTES: Real = real::lit(90.0);
+
+/// Half-time of the OTU dose during surface intervals in minutes
+///
+/// There is no standard recovery model for pulmonary toxicity; a 24 hour
+/// half-time is used so that the dose of a day of diving has largely
+/// recovered by the next day.
+pub const OTU_HALF_TIME_MINUTES: Real = real::lit(1440.0);
+
+/// PPO2 in bar below which no oxygen toxicity is accumulated
+pub const OXYGEN_TOXICITY_THRESHOLD_BAR: Real = real::lit(0.5);
+
+/// NOAA single exposure limits as (PPO2 in bar, limit in minutes)
+pub const NOAA_CNS_LIMITS: [(Real, Real); 11] = [
+    (real::lit(0.6), real::lit(720.0)),
+    (real::lit(0.7), real::lit(570.0)),
+    (real::lit(0.8), real::lit(450.0)),
+    (real::lit(0.9), real::lit(360.0)),
+    (real::lit(1.0), real::lit(300.0)),
+    (real::lit(1.1), real::lit(240.0)),
+    (real::lit(1.2), real::lit(210.0)),
+    (real::lit(1.3), real::lit(180.0)),
+    (real::lit(1.4), real::lit(150.0)),
+    (real::lit(1.5), real::lit(120.0)),
+    (real::lit(1.6), real::lit(45.0)),
+];
+
+/// Accumulated oxygen exposure
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct OxygenExposure {
+    /// Central nervous system oxygen toxicity in percent of the NOAA limit
+    pub cns_percent: Real,
+    /// Pulmonary oxygen toxicity in oxygen tolerance units
+    pub otu: Real,
+}
+
+impl OxygenExposure {
+    /// Creates an exposure record with no accumulated oxygen
+    pub fn new() -> Self {
+        OxygenExposure { cns_percent: ZERO, otu: ZERO }
+    }
+
+    /// Accumulates the exposure of breathing `ppo2_bar` for `seconds`
+    pub fn update(&mut self, ppo2_bar: Real, seconds: u32) {
+        if ppo2_bar <= OXYGEN_TOXICITY_THRESHOLD_BAR || seconds == 0 {
+            return;
+        }
+        let minutes = real::ratio(seconds.min(i32::MAX as u32) as i32, 60);
+        self.cns_percent += minutes / cns_limit_minutes(ppo2_bar) * real::from_int(100);
+        // OTU = t * ((PPO2 - 0.5) / 0.5) ^ (5/6)
+        let excess = (ppo2_bar - OXYGEN_TOXICITY_THRESHOLD_BAR) / OXYGEN_TOXICITY_THRESHOLD_BAR;
+        self.otu += minutes * real::powf(excess, real::ratio(5, 6));
+    }
+
+    /// Decays the exposure for a surface interval of `seconds`
+    pub fn surface_interval(&mut self, seconds: u32) {
+        let minutes = real::ratio(seconds.min(i32::MAX as u32) as i32, 60);
+        self.cns_percent *= real::decay(minutes, CNS_HALF_TIME_MINUTES);
+        self.otu *= real::decay(minutes, OTU_HALF_TIME_MINUTES);
+    }
+
+    /// Returns the CNS percentage rounded to a whole percent
+    pub fn cns_percent_rounded(&self) -> u16 {
+        real::round_u16(self.cns_percent)
+    }
+
+    /// Returns the OTU dose rounded to a whole unit
+    pub fn otu_rounded(&self) -> u16 {
+        real::round_u16(self.otu)
+    }
+}
+
+/// Returns the NOAA exposure limit in minutes for the given PPO2
+///
+/// Limits are interpolated linearly between table entries. Between 0.5 and
+/// 0.6 bar the 0.6 bar limit applies; above 1.6 bar the last table segment is
+/// extrapolated down to a limit of one minute.
+pub fn cns_limit_minutes(ppo2_bar: Real) -> Real {
+    let (first_ppo2, first_limit) = NOAA_CNS_LIMITS[0];
+    if ppo2_bar <= first_ppo2 {
+        return first_limit;
+    }
+    for window in NOAA_CNS_LIMITS.windows(2) {
+        let (low_ppo2, low_limit) = window[0];
+        let (high_ppo2, high_limit) = window[1];
+        if ppo2_bar <= high_ppo2 {
+            let position = (ppo2_bar - low_ppo2) / (high_ppo2 - low_ppo2);
+            return low_limit + (high_limit - low_limit) * position;
+        }
+    }
+
+    let (low_ppo2, low_limit) = NOAA_CNS_LIMITS[NOAA_CNS_LIMITS.len() - 2];
+    let (high_ppo2, high_limit) = NOAA_CNS_LIMITS[NOAA_CNS_LIMITS.len() - 1];
+    let slope = (high_limit - low_limit) / (high_ppo2 - low_ppo2);
+    (high_limit + slope * (ppo2_bar - high_ppo2)).max(ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns true if `actual` is within `tolerance` of `expected`
+    fn close(actual: Real, expected: f32, tolerance: f32) -> bool {
+        (real::to_f32(actual) - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn noaa_limits_are_used_at_table_entries() {
+        for (ppo2, limit) in NOAA_CNS_LIMITS {
+            assert!(close(cns_limit_minutes(ppo2), real::to_f32(limit), 0.01));
+        }
+        // Halfway between 1.4 bar (150 min) and 1.5 bar (120 min)
+        assert!(close(cns_limit_minutes(real::lit(1.45)), 135.0, 0.05));
+        assert!(close(cns_limit_minutes(real::lit(0.55)), 720.0, 0.01));
+    }
+
+    #[test]
+    fn full_noaa_exposure_is_one_hundred_percent() {
+        let mut exposure = OxygenExposure::new();
+        exposure.update(real::lit(1.6), 45 * 60);
+        assert!(close(exposure.cns_percent, 100.0, 0.01));
+        assert_eq!(exposure.cns_percent_rounded(), 100);
+
+        let mut exposure = OxygenExposure::new();
+        exposure.update(real::lit(1.2), 105 * 60);
+        assert_eq!(exposure.cns_percent_rounded(), 50);
+    }
+
+    #[test]
+    fn one_minute_at_one_bar_is_one_otu() {
+        let mut exposure = OxygenExposure::new();
+        exposure.update(ONE, 60);
+        assert!(close(exposure.otu, 1.0, 0.001));
+
+        // 60 minutes at 1.4 bar: 60 * 1.8^(5/6)
+        let mut exposure = OxygenExposure::new();
+        exposure.update(real::lit(1.4), 60 * 60);
+        assert!(close(exposure.otu, 60.0 * libm::powf(1.8, 5.0 / 6.0), 0.01));
+    }
+
+    #[test]
+    fn no_exposure_accumulates_at_or_below_the_threshold() {
+        let mut exposure = OxygenExposure::new();
+        exposure.update(real::lit(0.21), 3600);
+        exposure.update(OXYGEN_TOXICITY_THRESHOLD_BAR, 3600);
+        exposure.update(real::lit(1.4), 0);
+        assert_eq!(exposure, OxygenExposure::new());
+    }
+
+    #[test]
+    fn exposure_halves_over_each_half_time() {
+        let mut exposure = OxygenExposure { cns_percent: real::from_int(80), otu: real::from_int(300) };
+        exposure.surface_interval(90 * 60);
+        assert!(close(exposure.cns_percent, 40.0, 0.01));
+        exposure.surface_interval((1440 - 90) * 60);
+        assert!(close(exposure.otu, 150.0, 0.01));
+    }
+}
@@ -4,19 +4,35 @@
 //! for communication between the dive computer and external devices.
 
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use core::convert::TryFrom;
 
 /// Maximum message size in bytes
 pub const MAX_MESSAGE_SIZE: usize = 256;
 
 /// Protocol version
-pub const PROTOCOL_VERSION: u8 = 1;
+///
+/// Version 2 protects each message with a CRC-16 over header and payload;
+/// version 1 uses an 8-bit checksum over the payload only.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Oldest protocol version that is still accepted
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Size of the encoded message header in bytes since version 2
+pub const HEADER_SIZE: usize = 9;
+
+/// Largest size of an encoded version 1 header in bytes
+///
+/// Version 1 headers are serialized with Postcard, so the sequence number and
+/// payload length are varints of one to three bytes.
+pub const MAX_V1_HEADER_SIZE: usize = 11;
 
 /// Message header magic bytes for identifying valid messages
 pub const MESSAGE_MAGIC: [u8; 2] = [0xDC, 0x42]; // DC = Dive Computer, 42 = "the answer"
 
 /// Error types for protocol operations
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ProtocolError {
     /// Message is too large to fit in the buffer
     MessageTooLarge,
@@ -32,6 +48,14 @@ pub enum ProtocolError {
     SerializationError,
     /// Deserialization error
     DeserializationError,
+    /// Too many messages are waiting for an acknowledgment
+    WindowFull,
+    /// A fragmented transfer received no fragment in time and was abandoned
+    ReassemblyTimeout,
+    /// A sealed message was forged, corrupted or sealed with a different key
+    AuthenticationFailed,
+    /// A sealed message with this sequence number was already accepted
+    ReplayDetected,
 }
 
 /// Message types that can be sent or received
@@ -47,9 +71,38 @@ pub enum MessageKind {
     Ack = 0x04,
     /// Error message
     Error = 0x05,
+    /// Fragment of a payload larger than a single message
+    Fragment = 0x06,
+    /// Version and capability negotiation
+    Handshake = 0x07,
+    /// Pairing and session setup
+    Security = 0x08,
+}
+
+impl MessageKind {
+    /// Converts a wire value to a message kind
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(MessageKind::Command),
+            0x02 => Some(MessageKind::Response),
+            0x03 => Some(MessageKind::Notification),
+            0x04 => Some(MessageKind::Ack),
+            0x05 => Some(MessageKind::Error),
+            0x06 => Some(MessageKind::Fragment),
+            0x07 => Some(MessageKind::Handshake),
+            0x08 => Some(MessageKind::Security),
+            _ => None,
+        }
+    }
 }
 
 /// Message header containing metadata about the message
+///
+/// Since version 2 the header has the fixed layout produced by
+/// [`MessageHeader::encode`], with multi-byte fields in big-endian order.
+/// Version 1 headers are serialized with Postcard, as by the original
+/// version 1 peers; [`MessageHeader::write`] and [`MessageHeader::decode`]
+/// pick the layout from the version.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MessageHeader {
     /// Magic bytes to identify valid messages
@@ -67,39 +120,186 @@ pub struct MessageHeader {
 }
 
 impl MessageHeader {
-    /// Creates a new message header
+    /// Creates a new message header for the current protocol version
     pub fn new(kind: MessageKind, sequence: u16, payload_length: u16) -> Self {
+        MessageHeader::with_version(PROTOCOL_VERSION, kind, sequence, payload_length)
+    }
+    
+    /// Creates a new message header for the given protocol version
+    pub fn with_version(version: u8, kind: MessageKind, sequence: u16, payload_length: u16) -> Self {
         let mut header = MessageHeader {
             magic: MESSAGE_MAGIC,
-            version: PROTOCOL_VERSION,
+            version,
             kind,
             sequence,
             payload_length,
             header_checksum: 0, // Will be calculated below
         };
         
-        header.header_checksum = calculate_checksum(&[
-            header.magic[0], header.magic[1],
-            header.version,
-            header.kind as u8,
-            (header.sequence >> 8) as u8, header.sequence as u8,
-            (header.payload_length >> 8) as u8, header.payload_length as u8,
-        ]);
+        header.header_checksum = calculate_checksum(&header.encode()[..HEADER_SIZE - 1]);
         
         header
     }
     
-    /// Validates the header checksum
-    pub fn validate_checksum(&self) -> bool {
-        let calculated = calculate_checksum(&[
+    /// Encodes the header into the fixed layout of version 2
+    ///
+    /// The header checksum and the associated data of sealed messages are
+    /// computed over this layout in every version. Use `write` to encode the
+    /// header in the wire format of its version.
+    ///
+    /// | Offset | Size | Field                         |
+    /// |--------|------|-------------------------------|
+    /// | 0      | 2    | Magic bytes `[0xDC, 0x42]`    |
+    /// | 2      | 1    | Protocol version              |
+    /// | 3      | 1    | Message kind                  |
+    /// | 4      | 2    | Sequence number, big-endian   |
+    /// | 6      | 2    | Payload length, big-endian    |
+    /// | 8      | 1    | Checksum of bytes 0 to 7      |
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dive_computer_proto::protocol::{MessageHeader, MessageKind};
+    ///
+    /// let header = MessageHeader::with_version(2, MessageKind::Command, 0x1234, 5);
+    /// assert_eq!(header.encode(), [0xDC, 0x42, 0x02, 0x01, 0x12, 0x34, 0x00, 0x05, 0x93]);
+    ///
+    /// let header = MessageHeader::with_version(2, MessageKind::Error, 0xFFFF, 0);
+    /// assert_eq!(header.encode(), [0xDC, 0x42, 0x02, 0x05, 0xFF, 0xFF, 0x00, 0x00, 0xDC]);
+    /// ```
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let sequence = self.sequence.to_be_bytes();
+        let payload_length = self.payload_length.to_be_bytes();
+        [
             self.magic[0], self.magic[1],
             self.version,
             self.kind as u8,
-            (self.sequence >> 8) as u8, self.sequence as u8,
-            (self.payload_length >> 8) as u8, self.payload_length as u8,
-        ]);
+            sequence[0], sequence[1],
+            payload_length[0], payload_length[1],
+            self.header_checksum,
+        ]
+    }
+    
+    /// Writes the header in the wire format of its version to the start of `buffer`
+    ///
+    /// # Returns
+    ///
+    /// The header size, or `MessageTooLarge` if it does not fit into `buffer`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dive_computer_proto::protocol::{MessageHeader, MessageKind};
+    ///
+    /// let mut buffer = [0u8; 16];
+    /// let header = MessageHeader::with_version(1, MessageKind::Response, 1, 0x0102);
+    /// let size = header.write(&mut buffer).unwrap();
+    /// assert_eq!(&buffer[..size], &[0xDC, 0x42, 0x01, 0x01, 0x01, 0x82, 0x02, 0xDA]);
+    /// ```
+    pub fn write(&self, buffer: &mut [u8]) -> Result<usize, ProtocolError> {
+        if self.version >= 2 {
+            let header = buffer.get_mut(..HEADER_SIZE).ok_or(ProtocolError::MessageTooLarge)?;
+            header.copy_from_slice(&self.encode());
+            return Ok(HEADER_SIZE);
+        }
+        
+        match postcard::to_slice(self, buffer) {
+            Ok(data) => Ok(data.len()),
+            Err(postcard::Error::SerializeBufferFull) => Err(ProtocolError::MessageTooLarge),
+            Err(_) => Err(ProtocolError::SerializationError),
+        }
+    }
+    
+    /// Returns the size of the header in the wire format of its version in bytes
+    pub fn size(&self) -> usize {
+        if self.version >= 2 {
+            return HEADER_SIZE;
+        }
+        // Magic bytes, version, kind and checksum are single bytes
+        5 + varint_size(self.sequence) + varint_size(self.payload_length)
+    }
+    
+    /// Decodes a header from the start of `data`
+    ///
+    /// The layout is chosen by the version byte, which is at offset 2 in
+    /// both layouts. Only the layout is checked; use `validate` to check
+    /// magic bytes, version and checksum.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dive_computer_proto::protocol::{MessageHeader, MessageKind};
+    ///
+    /// let header = MessageHeader::decode(&[0xDC, 0x42, 0x02, 0x01, 0x12, 0x34, 0x00, 0x05, 0x93]).unwrap();
+    /// assert_eq!(header.kind, MessageKind::Command);
+    /// assert_eq!(header.sequence, 0x1234);
+    /// assert_eq!(header.payload_length, 5);
+    /// assert!(header.validate().is_ok());
+    ///
+    /// let header = MessageHeader::decode(&[0xDC, 0x42, 0x01, 0x01, 0x01, 0x82, 0x02, 0xDA]).unwrap();
+    /// assert_eq!(header.kind, MessageKind::Response);
+    /// assert_eq!(header.payload_length, 0x0102);
+    /// assert!(header.validate().is_ok());
+    /// ```
+    pub fn decode(data: &[u8]) -> Result<Self, ProtocolError> {
+        MessageHeader::decode_prefix(data)?.ok_or(ProtocolError::InvalidFormat)
+    }
+    
+    /// Decodes a header from the start of `data`, or returns `None` if
+    /// `data` ends before the header does
+    fn decode_prefix(data: &[u8]) -> Result<Option<Self>, ProtocolError> {
+        if data.len() < 3 {
+            return Ok(None);
+        }
+        
+        if data[2] == 1 {
+            return match postcard::take_from_bytes(data) {
+                Ok((header, _)) => Ok(Some(header)),
+                Err(postcard::Error::DeserializeUnexpectedEnd) => Ok(None),
+                Err(_) => Err(ProtocolError::InvalidFormat),
+            };
+        }
+        
+        if data.len() < HEADER_SIZE {
+            return Ok(None);
+        }
         
-        calculated == self.header_checksum
+        let kind = MessageKind::from_u8(data[3]).ok_or(ProtocolError::InvalidFormat)?;
+        Ok(Some(MessageHeader {
+            magic: [data[0], data[1]],
+            version: data[2],
+            kind,
+            sequence: u16::from_be_bytes([data[4], data[5]]),
+            payload_length: u16::from_be_bytes([data[6], data[7]]),
+            header_checksum: data[8],
+        }))
+    }
+    
+    /// Returns the size of the checksum that follows the payload in bytes
+    pub fn checksum_size(&self) -> usize {
+        if self.version >= 2 { 2 } else { 1 }
+    }
+    
+    /// Validates the header checksum
+    pub fn validate_checksum(&self) -> bool {
+        calculate_checksum(&self.encode()[..HEADER_SIZE - 1]) == self.header_checksum
+    }
+
+    /// Checks the magic bytes, version and header checksum
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        if self.magic != MESSAGE_MAGIC {
+            return Err(ProtocolError::InvalidMagic);
+        }
+        
+        if self.version < MIN_PROTOCOL_VERSION || self.version > PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion);
+        }
+        
+        if !self.validate_checksum() {
+            return Err(ProtocolError::ChecksumMismatch);
+        }
+        
+        Ok(())
     }
 }
 
@@ -110,13 +310,29 @@ pub struct Message<T> {
     pub header: MessageHeader,
     /// Message payload
     pub payload: T,
-    /// Payload checksum
-    pub payload_checksum: u8,
+    /// Message checksum: CRC-16 of header and payload since version 2, the
+    /// 8-bit payload checksum in version 1
+    ///
+    /// This field replaces the `payload_checksum: u8` field of earlier
+    /// releases; for version 1 messages it holds the same value.
+    pub checksum: u16,
 }
 
 impl<T: Serialize> Message<T> {
-    /// Creates a new message with the given payload
+    /// Creates a new message with the given payload for the current protocol version
     pub fn new(kind: MessageKind, sequence: u16, payload: T) -> Result<Self, ProtocolError> {
+        Message::with_version(PROTOCOL_VERSION, kind, sequence, payload)
+    }
+    
+    /// Creates a new message with the given payload for the given protocol version
+    ///
+    /// Use this to talk to peers that only support an older version, e.g. to
+    /// reply in the version of the request.
+    pub fn with_version(version: u8, kind: MessageKind, sequence: u16, payload: T) -> Result<Self, ProtocolError> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            return Err(ProtocolError::UnsupportedVersion);
+        }
+        
         // Serialize payload to calculate its length
         let mut payload_buffer = [0u8; MAX_MESSAGE_SIZE];
         let payload_length = match postcard::to_slice(&payload, &mut payload_buffer) {
@@ -128,41 +344,98 @@ impl<T: Serialize> Message<T> {
             return Err(ProtocolError::MessageTooLarge);
         }
         
-        let header = MessageHeader::new(kind, sequence, payload_length as u16);
-        let payload_checksum = calculate_checksum(&payload_buffer[..payload_length]);
+        let header = MessageHeader::with_version(version, kind, sequence, payload_length as u16);
+        let checksum = if version >= 2 {
+            let crc = update_crc16(CRC16_INITIAL, &header.encode());
+            update_crc16(crc, &payload_buffer[..payload_length])
+        } else {
+            calculate_checksum(&payload_buffer[..payload_length]) as u16
+        };
         
         Ok(Message {
             header,
             payload,
-            payload_checksum,
+            checksum,
         })
     }
     
     /// Serializes the message to a byte buffer
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dive_computer_proto::commands::Command;
+    /// use dive_computer_proto::protocol::{Message, MessageKind};
+    ///
+    /// let message = Message::new(MessageKind::Command, 7, Command::SwitchGas { slot: 3 }).unwrap();
+    /// let (size, buffer) = message.serialize().unwrap();
+    /// assert_eq!(
+    ///     &buffer[..size],
+    ///     &[0xDC, 0x42, 0x02, 0x01, 0x00, 0x07, 0x00, 0x02, 0xD5, 0x12, 0x03, 0x6C, 0x9F],
+    /// );
+    ///
+    /// let message = Message::with_version(1, MessageKind::Command, 7, Command::SwitchGas { slot: 3 }).unwrap();
+    /// let (size, buffer) = message.serialize().unwrap();
+    /// assert_eq!(
+    ///     &buffer[..size],
+    ///     &[0xDC, 0x42, 0x01, 0x00, 0x07, 0x02, 0xD6, 0x12, 0x03, 0xEA],
+    /// );
+    /// ```
     pub fn serialize(&self) -> Result<(usize, [u8; MAX_MESSAGE_SIZE]), ProtocolError> {
         let mut buffer = [0u8; MAX_MESSAGE_SIZE];
-        let mut offset = 0;
+        let size = self.serialize_into(&mut buffer)?;
+        Ok((size, buffer))
+    }
+    
+    /// Serializes the message directly into a caller-provided buffer, e.g. a
+    /// DMA transmit buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Output buffer; bytes after the message are left unchanged
+    ///
+    /// # Returns
+    ///
+    /// The message length, or `MessageTooLarge` if the message does not fit
+    /// into `buffer` or exceeds `MAX_MESSAGE_SIZE`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dive_computer_proto::commands::Command;
+    /// use dive_computer_proto::protocol::{Message, MessageKind};
+    ///
+    /// let message = Message::new(MessageKind::Command, 7, Command::SwitchGas { slot: 3 }).unwrap();
+    /// let mut dma_buffer = [0u8; 64];
+    /// let size = message.serialize_into(&mut dma_buffer).unwrap();
+    /// assert_eq!(
+    ///     &dma_buffer[..size],
+    ///     &[0xDC, 0x42, 0x02, 0x01, 0x00, 0x07, 0x00, 0x02, 0xD5, 0x12, 0x03, 0x6C, 0x9F],
+    /// );
+    /// ```
+    pub fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, ProtocolError> {
+        let limit = buffer.len().min(MAX_MESSAGE_SIZE);
         
-        // Serialize header
-        match postcard::to_slice(&self.header, &mut buffer) {
-            Ok(data) => offset += data.len(),
-            Err(_) => return Err(ProtocolError::SerializationError),
-        }
+        // Encode header
+        let mut offset = self.header.write(&mut buffer[..limit])?;
         
         // Serialize payload
-        match postcard::to_slice(&self.payload, &mut buffer[offset..]) {
+        match postcard::to_slice(&self.payload, &mut buffer[offset..limit]) {
             Ok(data) => offset += data.len(),
+            Err(postcard::Error::SerializeBufferFull) => return Err(ProtocolError::MessageTooLarge),
             Err(_) => return Err(ProtocolError::SerializationError),
         }
         
-        // Add payload checksum
-        if offset >= MAX_MESSAGE_SIZE {
+        // Add checksum, big-endian
+        let checksum_size = self.header.checksum_size();
+        if offset + checksum_size > limit {
             return Err(ProtocolError::MessageTooLarge);
         }
-        buffer[offset] = self.payload_checksum;
-        offset += 1;
+        let checksum_bytes = self.checksum.to_be_bytes();
+        buffer[offset..offset + checksum_size].copy_from_slice(&checksum_bytes[2 - checksum_size..]);
+        offset += checksum_size;
         
-        Ok((offset, buffer))
+        Ok(offset)
     }
 }
 
@@ -171,58 +444,278 @@ impl<'de, T: Deserialize<'de>> TryFrom<&'de [u8]> for Message<T> {
     
     /// Deserializes a message from a byte buffer
     fn try_from(data: &'de [u8]) -> Result<Self, Self::Error> {
-        if data.len() < 9 { // Minimum size for a header
-            return Err(ProtocolError::InvalidFormat);
-        }
-        
-        // Deserialize header
-        let (header, header_size): (MessageHeader, usize) = match postcard::take_from_bytes(data) {
-            Ok((header, rest)) => (header, data.len() - rest.len()),
-            Err(_) => return Err(ProtocolError::DeserializationError),
-        };
-        
-        // Validate header
-        if header.magic != MESSAGE_MAGIC {
-            return Err(ProtocolError::InvalidMagic);
-        }
-        
-        if header.version != PROTOCOL_VERSION {
-            return Err(ProtocolError::UnsupportedVersion);
-        }
-        
-        if !header.validate_checksum() {
-            return Err(ProtocolError::ChecksumMismatch);
-        }
-        
-        // Check if we have enough data for the payload
-        let expected_size = header_size + header.payload_length as usize + 1; // +1 for payload checksum
-        if data.len() < expected_size {
-            return Err(ProtocolError::InvalidFormat);
-        }
+        // Validate header and checksum before looking at the payload
+        let (header, checksum) = validate_frame(data)?;
         
         // Deserialize payload
-        let payload_data = &data[header_size..(header_size + header.payload_length as usize)];
+        let header_size = header.size();
+        let payload_data = &data[header_size..header_size + header.payload_length as usize];
         let payload: T = match postcard::from_bytes(payload_data) {
             Ok(payload) => payload,
             Err(_) => return Err(ProtocolError::DeserializationError),
         };
         
-        // Validate payload checksum
-        let payload_checksum = data[header_size + header.payload_length as usize];
-        let calculated_checksum = calculate_checksum(payload_data);
-        
-        if calculated_checksum != payload_checksum {
-            return Err(ProtocolError::ChecksumMismatch);
-        }
-        
         Ok(Message {
             header,
             payload,
-            payload_checksum,
+            checksum,
         })
     }
 }
 
+/// Checks the header and checksum of the frame at the start of `data`
+///
+/// This does not look at the payload, so it also works for frames whose
+/// payload type is not known yet.
+///
+/// # Returns
+///
+/// The decoded header and the checksum of the frame
+pub fn validate_frame(data: &[u8]) -> Result<(MessageHeader, u16), ProtocolError> {
+    // Decode and validate header
+    let header = MessageHeader::decode(data)?;
+    header.validate()?;
+    
+    // Check if we have enough data for the payload
+    let header_size = header.size();
+    let payload_end = header_size + header.payload_length as usize;
+    let expected_size = payload_end + header.checksum_size();
+    if data.len() < expected_size {
+        return Err(ProtocolError::InvalidFormat);
+    }
+    
+    let checksum_data = &data[payload_end..expected_size];
+    let (checksum, calculated_checksum) = if header.version >= 2 {
+        (
+            u16::from_be_bytes([checksum_data[0], checksum_data[1]]),
+            calculate_crc16(&data[..payload_end]),
+        )
+    } else {
+        (checksum_data[0] as u16, calculate_checksum(&data[header_size..payload_end]) as u16)
+    };
+    
+    if calculated_checksum != checksum {
+        return Err(ProtocolError::ChecksumMismatch);
+    }
+    
+    Ok((header, checksum))
+}
+
+/// Writes `header` and the matching checksum around a payload that is
+/// already in place after the first `header.size()` bytes of `buffer`
+///
+/// Returns the message length, or `MessageTooLarge` if the checksum does not fit.
+pub(crate) fn write_envelope(buffer: &mut [u8], header: &MessageHeader) -> Result<usize, ProtocolError> {
+    let header_size = header.size();
+    let payload_end = header_size + header.payload_length as usize;
+    let length = payload_end + header.checksum_size();
+    if length > buffer.len() || length > MAX_MESSAGE_SIZE {
+        return Err(ProtocolError::MessageTooLarge);
+    }
+    
+    header.write(buffer)?;
+    if header.version >= 2 {
+        let crc = calculate_crc16(&buffer[..payload_end]);
+        buffer[payload_end..length].copy_from_slice(&crc.to_be_bytes());
+    } else {
+        buffer[payload_end] = calculate_checksum(&buffer[header_size..payload_end]);
+    }
+    Ok(length)
+}
+
+/// Incremental decoder for messages arriving in arbitrary chunks
+///
+/// Bytes are appended with [`StreamDecoder::push`] as they arrive, e.g. from a
+/// UART interrupt or BLE notifications, and complete messages are taken out
+/// with [`StreamDecoder::next_message`]. A chunk may hold part of a message or
+/// several messages back to back.
+///
+/// Bytes before `MESSAGE_MAGIC` are discarded. When a candidate frame turns
+/// out to be invalid, only its first byte is dropped, so a valid frame that
+/// follows a corrupted or truncated one is still found.
+#[derive(Debug)]
+pub struct StreamDecoder {
+    /// Received bytes that have not been decoded yet
+    buffer: [u8; MAX_MESSAGE_SIZE],
+    /// Number of valid bytes in `buffer`
+    length: usize,
+    /// Length of the frame returned by `next_frame`, removed on the next call
+    returned: usize,
+}
+
+impl StreamDecoder {
+    /// Creates an empty decoder
+    pub const fn new() -> Self {
+        StreamDecoder {
+            buffer: [0u8; MAX_MESSAGE_SIZE],
+            length: 0,
+            returned: 0,
+        }
+    }
+    
+    /// Appends received bytes to the decoder
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Bytes as received from the transport
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes taken from `data`. This is less than `data.len()`
+    /// when the buffer is full; call `next_message` to make room and push the
+    /// remaining bytes again.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        self.release();
+        let count = data.len().min(MAX_MESSAGE_SIZE - self.length);
+        self.buffer[self.length..self.length + count].copy_from_slice(&data[..count]);
+        self.length += count;
+        count
+    }
+    
+    /// Decodes the next message from the buffered bytes
+    ///
+    /// # Returns
+    ///
+    /// * `None` if no complete message is buffered yet
+    /// * `Some(Ok(message))` for each decoded message
+    /// * `Some(Err(error))` for an invalid frame; call again to continue with
+    ///   the bytes that follow it
+    pub fn next_message<T: DeserializeOwned>(&mut self) -> Option<Result<Message<T>, ProtocolError>> {
+        Some(self.next_frame()?.and_then(Message::try_from))
+    }
+    
+    /// Returns the next complete frame without decoding its payload
+    ///
+    /// The header and checksum of the frame are validated. This is useful
+    /// when the payload type depends on the header, e.g. for `MessageKind::Ack`
+    /// messages, which have no payload, and for payload types that borrow from
+    /// the frame, such as `commands::Notification`, which `Message::try_from`
+    /// decodes in place. The frame stays valid until the decoder is used again.
+    ///
+    /// # Returns
+    ///
+    /// The same as `next_message`, with the raw frame instead of the message
+    pub fn next_frame(&mut self) -> Option<Result<&[u8], ProtocolError>> {
+        self.release();
+        self.synchronize();
+        
+        let result = match frame_length(&self.buffer[..self.length]) {
+            Ok(None) => return None,
+            Ok(Some(length)) => validate_frame(&self.buffer[..length]).map(|_| length),
+            Err(error) => Err(error),
+        };
+        
+        match result {
+            Ok(length) => {
+                self.returned = length;
+                Some(Ok(&self.buffer[..length]))
+            }
+            Err(error) => {
+                // Skip this magic and search for the next frame
+                self.consume(1);
+                Some(Err(error))
+            }
+        }
+    }
+    
+    /// Discards all buffered bytes
+    pub fn reset(&mut self) {
+        self.length = 0;
+        self.returned = 0;
+    }
+    
+    /// Returns the number of buffered bytes that have not been decoded yet
+    pub fn buffered_len(&self) -> usize {
+        self.length - self.returned
+    }
+    
+    /// Removes the frame returned by the last `next_frame` call
+    fn release(&mut self) {
+        self.consume(self.returned);
+        self.returned = 0;
+    }
+    
+    /// Discards bytes up to the next occurrence of `MESSAGE_MAGIC`
+    ///
+    /// A trailing first magic byte is kept, as the second one may still arrive.
+    fn synchronize(&mut self) {
+        let data = &self.buffer[..self.length];
+        let start = match data.windows(MESSAGE_MAGIC.len()).position(|window| window == MESSAGE_MAGIC) {
+            Some(position) => position,
+            None if data.last() == Some(&MESSAGE_MAGIC[0]) => self.length - 1,
+            None => self.length,
+        };
+        self.consume(start);
+    }
+    
+    /// Removes `count` bytes from the front of the buffer
+    fn consume(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.length, 0);
+        self.length -= count;
+    }
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        StreamDecoder::new()
+    }
+}
+
+/// Returns the length of the frame at the start of `data`
+///
+/// Returns `Ok(None)` if `data` does not hold the complete frame yet, or an
+/// error if the header is invalid or announces a frame larger than
+/// `MAX_MESSAGE_SIZE`.
+fn frame_length(data: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    let header = match MessageHeader::decode_prefix(data)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    header.validate()?;
+    
+    let length = header.size() + header.payload_length as usize + header.checksum_size();
+    if length > MAX_MESSAGE_SIZE {
+        return Err(ProtocolError::MessageTooLarge);
+    }
+    
+    if data.len() < length { Ok(None) } else { Ok(Some(length)) }
+}
+
+/// Returns the size of a `u16` encoded as a Postcard varint in bytes
+fn varint_size(value: u16) -> usize {
+    match value {
+        0..=0x7F => 1,
+        0x80..=0x3FFF => 2,
+        _ => 3,
+    }
+}
+
+/// Initial value of the CRC-16 register
+const CRC16_INITIAL: u16 = 0xFFFF;
+
+/// CRC-16 generator polynomial x^16 + x^12 + x^5 + 1
+const CRC16_POLYNOMIAL: u16 = 0x1021;
+
+/// Calculates the CRC-16-CCITT of the given data
+///
+/// This is the CRC-16/CCITT-FALSE variant: polynomial 0x1021, initial value
+/// 0xFFFF, no reflection and no final XOR. The check value for the ASCII
+/// string "123456789" is 0x29B1.
+pub fn calculate_crc16(data: &[u8]) -> u16 {
+    update_crc16(CRC16_INITIAL, data)
+}
+
+/// Feeds `data` into a running CRC-16 calculation
+fn update_crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ CRC16_POLYNOMIAL } else { crc << 1 };
+        }
+    }
+    crc
+}
+
 /// Calculates a simple checksum for the given data
 pub fn calculate_checksum(data: &[u8]) -> u8 {
     let mut checksum: u8 = 0;
@@ -230,4 +723,109 @@ pub fn calculate_checksum(data: &[u8]) -> u8 {
         checksum = checksum.wrapping_add(byte);
     }
     !checksum // Invert bits for better error detection
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+
+    /// Serializes a gas switch command with the given sequence number
+    fn frame(sequence: u16) -> ([u8; MAX_MESSAGE_SIZE], usize) {
+        let (length, bytes) = Message::new(MessageKind::Command, sequence, Command::SwitchGas { slot: 3 })
+            .unwrap()
+            .serialize()
+            .unwrap();
+        (bytes, length)
+    }
+
+    /// Returns the sequence number of the next message, or `None` if there is none or it is invalid
+    fn next_sequence(decoder: &mut StreamDecoder) -> Option<u16> {
+        match decoder.next_message::<Command>()? {
+            Ok(message) => {
+                assert!(matches!(message.payload, Command::SwitchGas { slot: 3 }));
+                Some(message.header.sequence)
+            }
+            Err(_) => None,
+        }
+    }
+
+    #[test]
+    fn leading_garbage_is_skipped() {
+        let (bytes, length) = frame(7);
+        let mut decoder = StreamDecoder::new();
+        // Includes a lone first magic byte and a truncated magic at the end of the garbage
+        decoder.push(&[0x00, 0xDC, 0x13, 0xFF, 0x42, 0xDC]);
+        assert!(decoder.next_message::<Command>().is_none());
+        // The trailing 0xDC is kept until the next byte shows it does not start a frame
+        assert_eq!(decoder.buffered_len(), 1);
+        decoder.push(&bytes[..length]);
+        assert_eq!(next_sequence(&mut decoder), Some(7));
+        assert!(decoder.next_message::<Command>().is_none());
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn frames_split_across_pushes_are_reassembled() {
+        let (first, first_length) = frame(1);
+        let (second, second_length) = frame(2);
+        let mut decoder = StreamDecoder::new();
+        for &byte in &first[..first_length - 1] {
+            decoder.push(&[byte]);
+            assert!(decoder.next_message::<Command>().is_none());
+        }
+        // The last byte of the first frame arrives together with half of the second
+        let mut chunk = [first[first_length - 1]; 1 + MAX_MESSAGE_SIZE];
+        chunk[1..1 + second_length / 2].copy_from_slice(&second[..second_length / 2]);
+        decoder.push(&chunk[..1 + second_length / 2]);
+        assert_eq!(next_sequence(&mut decoder), Some(1));
+        assert!(decoder.next_message::<Command>().is_none());
+        decoder.push(&second[second_length / 2..second_length]);
+        assert_eq!(next_sequence(&mut decoder), Some(2));
+    }
+
+    #[test]
+    fn valid_frames_after_corrupt_ones_are_decoded() {
+        let (valid, length) = frame(3);
+
+        // A corrupted payload length fails the header checksum
+        let mut corrupt_length = valid;
+        corrupt_length[7] ^= 0x40;
+        // A corrupted payload byte fails the CRC
+        let mut corrupt_crc = valid;
+        corrupt_crc[HEADER_SIZE] ^= 0x01;
+
+        let mut decoder = StreamDecoder::new();
+        decoder.push(&corrupt_length[..length]);
+        decoder.push(&corrupt_crc[..length]);
+        decoder.push(&valid[..length]);
+        assert_eq!(decoder.next_message::<Command>().unwrap().err(), Some(ProtocolError::ChecksumMismatch));
+        assert_eq!(decoder.next_message::<Command>().unwrap().err(), Some(ProtocolError::ChecksumMismatch));
+        assert_eq!(next_sequence(&mut decoder), Some(3));
+        assert!(decoder.next_message::<Command>().is_none());
+    }
+
+    #[test]
+    fn crc16_matches_the_check_value() {
+        assert_eq!(calculate_crc16(b"123456789"), 0x29B1);
+        assert_eq!(calculate_crc16(&[]), CRC16_INITIAL);
+        // Split calculations give the same result
+        assert_eq!(update_crc16(calculate_crc16(b"1234"), b"56789"), 0x29B1);
+    }
+
+    #[test]
+    fn single_bit_errors_are_detected() {
+        let (valid, length) = frame(0x0102);
+        assert!(validate_frame(&valid[..length]).is_ok());
+        for index in 0..length {
+            for bit in 0..8 {
+                let mut corrupt = valid;
+                corrupt[index] ^= 1 << bit;
+                let result = validate_frame(&corrupt[..length]);
+                assert!(result.is_err(), "bit {} of byte {} not detected", bit, index);
+                if index >= HEADER_SIZE {
+                    assert_eq!(result.err(), Some(ProtocolError::ChecksumMismatch));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Numeric type used by the dive calculations
+//!
+//! All dive calculations use the [`Real`] type and the helpers in this module
+//! instead of a concrete number type. By default `Real` is `f32`; with the
+//! `fixed-point` feature it is the Q32.32 [`Fixed`](crate::fixed::Fixed) type,
+//! for targets without a floating point unit. See the `fixed` module for the
+//! accuracy of the fixed-point path relative to the `f32` reference.
+
+/// Number type used by the dive calculations
+#[cfg(not(feature = "fixed-point"))]
+pub type Real = f32;
+
+/// Number type used by the dive calculations
+#[cfg(feature = "fixed-point")]
+pub type Real = crate::fixed::Fixed;
+
+/// 0.0
+pub const ZERO: Real = lit(0.0);
+
+/// 1.0
+pub const ONE: Real = lit(1.0);
+
+/// Natural logarithm of 2
+pub const LN_2: Real = lit(core::f32::consts::LN_2);
+
+/// Converts a floating point literal to `Real`
+///
+/// Intended for constants, where the conversion happens at compile time.
+pub const fn lit(value: f32) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        value
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        crate::fixed::Fixed::from_f32(value)
+    }
+}
+
+/// Converts an array of floating point literals to `Real`
+pub const fn lits<const N: usize>(values: [f32; N]) -> [Real; N] {
+    let mut result = [ZERO; N];
+    let mut i = 0;
+    while i < N {
+        result[i] = lit(values[i]);
+        i += 1;
+    }
+    result
+}
+
+/// Converts an integer to `Real`
+pub fn from_int(value: i32) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        value as f32
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        crate::fixed::Fixed::from_int(value)
+    }
+}
+
+/// Returns `numerator / denominator` as `Real`
+pub fn ratio(numerator: i32, denominator: i32) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        numerator as f32 / denominator as f32
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        crate::fixed::Fixed::from_ratio(numerator, denominator)
+    }
+}
+
+/// Converts a `Real` to `f32`, e.g. for display or comparison with a reference
+pub fn to_f32(value: Real) -> f32 {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        value
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        value.to_f32()
+    }
+}
+
+/// Returns e raised to the power of `value`
+pub fn exp(value: Real) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::expf(value)
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        value.exp()
+    }
+}
+
+/// Returns the fraction remaining after exponential decay for `time` with
+/// the given `half_time`, i.e. `2^(-time / half_time)`
+pub fn decay(time: Real, half_time: Real) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::expf(-LN_2 * time / half_time)
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        time.decay(half_time)
+    }
+}
+
+/// Returns the integral of the decay curve from zero to `time`, i.e.
+/// `half_time / ln(2) * (1 - 2^(-time / half_time))`
+pub fn decay_integral(time: Real, half_time: Real) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        -half_time / LN_2 * libm::expm1f(-LN_2 * time / half_time)
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        time.decay_integral(half_time)
+    }
+}
+
+/// Returns the square root of `value`
+pub fn sqrt(value: Real) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::sqrtf(value)
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        value.sqrt()
+    }
+}
+
+/// Returns `base` raised to the power of `exponent`
+pub fn powf(base: Real, exponent: Real) -> Real {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::powf(base, exponent)
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        base.powf(exponent)
+    }
+}
+
+/// Rounds to the nearest integer, with halves rounded away from zero
+pub fn round(value: Real) -> i32 {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::roundf(value) as i32
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        value.round_to_int()
+    }
+}
+
+/// Returns the smallest integer greater than or equal to `value`
+pub fn ceil(value: Real) -> i32 {
+    #[cfg(not(feature = "fixed-point"))]
+    {
+        libm::ceilf(value) as i32
+    }
+    #[cfg(feature = "fixed-point")]
+    {
+        value.ceil_to_int()
+    }
+}
+
+/// Rounds to the nearest integer and clamps the result to the `u16` range
+pub fn round_u16(value: Real) -> u16 {
+    round(value).clamp(0, u16::MAX as i32) as u16
+}
+
+/// Rounds up to the next integer and clamps the result to the `u16` range
+pub fn ceil_u16(value: Real) -> u16 {
+    ceil(value).clamp(0, u16::MAX as i32) as u16
+}
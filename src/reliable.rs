@@ -0,0 +1,658 @@
+//! Reliable delivery on top of the message protocol
+//!
+//! This module adds acknowledgments, retransmission and duplicate suppression
+//! to `protocol::Message`. Every message sent through a [`ReliableLink`] gets
+//! the next sequence number and is kept until the peer acknowledges it with a
+//! `MessageKind::Ack` message carrying the same sequence number. Messages that
+//! are not acknowledged in time are retransmitted; the receiving side
+//! acknowledges every message again but delivers it only once.
+//!
+//! A retransmitted message can arrive after messages sent later. Use a window
+//! size of 1 where messages must be delivered strictly in order.
+//!
+//! Within a session, frames are passed to [`ReliableLink::receive_sealed`],
+//! which records a sequence number as received only after the message was
+//! authenticated and exchanges sealed acknowledgments.
+//!
+//! The link does not read a clock itself; the caller passes the current time
+//! in milliseconds, which may wrap around.
+
+use serde::Serialize;
+use crate::protocol::{
+    validate_frame, Message, MessageHeader, MessageKind, ProtocolError, HEADER_SIZE, MAX_MESSAGE_SIZE,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::session::{is_protected, Session, TAG_SIZE};
+
+/// Maximum number of unacknowledged messages a link can hold
+pub const MAX_WINDOW_SIZE: usize = 8;
+
+/// Largest size of a serialized acknowledgment in bytes (version 2 header,
+/// authentication tag of a sealed acknowledgment and CRC-16)
+pub const ACK_FRAME_SIZE: usize = HEADER_SIZE + TAG_SIZE + 2;
+
+/// Settings that control retransmission and duplicate suppression
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ReliabilityConfig {
+    /// Time to wait for an acknowledgment before retransmitting in milliseconds
+    pub ack_timeout_ms: u32,
+    /// Number of retransmissions before a message is given up
+    pub max_retries: u8,
+    /// Number of sequence numbers from the oldest unacknowledged message on
+    /// that may be in flight, at most `MAX_WINDOW_SIZE`
+    ///
+    /// The receiving side remembers the same number of sequence numbers, so
+    /// both peers must use the same window size.
+    pub window_size: u8,
+}
+
+impl Default for ReliabilityConfig {
+    fn default() -> Self {
+        ReliabilityConfig {
+            ack_timeout_ms: 500,
+            max_retries: 5,
+            window_size: 4,
+        }
+    }
+}
+
+/// Serialized acknowledgment to send back to the peer
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AckFrame {
+    /// Serialized `MessageKind::Ack` message
+    bytes: [u8; ACK_FRAME_SIZE],
+    /// Number of valid bytes in `bytes`
+    length: usize,
+}
+
+impl AckFrame {
+    /// Creates the acknowledgment for a message with the given header
+    /// fields, sealed with `session` if given
+    fn new(version: u8, sequence: u16, session: Option<&mut Session>) -> Result<Self, ProtocolError> {
+        let mut bytes = [0u8; ACK_FRAME_SIZE];
+        let mut length = Message::with_version(version, MessageKind::Ack, sequence, ())?.serialize_into(&mut bytes)?;
+        if let Some(session) = session {
+            length = session.seal(&mut bytes, length)?;
+        }
+        Ok(AckFrame { bytes, length })
+    }
+
+    /// Returns the bytes to send
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+}
+
+/// Result of passing a received frame to the link
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Received<'a> {
+    /// The peer acknowledged the message with this sequence number
+    Ack {
+        /// Sequence number of the acknowledged message
+        sequence: u16,
+        /// False if no message with this sequence number was pending
+        pending: bool,
+    },
+    /// A new message; deliver `frame` and send `ack`
+    Message {
+        /// The received frame, e.g. for `Message::try_from`
+        frame: &'a [u8],
+        /// Acknowledgment to send back
+        ack: AckFrame,
+    },
+    /// A message that was already delivered; only send `ack`
+    ///
+    /// This happens when an acknowledgment got lost and the peer retransmitted.
+    Duplicate {
+        /// Acknowledgment to send back
+        ack: AckFrame,
+    },
+}
+
+/// Event reported by `ReliableLink::poll`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LinkEvent<'a> {
+    /// A message was not acknowledged in time; send these bytes again
+    Retransmit(&'a [u8]),
+    /// A message was not acknowledged after all retries and has been dropped
+    Failed {
+        /// Sequence number of the dropped message
+        sequence: u16,
+    },
+}
+
+/// A sent message waiting for its acknowledgment
+#[derive(Debug, Clone, Copy)]
+struct PendingMessage {
+    /// Serialized message
+    bytes: [u8; MAX_MESSAGE_SIZE],
+    /// Number of valid bytes in `bytes`
+    length: usize,
+    /// Sequence number of the message
+    sequence: u16,
+    /// Time of the last transmission in milliseconds
+    sent_at_ms: u32,
+    /// Number of retransmissions so far
+    retries: u8,
+}
+
+/// Sequence numbers recently received from the peer
+#[derive(Debug, Clone, Copy, Default)]
+struct ReceiveWindow {
+    /// Highest sequence number received, or `None` before the first message
+    highest: Option<u16>,
+    /// Bit `n` is set if sequence number `highest - n` was received
+    received: u32,
+}
+
+impl ReceiveWindow {
+    /// Returns true if `sequence` was not received yet
+    ///
+    /// Sequence numbers more than `window_size` behind the highest one are
+    /// treated as already received.
+    fn is_new(&self, sequence: u16, window_size: u8) -> bool {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => return true,
+        };
+
+        // Distance in wrapping sequence space; ahead if within half the range
+        let ahead = sequence.wrapping_sub(highest);
+        if ahead != 0 && ahead < 0x8000 {
+            return true;
+        }
+
+        let behind = highest.wrapping_sub(sequence);
+        behind < window_size as u16 && self.received & (1u32 << behind) == 0
+    }
+
+    /// Records `sequence` as received; only call this if `is_new` returned true
+    fn commit(&mut self, sequence: u16) {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(sequence);
+                self.received = 1;
+                return;
+            }
+        };
+
+        let ahead = sequence.wrapping_sub(highest);
+        if ahead != 0 && ahead < 0x8000 {
+            self.received = if ahead >= 32 { 0 } else { self.received << ahead };
+            self.received |= 1;
+            self.highest = Some(sequence);
+        } else {
+            self.received |= 1u32 << highest.wrapping_sub(sequence);
+        }
+    }
+}
+
+/// Reliable message delivery to a single peer
+#[derive(Debug, Clone)]
+pub struct ReliableLink {
+    /// Retransmission and window settings
+    config: ReliabilityConfig,
+    /// Protocol version of sent messages
+    version: u8,
+    /// Sequence number of the next message to send
+    next_sequence: u16,
+    /// Messages waiting for an acknowledgment
+    pending: [Option<PendingMessage>; MAX_WINDOW_SIZE],
+    /// Sequence numbers received from the peer
+    received: ReceiveWindow,
+}
+
+impl ReliableLink {
+    /// Creates a link with the given settings
+    ///
+    /// The window size is limited to `1..=MAX_WINDOW_SIZE`.
+    pub fn new(config: ReliabilityConfig) -> Self {
+        let mut config = config;
+        config.window_size = config.window_size.clamp(1, MAX_WINDOW_SIZE as u8);
+        ReliableLink {
+            config,
+            version: PROTOCOL_VERSION,
+            next_sequence: 0,
+            pending: [None; MAX_WINDOW_SIZE],
+            received: ReceiveWindow::default(),
+        }
+    }
+
+    /// Returns the settings of the link
+    pub fn config(&self) -> &ReliabilityConfig {
+        &self.config
+    }
+
+    /// Sets the protocol version of sent messages, e.g. the version agreed
+    /// in a handshake
+    ///
+    /// Messages that are already pending keep their version. Returns
+    /// `UnsupportedVersion` for versions this library cannot encode.
+    pub fn set_version(&mut self, version: u8) -> Result<(), ProtocolError> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            return Err(ProtocolError::UnsupportedVersion);
+        }
+        self.version = version;
+        Ok(())
+    }
+
+    /// Serializes a message with the next sequence number and keeps it for retransmission
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Message kind; must not be `MessageKind::Ack`
+    /// * `payload` - Message payload
+    /// * `now_ms` - Current time in milliseconds
+    ///
+    /// # Returns
+    ///
+    /// The sequence number and the bytes to send, or `WindowFull` if the
+    /// message would be `window_size` or more sequence numbers ahead of the
+    /// oldest message still waiting for an acknowledgment
+    pub fn send<T: Serialize>(&mut self, kind: MessageKind, payload: T, now_ms: u32) -> Result<(u16, &[u8]), ProtocolError> {
+        self.enqueue(kind, payload, None, now_ms)
+    }
+
+    /// Like `send`, but seals the message with `session` before it is kept
+    /// for retransmission
+    ///
+    /// Use this for protected message kinds once a session is running.
+    pub fn send_sealed<T: Serialize>(
+        &mut self,
+        kind: MessageKind,
+        payload: T,
+        session: &mut Session,
+        now_ms: u32,
+    ) -> Result<(u16, &[u8]), ProtocolError> {
+        self.enqueue(kind, payload, Some(session), now_ms)
+    }
+
+    /// Serializes a message with the next sequence number, optionally seals
+    /// it and keeps it for retransmission
+    fn enqueue<T: Serialize>(
+        &mut self,
+        kind: MessageKind,
+        payload: T,
+        session: Option<&mut Session>,
+        now_ms: u32,
+    ) -> Result<(u16, &[u8]), ProtocolError> {
+        if kind == MessageKind::Ack {
+            return Err(ProtocolError::InvalidFormat);
+        }
+        // The window covers a range of sequence numbers starting at the oldest
+        // unacknowledged message, so that the peer can tell retransmissions
+        // of it from duplicates
+        let next_sequence = self.next_sequence;
+        let window_size = self.config.window_size as u16;
+        let window_full = self.pending.iter().flatten().any(|pending| {
+            next_sequence.wrapping_sub(pending.sequence) >= window_size
+        });
+        if window_full {
+            return Err(ProtocolError::WindowFull);
+        }
+        let slot = self.pending.iter().position(Option::is_none).ok_or(ProtocolError::WindowFull)?;
+
+        let sequence = self.next_sequence;
+        let mut bytes = [0u8; MAX_MESSAGE_SIZE];
+        let message = Message::with_version(self.version, kind, sequence, payload)?;
+        let mut length = message.serialize_into(&mut bytes)?;
+        if let Some(session) = session {
+            length = session.seal(&mut bytes, length)?;
+        }
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        let pending = self.pending[slot].insert(PendingMessage {
+            bytes,
+            length,
+            sequence,
+            sent_at_ms: now_ms,
+            retries: 0,
+        });
+        Ok((sequence, &pending.bytes[..pending.length]))
+    }
+
+    /// Processes a frame received from the peer
+    ///
+    /// Acknowledgments complete pending messages. Any other message is
+    /// acknowledged and reported as `Received::Message` the first time and as
+    /// `Received::Duplicate` when it is received again.
+    ///
+    /// Anyone who can inject frames can acknowledge messages or send new
+    /// ones; within a session use `receive_sealed` instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - A complete frame, e.g. from `StreamDecoder::next_frame`
+    pub fn receive<'a>(&mut self, frame: &'a [u8]) -> Result<Received<'a>, ProtocolError> {
+        let (header, _) = validate_frame(frame)?;
+
+        if header.kind == MessageKind::Ack {
+            return Ok(self.complete(&header));
+        }
+
+        let ack = AckFrame::new(header.version, header.sequence, None)?;
+        if !self.received.is_new(header.sequence, self.config.window_size) {
+            return Ok(Received::Duplicate { ack });
+        }
+        self.received.commit(header.sequence);
+        Ok(Received::Message { frame, ack })
+    }
+
+    /// Processes a frame received from a peer that seals its messages with `session`
+    ///
+    /// Like `receive`, but new messages and acknowledgments are opened with
+    /// `session` in place, and a sequence number is recorded as received
+    /// only once its message was authenticated. A forged frame is rejected
+    /// with the error of `Session::open` and leaves the link unchanged.
+    /// Acknowledgments sent back are sealed.
+    ///
+    /// Duplicates are detected before opening, as a retransmission repeats
+    /// the sealed bytes that the session already accepted. As the header of
+    /// a duplicate is not authenticated, sealed acknowledgments use the
+    /// version of the link (see `set_version`) rather than the received one,
+    /// so every acknowledgment of a message is identical.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - A complete sealed frame, e.g. copied from `StreamDecoder::next_frame`
+    /// * `session` - Session with the peer
+    ///
+    /// # Returns
+    ///
+    /// The same as `receive`, with the opened frame in `Received::Message`, or
+    /// `AuthenticationFailed` for a frame of a kind that is not sealed in a
+    /// session (see `is_protected`), which could not be told from a forged one
+    pub fn receive_sealed<'a>(&mut self, frame: &'a mut [u8], session: &mut Session) -> Result<Received<'a>, ProtocolError> {
+        let (header, _) = validate_frame(frame)?;
+        if !is_protected(header.kind) {
+            return Err(ProtocolError::AuthenticationFailed);
+        }
+
+        if header.kind == MessageKind::Ack {
+            session.open(frame)?;
+            return Ok(self.complete(&header));
+        }
+
+        if !self.received.is_new(header.sequence, self.config.window_size) {
+            let ack = AckFrame::new(self.version, header.sequence, Some(session))?;
+            return Ok(Received::Duplicate { ack });
+        }
+
+        let length = session.open(frame)?;
+        self.received.commit(header.sequence);
+        let ack = AckFrame::new(self.version, header.sequence, Some(session))?;
+        Ok(Received::Message { frame: &frame[..length], ack })
+    }
+
+    /// Completes the pending message acknowledged by `header`
+    fn complete(&mut self, header: &MessageHeader) -> Received<'static> {
+        let slot = self.pending.iter_mut().find(|slot| {
+            slot.as_ref().is_some_and(|pending| pending.sequence == header.sequence)
+        });
+        let pending = match slot {
+            Some(slot) => slot.take().is_some(),
+            None => false,
+        };
+        Received::Ack { sequence: header.sequence, pending }
+    }
+
+    /// Checks pending messages for expired acknowledgment timeouts
+    ///
+    /// Call this regularly and until it returns `None`; each call reports at
+    /// most one event.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_ms` - Current time in milliseconds
+    pub fn poll(&mut self, now_ms: u32) -> Option<LinkEvent<'_>> {
+        let config = self.config;
+        let slot = self.pending.iter_mut().find(|slot| {
+            slot.as_ref().is_some_and(|pending| now_ms.wrapping_sub(pending.sent_at_ms) >= config.ack_timeout_ms)
+        })?;
+
+        if slot.as_ref().is_some_and(|pending| pending.retries >= config.max_retries) {
+            let sequence = slot.take()?.sequence;
+            return Some(LinkEvent::Failed { sequence });
+        }
+        let pending = slot.as_mut()?;
+        pending.retries += 1;
+        pending.sent_at_ms = now_ms;
+        Some(LinkEvent::Retransmit(&pending.bytes[..pending.length]))
+    }
+
+    /// Returns the number of messages waiting for an acknowledgment
+    pub fn pending_count(&self) -> usize {
+        self.pending.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Drops all pending messages and forgets the received sequence numbers
+    ///
+    /// Use this when the peer restarts, as it then starts again with sequence
+    /// number 0.
+    pub fn reset(&mut self) {
+        self.pending = [None; MAX_WINDOW_SIZE];
+        self.received = ReceiveWindow::default();
+        self.next_sequence = 0;
+    }
+}
+
+impl Default for ReliableLink {
+    fn default() -> Self {
+        ReliableLink::new(ReliabilityConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::protocol::write_envelope;
+    use crate::session::{BondKey, Role, KEY_SIZE, NONCE_SIZE};
+
+    /// Creates both sides of a session with a shared bond key
+    fn sessions() -> (Session, Session) {
+        let bond = BondKey::from_bytes([5; KEY_SIZE]);
+        let initiator = Session::new(Role::Initiator, &bond, [6; NONCE_SIZE], [7; NONCE_SIZE]);
+        let responder = Session::new(Role::Responder, &bond, [7; NONCE_SIZE], [6; NONCE_SIZE]);
+        (initiator, responder)
+    }
+
+    /// Serializes a plain command with the given sequence number
+    fn command(sequence: u16) -> ([u8; MAX_MESSAGE_SIZE], usize) {
+        let (length, bytes) = Message::new(MessageKind::Command, sequence, Command::GetBatteryStatus)
+            .unwrap()
+            .serialize()
+            .unwrap();
+        (bytes, length)
+    }
+
+    /// Returns true if `received` delivers a new message
+    fn is_message(received: Result<Received, ProtocolError>) -> bool {
+        matches!(received, Ok(Received::Message { .. }))
+    }
+
+    /// Returns true if `received` reports a duplicate
+    fn is_duplicate(received: Result<Received, ProtocolError>) -> bool {
+        matches!(received, Ok(Received::Duplicate { .. }))
+    }
+
+    #[test]
+    fn acknowledgments_complete_pending_messages() {
+        let mut sender = ReliableLink::default();
+        let mut receiver = ReliableLink::default();
+        let (sequence, bytes) = sender.send(MessageKind::Command, Command::GetBatteryStatus, 0).unwrap();
+        assert_eq!(sequence, 0);
+        let bytes = bytes.to_vec();
+
+        let ack = match receiver.receive(&bytes) {
+            Ok(Received::Message { frame, ack }) => {
+                assert_eq!(frame, &bytes[..]);
+                ack
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(sender.receive(ack.as_bytes()), Ok(Received::Ack { sequence: 0, pending: true }));
+        assert_eq!(sender.pending_count(), 0);
+        assert_eq!(sender.receive(ack.as_bytes()), Ok(Received::Ack { sequence: 0, pending: false }));
+    }
+
+    #[test]
+    fn duplicates_are_acknowledged_but_not_delivered() {
+        let mut receiver = ReliableLink::default();
+        let (bytes, length) = command(7);
+        assert!(is_message(receiver.receive(&bytes[..length])));
+        match receiver.receive(&bytes[..length]) {
+            Ok(Received::Duplicate { ack }) => {
+                let header = validate_frame(ack.as_bytes()).unwrap().0;
+                assert_eq!((header.kind, header.sequence), (MessageKind::Ack, 7));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sequence_numbers_behind_the_window_count_as_duplicates() {
+        let mut receiver = ReliableLink::default();
+        let window_size = receiver.config().window_size as u16;
+        let (bytes, length) = command(10);
+        assert!(is_message(receiver.receive(&bytes[..length])));
+
+        // Within the window, messages may arrive out of order
+        let (bytes, length) = command(10 - (window_size - 1));
+        assert!(is_message(receiver.receive(&bytes[..length])));
+        assert!(is_duplicate(receiver.receive(&bytes[..length])));
+
+        let (bytes, length) = command(10 - window_size);
+        assert!(is_duplicate(receiver.receive(&bytes[..length])));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let mut receiver = ReliableLink::default();
+        for sequence in [0xFFFE, 0xFFFF, 0x0000, 0x0001] {
+            let (bytes, length) = command(sequence);
+            assert!(is_message(receiver.receive(&bytes[..length])));
+        }
+        let (bytes, length) = command(0xFFFF);
+        assert!(is_duplicate(receiver.receive(&bytes[..length])));
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_retransmitted_and_given_up() {
+        let mut sender = ReliableLink::default();
+        let config = *sender.config();
+        let bytes = sender.send(MessageKind::Command, Command::GetBatteryStatus, 0).unwrap().1.to_vec();
+
+        assert_eq!(sender.poll(config.ack_timeout_ms - 1), None);
+        let mut now_ms = 0;
+        for _ in 0..config.max_retries {
+            now_ms += config.ack_timeout_ms;
+            assert_eq!(sender.poll(now_ms), Some(LinkEvent::Retransmit(&bytes)));
+            assert_eq!(sender.poll(now_ms), None);
+        }
+        now_ms += config.ack_timeout_ms;
+        assert_eq!(sender.poll(now_ms), Some(LinkEvent::Failed { sequence: 0 }));
+        assert_eq!(sender.pending_count(), 0);
+    }
+
+    #[test]
+    fn sealed_messages_and_acknowledgments_are_authenticated() {
+        let (mut initiator, mut responder) = sessions();
+        let mut sender = ReliableLink::default();
+        let mut receiver = ReliableLink::default();
+        let mut frame = [0u8; MAX_MESSAGE_SIZE];
+        let sealed = sender
+            .send_sealed(MessageKind::Command, Command::FactoryReset, &mut initiator, 0)
+            .unwrap()
+            .1
+            .to_vec();
+
+        frame[..sealed.len()].copy_from_slice(&sealed);
+        let ack = match receiver.receive_sealed(&mut frame[..sealed.len()], &mut responder) {
+            Ok(Received::Message { frame, ack }) => {
+                let message = Message::<Command>::try_from(frame).unwrap();
+                assert!(matches!(message.payload, Command::FactoryReset));
+                ack
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+
+        // A retransmission is recognized before opening and acknowledged again
+        frame[..sealed.len()].copy_from_slice(&sealed);
+        assert_eq!(receiver.receive_sealed(&mut frame[..sealed.len()], &mut responder), Ok(Received::Duplicate { ack }));
+
+        // A plain acknowledgment does not complete a sealed message
+        let plain_ack = AckFrame::new(PROTOCOL_VERSION, 0, None).unwrap();
+        let mut ack_frame = plain_ack.bytes;
+        assert_eq!(
+            sender.receive_sealed(&mut ack_frame[..plain_ack.length], &mut initiator),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+        assert_eq!(sender.pending_count(), 1);
+
+        let mut ack_frame = ack.bytes;
+        assert_eq!(
+            sender.receive_sealed(&mut ack_frame[..ack.length], &mut initiator),
+            Ok(Received::Ack { sequence: 0, pending: true })
+        );
+        assert_eq!(sender.pending_count(), 0);
+    }
+
+    #[test]
+    fn acknowledgments_of_duplicates_ignore_the_received_version() {
+        let (mut initiator, mut responder) = sessions();
+        let mut sender = ReliableLink::default();
+        let mut receiver = ReliableLink::default();
+        let sealed = sender
+            .send_sealed(MessageKind::Command, Command::GetBatteryStatus, &mut initiator, 0)
+            .unwrap()
+            .1
+            .to_vec();
+        let mut frame = [0u8; MAX_MESSAGE_SIZE];
+        frame[..sealed.len()].copy_from_slice(&sealed);
+        let ack = match receiver.receive_sealed(&mut frame[..sealed.len()], &mut responder) {
+            Ok(Received::Message { ack, .. }) => ack,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        // A replay re-encoded with another version and a matching checksum
+        let header = validate_frame(&sealed).unwrap().0;
+        let payload = &sealed[header.size()..header.size() + header.payload_length as usize];
+        let replayed = MessageHeader::with_version(1, header.kind, header.sequence, header.payload_length);
+        let mut forged = [0u8; MAX_MESSAGE_SIZE];
+        forged[replayed.size()..replayed.size() + payload.len()].copy_from_slice(payload);
+        let length = write_envelope(&mut forged, &replayed).unwrap();
+        assert_eq!(receiver.receive_sealed(&mut forged[..length], &mut responder), Ok(Received::Duplicate { ack }));
+
+        frame[..sealed.len()].copy_from_slice(&sealed);
+        assert_eq!(receiver.receive_sealed(&mut frame[..sealed.len()], &mut responder), Ok(Received::Duplicate { ack }));
+    }
+
+    #[test]
+    fn forged_frames_do_not_advance_the_receive_window() {
+        let (mut initiator, mut responder) = sessions();
+        let mut forger = Session::new(Role::Initiator, &BondKey::from_bytes([9; KEY_SIZE]), [6; NONCE_SIZE], [7; NONCE_SIZE]);
+        let mut receiver = ReliableLink::default();
+
+        // A frame sealed with another key, far ahead in sequence space
+        let message = Message::new(MessageKind::Command, 100, Command::FactoryReset).unwrap();
+        let mut frame = [0u8; MAX_MESSAGE_SIZE];
+        let length = forger.seal_message(&message, &mut frame).unwrap();
+        assert_eq!(
+            receiver.receive_sealed(&mut frame[..length], &mut responder),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+
+        // Plain messages cannot be told from forged ones within a session
+        let (mut plain, length) = command(0);
+        assert_eq!(
+            receiver.receive_sealed(&mut plain[..length], &mut responder),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+
+        let message = Message::new(MessageKind::Command, 0, Command::GetBatteryStatus).unwrap();
+        let length = initiator.seal_message(&message, &mut frame).unwrap();
+        assert!(is_message(receiver.receive_sealed(&mut frame[..length], &mut responder)));
+    }
+}
@@ -2,9 +2,21 @@
 //!
 //! This module defines the structures and functionality for working with
 //! various sensors in a dive computer system, including sensor identification,
-//! configuration, and data handling.
+//! configuration, and data handling. Depth is derived from absolute pressure
+//! readings with a calibrated pressure model.
 
 use serde::{Serialize, Deserialize};
+use crate::dive_calc::{PressureModel, WaterDensity};
+use crate::real;
+
+/// Error types for sensor data handling
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SensorError {
+    /// The reading is not of the expected type
+    WrongReadingType,
+    /// The reading value is outside the valid range
+    OutOfRange,
+}
 
 /// Represents a physical sensor device connected to the dive computer
 ///
@@ -34,6 +46,16 @@ impl Sensor {
             name,
         }
     }
+
+    /// Returns the name without the zero padding
+    ///
+    /// # Returns
+    ///
+    /// The name, or `None` if it is not valid UTF-8
+    pub fn name_str(&self) -> Option<&str> {
+        let length = self.name.iter().position(|&byte| byte == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..length]).ok()
+    }
 }
 
 /// Represents a response from a sensor containing a reading value
@@ -89,3 +111,140 @@ pub enum ReadingType {
     /// Battery level in percentage (0-100)
     Battery,
 }
+
+/// Converts absolute pressure readings into depth readings
+///
+/// The converter holds the surface pressure captured at calibration time and
+/// the configured water density, so depth is derived from the pressure sensor
+/// instead of being provided by a separate sensor.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DepthConverter {
+    /// Surface pressure and water density used for the conversion
+    pub pressure: PressureModel,
+}
+
+impl DepthConverter {
+    /// Creates a new depth converter with the given pressure model
+    pub fn new(pressure: PressureModel) -> Self {
+        DepthConverter { pressure }
+    }
+
+    /// Captures the surface pressure from a pressure reading taken at the surface
+    ///
+    /// This should be called when sensors are calibrated or a dive is started.
+    ///
+    /// # Arguments
+    ///
+    /// * `reading` - A `ReadingType::Pressure` reading in millibars
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the surface pressure was updated, or a `SensorError`
+    pub fn calibrate_surface(&mut self, reading: &SensorResponse) -> Result<(), SensorError> {
+        if reading.reading_type != ReadingType::Pressure {
+            return Err(SensorError::WrongReadingType);
+        }
+        if reading.value <= 0 || reading.value > u16::MAX as i32 {
+            return Err(SensorError::OutOfRange);
+        }
+        self.pressure.surface_pressure_mbar = reading.value as u16;
+        Ok(())
+    }
+
+    /// Sets the water density used for the conversion
+    pub fn set_water_density(&mut self, water: WaterDensity) {
+        self.pressure.water = water;
+    }
+
+    /// Converts an absolute pressure in millibars to a depth in centimeters
+    ///
+    /// The depth is rounded to the nearest centimeter; pressures at or below
+    /// the surface pressure return a depth of 0.
+    pub fn depth_cm(&self, pressure_mbar: i32) -> u16 {
+        let gauge_bar = real::ratio(pressure_mbar.saturating_sub(self.pressure.surface_pressure_mbar as i32), 1000);
+        real::round_u16(gauge_bar / self.pressure.bar_per_meter() * real::from_int(100))
+    }
+
+    /// Converts a pressure reading into a depth reading
+    ///
+    /// The returned reading keeps the sensor ID and timestamp of the pressure
+    /// reading and carries the depth in centimeters.
+    ///
+    /// # Arguments
+    ///
+    /// * `reading` - A `ReadingType::Pressure` reading in millibars
+    ///
+    /// # Returns
+    ///
+    /// A `ReadingType::Depth` reading, or a `SensorError`
+    pub fn convert(&self, reading: &SensorResponse) -> Result<SensorResponse, SensorError> {
+        if reading.reading_type != ReadingType::Pressure {
+            return Err(SensorError::WrongReadingType);
+        }
+        Ok(SensorResponse::new(
+            reading.sensor_id,
+            ReadingType::Depth,
+            self.depth_cm(reading.value) as i32,
+            reading.timestamp,
+        ))
+    }
+}
+
+impl Default for DepthConverter {
+    fn default() -> Self {
+        DepthConverter::new(PressureModel::STANDARD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a pressure reading of `mbar` from sensor 3
+    fn pressure_reading(mbar: i32) -> SensorResponse {
+        SensorResponse::new(3, ReadingType::Pressure, mbar, 1234567890)
+    }
+
+    #[test]
+    fn pressure_is_converted_to_depth() {
+        let converter = DepthConverter::new(PressureModel::new(1013, WaterDensity::Salt));
+        // 1.517 bar of sea water at 0.1005 bar per meter
+        assert_eq!(converter.depth_cm(2530), 1509);
+        let depth = converter.convert(&pressure_reading(2530)).unwrap();
+        assert_eq!(depth, SensorResponse::new(3, ReadingType::Depth, 1509, 1234567890));
+
+        let mut converter = converter;
+        converter.set_water_density(WaterDensity::Fresh);
+        assert_eq!(converter.depth_cm(2530), 1547);
+    }
+
+    #[test]
+    fn surface_calibration_moves_the_zero_point() {
+        let mut converter = DepthConverter::new(PressureModel::new(1013, WaterDensity::Salt));
+        converter.calibrate_surface(&pressure_reading(1008)).unwrap();
+        assert_eq!(converter.pressure.surface_pressure_mbar, 1008);
+        assert_eq!(converter.depth_cm(1008), 0);
+        assert_eq!(converter.depth_cm(2530), 1514);
+    }
+
+    #[test]
+    fn pressure_below_the_surface_pressure_is_at_the_surface() {
+        let converter = DepthConverter::default();
+        assert_eq!(converter.depth_cm(1000), 0);
+        assert_eq!(converter.depth_cm(0), 0);
+        assert_eq!(converter.depth_cm(i32::MIN), 0);
+    }
+
+    #[test]
+    fn invalid_readings_are_rejected() {
+        let mut converter = DepthConverter::default();
+        let temperature = SensorResponse::new(2, ReadingType::Temperature, 215, 0);
+        assert_eq!(converter.convert(&temperature), Err(SensorError::WrongReadingType));
+        assert_eq!(converter.calibrate_surface(&temperature), Err(SensorError::WrongReadingType));
+        assert_eq!(converter.calibrate_surface(&pressure_reading(0)), Err(SensorError::OutOfRange));
+        assert_eq!(converter.calibrate_surface(&pressure_reading(-1013)), Err(SensorError::OutOfRange));
+        assert_eq!(converter.calibrate_surface(&pressure_reading(70000)), Err(SensorError::OutOfRange));
+        // Rejected readings leave the calibration unchanged
+        assert_eq!(converter, DepthConverter::default());
+    }
+}
@@ -0,0 +1,716 @@
+//! Authenticated and encrypted sessions
+//!
+//! This module protects the command channel against eavesdropping, forged
+//! commands and replayed messages. It has two parts:
+//!
+//! * [`Pairing`] runs once per pair of devices. Both sides exchange X25519
+//!   public keys and nonces in `MessageKind::Security` messages and derive a
+//!   long-term [`BondKey`]. Both show a six-digit verification code, which the
+//!   user compares before the bond is stored; the responder commits to its
+//!   nonce before it learns the initiator's, so an attacker in the middle
+//!   cannot make the codes match.
+//! * [`Session`] runs on every connection. Both sides exchange fresh nonces,
+//!   derive one ChaCha20-Poly1305 key per direction from the bond key, and
+//!   seal every protected message. The header stays readable and is
+//!   authenticated together with the encrypted payload; the AEAD nonce is
+//!   the header sequence number extended to 64 bits, so each sequence number
+//!   is accepted only once. Acknowledgments are sealed too, with an empty
+//!   payload, so that only the peer can acknowledge a message.
+//!
+//! The library has no random number generator; the caller provides the
+//! secret key and nonces, which must come from a cryptographically secure
+//! source.
+
+use serde::{Serialize, Deserialize};
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+use crate::commands::Privilege;
+use crate::protocol::{validate_frame, write_envelope, Message, MessageHeader, MessageKind, ProtocolError};
+
+/// Size of keys in bytes
+pub const KEY_SIZE: usize = 32;
+
+/// Size of the nonces exchanged during pairing and session start in bytes
+pub const NONCE_SIZE: usize = 16;
+
+/// Size of the authentication tag appended to sealed payloads in bytes
+pub const TAG_SIZE: usize = 16;
+
+/// Number of sequence numbers behind the highest one that can still be accepted
+pub const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// Context string for deriving bond keys
+const BOND_INFO: &[u8] = b"dive-computer-proto bond";
+
+/// Context string for deriving session keys
+const SESSION_INFO: &[u8] = b"dive-computer-proto session";
+
+/// Side of a pairing or session
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Role {
+    /// The side that starts pairing and sessions, usually the app
+    Initiator,
+    /// The side that answers, usually the dive computer
+    Responder,
+}
+
+/// Payload of `MessageKind::Security` messages
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SecurityMessage {
+    /// Starts pairing with the initiator's public key
+    PairingRequest {
+        /// X25519 public key of the initiator
+        public_key: [u8; KEY_SIZE],
+    },
+    /// Answers a pairing request
+    PairingResponse {
+        /// X25519 public key of the responder
+        public_key: [u8; KEY_SIZE],
+        /// SHA-256 of the responder's public key, the initiator's public key
+        /// and the responder's nonce
+        commitment: [u8; 32],
+    },
+    /// Pairing nonce; the initiator sends its own first
+    PairingNonce {
+        /// Random nonce of the sender
+        nonce: [u8; NONCE_SIZE],
+    },
+    /// Starts a session with a bonded peer; the responder answers with its own
+    SessionStart {
+        /// Random nonce of the sender
+        nonce: [u8; NONCE_SIZE],
+    },
+}
+
+/// Long-term key shared by two paired devices
+///
+/// Store it in non-volatile memory on both sides to start sessions later
+/// without pairing again.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct BondKey([u8; KEY_SIZE]);
+
+impl BondKey {
+    /// Restores a stored bond key
+    pub const fn from_bytes(bytes: [u8; KEY_SIZE]) -> Self {
+        BondKey(bytes)
+    }
+
+    /// Returns the key bytes for storage
+    pub const fn to_bytes(&self) -> [u8; KEY_SIZE] {
+        self.0
+    }
+}
+
+/// Pairing procedure that establishes a [`BondKey`]
+///
+/// The messages are exchanged in this order:
+///
+/// 1. Initiator: `PairingRequest` from [`Pairing::start`]
+/// 2. Responder: `PairingResponse`
+/// 3. Initiator: `PairingNonce`
+/// 4. Responder: `PairingNonce`
+///
+/// Pass every received message to [`Pairing::handle`] and send the returned
+/// reply. Afterwards both sides show [`Pairing::verification_code`]; only if
+/// the user confirms that the codes match, call [`Pairing::finish`].
+pub struct Pairing {
+    /// Side of the pairing
+    role: Role,
+    /// X25519 secret key
+    secret: [u8; KEY_SIZE],
+    /// X25519 public key
+    public_key: [u8; KEY_SIZE],
+    /// Own nonce
+    nonce: [u8; NONCE_SIZE],
+    /// Public key of the peer, once received
+    peer_public_key: Option<[u8; KEY_SIZE]>,
+    /// Commitment of the responder, once received by the initiator
+    commitment: Option<[u8; 32]>,
+    /// Nonce of the peer, once received
+    peer_nonce: Option<[u8; NONCE_SIZE]>,
+}
+
+impl Pairing {
+    /// Creates a pairing procedure
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - Side of the pairing
+    /// * `secret` - Random X25519 secret key, used for this pairing only
+    /// * `nonce` - Random nonce, used for this pairing only
+    pub fn new(role: Role, secret: [u8; KEY_SIZE], nonce: [u8; NONCE_SIZE]) -> Self {
+        Pairing {
+            role,
+            secret,
+            public_key: x25519(secret, X25519_BASEPOINT_BYTES),
+            nonce,
+            peer_public_key: None,
+            commitment: None,
+            peer_nonce: None,
+        }
+    }
+
+    /// Returns the first message of the initiator
+    ///
+    /// Returns `InvalidFormat` on the responder, which waits for the request.
+    pub fn start(&self) -> Result<SecurityMessage, ProtocolError> {
+        match self.role {
+            Role::Initiator => Ok(SecurityMessage::PairingRequest { public_key: self.public_key }),
+            Role::Responder => Err(ProtocolError::InvalidFormat),
+        }
+    }
+
+    /// Processes a message received from the peer
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(reply))` with the message to send back
+    /// * `Ok(None)` once the initiator has received the last message
+    /// * `Err(AuthenticationFailed)` if the responder's nonce does not match
+    ///   its commitment
+    /// * `Err(InvalidFormat)` for a message that is not expected at this point
+    pub fn handle(&mut self, message: &SecurityMessage) -> Result<Option<SecurityMessage>, ProtocolError> {
+        match (self.role, *message) {
+            (Role::Responder, SecurityMessage::PairingRequest { public_key }) if self.peer_public_key.is_none() => {
+                self.peer_public_key = Some(public_key);
+                Ok(Some(SecurityMessage::PairingResponse {
+                    public_key: self.public_key,
+                    commitment: commitment(&self.public_key, &public_key, &self.nonce),
+                }))
+            }
+            (Role::Initiator, SecurityMessage::PairingResponse { public_key, commitment })
+                if self.peer_public_key.is_none() =>
+            {
+                self.peer_public_key = Some(public_key);
+                self.commitment = Some(commitment);
+                Ok(Some(SecurityMessage::PairingNonce { nonce: self.nonce }))
+            }
+            (Role::Responder, SecurityMessage::PairingNonce { nonce })
+                if self.peer_public_key.is_some() && self.peer_nonce.is_none() =>
+            {
+                self.peer_nonce = Some(nonce);
+                Ok(Some(SecurityMessage::PairingNonce { nonce: self.nonce }))
+            }
+            (Role::Initiator, SecurityMessage::PairingNonce { nonce }) if self.peer_nonce.is_none() => {
+                let (peer_public_key, expected) = match (self.peer_public_key, self.commitment) {
+                    (Some(peer_public_key), Some(expected)) => (peer_public_key, expected),
+                    _ => return Err(ProtocolError::InvalidFormat),
+                };
+                if commitment(&peer_public_key, &self.public_key, &nonce) != expected {
+                    return Err(ProtocolError::AuthenticationFailed);
+                }
+                self.peer_nonce = Some(nonce);
+                Ok(None)
+            }
+            _ => Err(ProtocolError::InvalidFormat),
+        }
+    }
+
+    /// Returns the six-digit code to show to the user, once all messages
+    /// have been exchanged
+    pub fn verification_code(&self) -> Option<u32> {
+        let transcript = self.transcript()?;
+        let digest = Sha256::new()
+            .chain_update(transcript.initiator_key)
+            .chain_update(transcript.responder_key)
+            .chain_update(transcript.initiator_nonce)
+            .chain_update(transcript.responder_nonce)
+            .finalize();
+        Some(u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000)
+    }
+
+    /// Derives the bond key after the user confirmed the verification code
+    ///
+    /// Returns `InvalidFormat` if pairing is not complete and
+    /// `AuthenticationFailed` if the peer sent an invalid public key.
+    pub fn finish(&self) -> Result<BondKey, ProtocolError> {
+        let transcript = self.transcript().ok_or(ProtocolError::InvalidFormat)?;
+        let peer_public_key = self.peer_public_key.ok_or(ProtocolError::InvalidFormat)?;
+
+        let shared = x25519(self.secret, peer_public_key);
+        // Low-order points give an all-zero shared secret
+        if shared == [0u8; KEY_SIZE] {
+            return Err(ProtocolError::AuthenticationFailed);
+        }
+
+        let mut salt = [0u8; 2 * NONCE_SIZE];
+        salt[..NONCE_SIZE].copy_from_slice(&transcript.initiator_nonce);
+        salt[NONCE_SIZE..].copy_from_slice(&transcript.responder_nonce);
+        let mut key = [0u8; KEY_SIZE];
+        Hkdf::<Sha256>::new(Some(&salt), &shared)
+            .expand_multi_info(&[BOND_INFO, &transcript.initiator_key, &transcript.responder_key], &mut key)
+            .map_err(|_| ProtocolError::InvalidFormat)?;
+        Ok(BondKey(key))
+    }
+
+    /// Returns the exchanged public keys and nonces once pairing is complete
+    fn transcript(&self) -> Option<Transcript> {
+        let peer_public_key = self.peer_public_key?;
+        let peer_nonce = self.peer_nonce?;
+        Some(match self.role {
+            Role::Initiator => Transcript {
+                initiator_key: self.public_key,
+                responder_key: peer_public_key,
+                initiator_nonce: self.nonce,
+                responder_nonce: peer_nonce,
+            },
+            Role::Responder => Transcript {
+                initiator_key: peer_public_key,
+                responder_key: self.public_key,
+                initiator_nonce: peer_nonce,
+                responder_nonce: self.nonce,
+            },
+        })
+    }
+}
+
+/// Public keys and nonces exchanged during pairing
+struct Transcript {
+    /// Public key of the initiator
+    initiator_key: [u8; KEY_SIZE],
+    /// Public key of the responder
+    responder_key: [u8; KEY_SIZE],
+    /// Nonce of the initiator
+    initiator_nonce: [u8; NONCE_SIZE],
+    /// Nonce of the responder
+    responder_nonce: [u8; NONCE_SIZE],
+}
+
+/// Returns the responder's commitment to its nonce
+fn commitment(responder_key: &[u8; KEY_SIZE], initiator_key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(responder_key)
+        .chain_update(initiator_key)
+        .chain_update(nonce)
+        .finalize()
+        .into()
+}
+
+/// Returns true if messages of this kind are sealed once a session is running
+///
+/// Handshake and security messages stay in plain text, as they are needed to
+/// set up the session. Acknowledgments are sealed with an empty payload.
+pub fn is_protected(kind: MessageKind) -> bool {
+    !matches!(kind, MessageKind::Handshake | MessageKind::Security)
+}
+
+/// Sequence numbers recently accepted from the peer
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplayWindow {
+    /// Highest counter accepted, or `None` before the first message
+    highest: Option<u64>,
+    /// Bit `n` is set if counter `highest - n` was accepted
+    accepted: u64,
+}
+
+impl ReplayWindow {
+    /// Extends a header sequence number to the counter closest to the highest one
+    ///
+    /// Returns `ReplayDetected` for counters that were already accepted or are
+    /// too far behind.
+    fn check(&self, sequence: u16) -> Result<u64, ProtocolError> {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => return Ok(sequence as u64),
+        };
+        let counter = self.extend(sequence).ok_or(ProtocolError::ReplayDetected)?;
+        if counter > highest {
+            return Ok(counter);
+        }
+        let behind = highest - counter;
+        if behind >= REPLAY_WINDOW_SIZE || self.accepted & (1 << behind) != 0 {
+            return Err(ProtocolError::ReplayDetected);
+        }
+        Ok(counter)
+    }
+
+    /// Extends a header sequence number to the counter closest to the highest
+    /// one, without checking whether it was accepted
+    ///
+    /// Returns `None` before the first message and for counters below 0.
+    fn extend(&self, sequence: u16) -> Option<u64> {
+        let highest = self.highest?;
+        let delta = sequence.wrapping_sub(highest as u16) as i16 as i64;
+        u64::try_from(highest as i64 + delta).ok()
+    }
+
+    /// Records an authenticated counter
+    fn accept(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => self.accepted |= 1 << (highest - counter),
+            Some(highest) => {
+                let ahead = counter - highest;
+                self.accepted = if ahead >= REPLAY_WINDOW_SIZE { 0 } else { self.accepted << ahead };
+                self.accepted |= 1;
+                self.highest = Some(counter);
+            }
+            None => {
+                self.accepted = 1;
+                self.highest = Some(counter);
+            }
+        }
+    }
+}
+
+/// Encrypted session with a bonded peer
+///
+/// Both sides exchange `SessionStart` messages with fresh nonces and create
+/// the session from their own and the peer's nonce. Protected messages (see
+/// [`is_protected`]) are then sent sealed and opened on receipt; a message
+/// that fails to open must be discarded.
+///
+/// A session authorizes commands up to `Privilege::Configuration`. Grant
+/// `Privilege::Destructive` or `Privilege::Firmware` with [`Session::grant`]
+/// only after the user confirmed the operation on the device.
+///
+/// Sequence numbers of sealed messages must increase by less than 32768 from
+/// one message to the next. Retransmissions reuse the sealed bytes, so the
+/// receiver must drop duplicates before opening, e.g. with
+/// `ReliableLink::receive_sealed`.
+///
+/// An acknowledgment is sealed with the counter of the message it
+/// acknowledges, in a nonce space of its own, so it can only be sealed after
+/// that message was opened and only be opened for a message sealed before.
+pub struct Session {
+    /// Cipher for messages to the peer
+    send_cipher: ChaCha20Poly1305,
+    /// Cipher for messages from the peer
+    receive_cipher: ChaCha20Poly1305,
+    /// Counter of the last sealed message
+    last_sent: Option<u64>,
+    /// Counters accepted from the peer
+    received: ReplayWindow,
+    /// Privilege granted to commands received in this session
+    privilege: Privilege,
+}
+
+impl Session {
+    /// Creates a session after the `SessionStart` exchange
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - Side of the session
+    /// * `bond` - Key established by pairing
+    /// * `nonce` - Random nonce sent in the own `SessionStart`, used once
+    /// * `peer_nonce` - Nonce received in the peer's `SessionStart`
+    pub fn new(role: Role, bond: &BondKey, nonce: [u8; NONCE_SIZE], peer_nonce: [u8; NONCE_SIZE]) -> Self {
+        let (initiator_nonce, responder_nonce) = match role {
+            Role::Initiator => (nonce, peer_nonce),
+            Role::Responder => (peer_nonce, nonce),
+        };
+        let mut salt = [0u8; 2 * NONCE_SIZE];
+        salt[..NONCE_SIZE].copy_from_slice(&initiator_nonce);
+        salt[NONCE_SIZE..].copy_from_slice(&responder_nonce);
+
+        // One key per direction: initiator to responder, then responder to initiator
+        let mut keys = [0u8; 2 * KEY_SIZE];
+        // 64 bytes is always a valid HKDF-SHA256 output length
+        let _ = Hkdf::<Sha256>::new(Some(&salt), &bond.0).expand(SESSION_INFO, &mut keys);
+        let initiator_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys[..KEY_SIZE]));
+        let responder_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys[KEY_SIZE..]));
+        let (send_cipher, receive_cipher) = match role {
+            Role::Initiator => (initiator_cipher, responder_cipher),
+            Role::Responder => (responder_cipher, initiator_cipher),
+        };
+
+        Session {
+            send_cipher,
+            receive_cipher,
+            last_sent: None,
+            received: ReplayWindow::default(),
+            privilege: Privilege::Configuration,
+        }
+    }
+
+    /// Returns the privilege granted to commands received in this session
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    /// Sets the privilege granted to commands received in this session
+    pub fn grant(&mut self, privilege: Privilege) {
+        self.privilege = privilege;
+    }
+
+    /// Serializes and seals a message
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - Message to send
+    /// * `buffer` - Output buffer; needs `TAG_SIZE` bytes more than the plain
+    ///   message, and one more for version 1 messages whose length varint grows
+    ///
+    /// # Returns
+    ///
+    /// The length of the sealed message
+    pub fn seal_message<T: Serialize>(&mut self, message: &Message<T>, buffer: &mut [u8]) -> Result<usize, ProtocolError> {
+        let length = message.serialize_into(buffer)?;
+        self.seal(buffer, length)
+    }
+
+    /// Seals a serialized message in place
+    ///
+    /// The payload is encrypted, the authentication tag appended, and the
+    /// header and checksum are updated for the longer payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Buffer holding the message, with room for `TAG_SIZE` more bytes
+    /// * `length` - Length of the message in `buffer`
+    ///
+    /// # Returns
+    ///
+    /// The length of the sealed message, `MessageTooLarge` if it does not fit
+    /// into `buffer` or `MAX_MESSAGE_SIZE`, `ReplayDetected` once the 64-bit
+    /// counter is exhausted, or `InvalidFormat` for an acknowledgment before
+    /// any message was opened
+    pub fn seal(&mut self, buffer: &mut [u8], length: usize) -> Result<usize, ProtocolError> {
+        let (header, _) = validate_frame(&buffer[..length])?;
+        let payload_length = header.payload_length as usize;
+        let sealed_length = u16::try_from(payload_length + TAG_SIZE).map_err(|_| ProtocolError::MessageTooLarge)?;
+        let sealed = MessageHeader::with_version(header.version, header.kind, header.sequence, sealed_length);
+        // Version 1 headers grow when the payload length needs a longer varint
+        let payload_start = sealed.size();
+        let payload_end = payload_start + payload_length;
+        if payload_end + TAG_SIZE + sealed.checksum_size() > buffer.len() {
+            return Err(ProtocolError::MessageTooLarge);
+        }
+
+        // Counters continue across wraps of the 16-bit sequence number
+        let counter = if header.kind == MessageKind::Ack {
+            self.received.extend(header.sequence).ok_or(ProtocolError::InvalidFormat)?
+        } else {
+            match self.last_sent {
+                None => header.sequence as u64,
+                Some(last) => {
+                    let counter = (last & !0xFFFF) | header.sequence as u64;
+                    if counter > last { counter } else { counter.checked_add(0x10000).ok_or(ProtocolError::ReplayDetected)? }
+                }
+            }
+        };
+
+        buffer.copy_within(header.size()..header.size() + payload_length, payload_start);
+        let associated_data = sealed.encode();
+        let tag = self
+            .send_cipher
+            .encrypt_in_place_detached(&nonce(&header, counter), &associated_data, &mut buffer[payload_start..payload_end])
+            .map_err(|_| ProtocolError::MessageTooLarge)?;
+        buffer[payload_end..payload_end + TAG_SIZE].copy_from_slice(&tag);
+        let length = write_envelope(buffer, &sealed)?;
+        if header.kind != MessageKind::Ack {
+            self.last_sent = Some(counter);
+        }
+        Ok(length)
+    }
+
+    /// Opens a sealed message in place
+    ///
+    /// On success the payload is decrypted and the header and checksum are
+    /// updated, so that the first bytes of `frame` hold a plain message that
+    /// `Message::try_from` decodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - A complete sealed message, e.g. copied from `StreamDecoder::next_frame`
+    ///
+    /// # Returns
+    ///
+    /// The length of the plain message, `ReplayDetected` if the sequence
+    /// number was already accepted or is too old, or `AuthenticationFailed`
+    /// if the message was forged, corrupted or sealed with a different key,
+    /// or is an acknowledgment for a message that was never sealed
+    pub fn open(&mut self, frame: &mut [u8]) -> Result<usize, ProtocolError> {
+        let (header, _) = validate_frame(frame)?;
+        let payload_length = (header.payload_length as usize)
+            .checked_sub(TAG_SIZE)
+            .ok_or(ProtocolError::AuthenticationFailed)?;
+        let payload_start = header.size();
+        let payload_end = payload_start + payload_length;
+        let counter = if header.kind == MessageKind::Ack {
+            self.acknowledged_counter(header.sequence)?
+        } else {
+            self.received.check(header.sequence)?
+        };
+
+        let associated_data = header.encode();
+        let tag = *Tag::from_slice(&frame[payload_end..payload_end + TAG_SIZE]);
+        self.receive_cipher
+            .decrypt_in_place_detached(&nonce(&header, counter), &associated_data, &mut frame[payload_start..payload_end], &tag)
+            .map_err(|_| ProtocolError::AuthenticationFailed)?;
+        // Acknowledgments may repeat, e.g. for retransmissions
+        if header.kind != MessageKind::Ack {
+            self.received.accept(counter);
+        }
+
+        let plain = MessageHeader::with_version(header.version, header.kind, header.sequence, payload_length as u16);
+        frame.copy_within(payload_start..payload_end, plain.size());
+        write_envelope(frame, &plain)
+    }
+
+    /// Extends the sequence number of an acknowledgment to the counter of the
+    /// latest sealed message with that sequence number
+    fn acknowledged_counter(&self, sequence: u16) -> Result<u64, ProtocolError> {
+        let last = self.last_sent.ok_or(ProtocolError::AuthenticationFailed)?;
+        let behind = (last as u16).wrapping_sub(sequence) as u64;
+        last.checked_sub(behind).ok_or(ProtocolError::AuthenticationFailed)
+    }
+}
+
+/// Builds the AEAD nonce for a message counter
+///
+/// Acknowledgments carry the counter of the acknowledged message, so they
+/// use a nonce space of their own, marked by the fourth byte. Their version
+/// is part of the nonce, so that acknowledgments of one message in different
+/// versions, and thus with different associated data, never share a nonce.
+fn nonce(header: &MessageHeader, counter: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    if header.kind == MessageKind::Ack {
+        nonce[2] = header.version;
+        nonce[3] = 1;
+    }
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::protocol::MAX_MESSAGE_SIZE;
+
+    /// Runs the pairing message exchange and returns both procedures
+    fn pair() -> (Pairing, Pairing) {
+        let mut initiator = Pairing::new(Role::Initiator, [1; KEY_SIZE], [2; NONCE_SIZE]);
+        let mut responder = Pairing::new(Role::Responder, [3; KEY_SIZE], [4; NONCE_SIZE]);
+        let request = initiator.start().unwrap();
+        let response = responder.handle(&request).unwrap().unwrap();
+        let initiator_nonce = initiator.handle(&response).unwrap().unwrap();
+        let responder_nonce = responder.handle(&initiator_nonce).unwrap().unwrap();
+        assert_eq!(initiator.handle(&responder_nonce), Ok(None));
+        (initiator, responder)
+    }
+
+    /// Creates both sides of a session with a shared bond key
+    fn sessions() -> (Session, Session) {
+        let bond = BondKey::from_bytes([5; KEY_SIZE]);
+        let initiator = Session::new(Role::Initiator, &bond, [6; NONCE_SIZE], [7; NONCE_SIZE]);
+        let responder = Session::new(Role::Responder, &bond, [7; NONCE_SIZE], [6; NONCE_SIZE]);
+        (initiator, responder)
+    }
+
+    /// Seals a command with the given sequence number
+    fn seal(session: &mut Session, version: u8, sequence: u16) -> ([u8; MAX_MESSAGE_SIZE], usize) {
+        let message = Message::with_version(version, MessageKind::Command, sequence, Command::SwitchGas { slot: 3 }).unwrap();
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let length = session.seal_message(&message, &mut buffer).unwrap();
+        (buffer, length)
+    }
+
+    /// Opens a sealed frame and returns the sequence number of the decoded command
+    fn open(session: &mut Session, sealed: &([u8; MAX_MESSAGE_SIZE], usize)) -> Result<u16, ProtocolError> {
+        let mut frame = sealed.0;
+        let length = session.open(&mut frame[..sealed.1])?;
+        let message = Message::<Command>::try_from(&frame[..length])?;
+        assert!(matches!(message.payload, Command::SwitchGas { slot: 3 }));
+        Ok(message.header.sequence)
+    }
+
+    #[test]
+    fn pairing_derives_the_same_bond_on_both_sides() {
+        let (initiator, responder) = pair();
+        assert!(initiator.verification_code().unwrap() < 1_000_000);
+        assert_eq!(initiator.verification_code(), responder.verification_code());
+        assert!(initiator.finish().unwrap() == responder.finish().unwrap());
+    }
+
+    #[test]
+    fn pairing_rejects_a_nonce_that_does_not_match_the_commitment() {
+        let mut initiator = Pairing::new(Role::Initiator, [1; KEY_SIZE], [2; NONCE_SIZE]);
+        let mut responder = Pairing::new(Role::Responder, [3; KEY_SIZE], [4; NONCE_SIZE]);
+        let response = responder.handle(&initiator.start().unwrap()).unwrap().unwrap();
+        initiator.handle(&response).unwrap();
+
+        let forged = SecurityMessage::PairingNonce { nonce: [5; NONCE_SIZE] };
+        assert_eq!(initiator.handle(&forged), Err(ProtocolError::AuthenticationFailed));
+        assert_eq!(initiator.verification_code(), None);
+        assert_eq!(initiator.finish().err(), Some(ProtocolError::InvalidFormat));
+    }
+
+    #[test]
+    fn pairing_rejects_messages_out_of_order() {
+        let mut responder = Pairing::new(Role::Responder, [3; KEY_SIZE], [4; NONCE_SIZE]);
+        assert_eq!(responder.start(), Err(ProtocolError::InvalidFormat));
+        let nonce = SecurityMessage::PairingNonce { nonce: [2; NONCE_SIZE] };
+        assert_eq!(responder.handle(&nonce), Err(ProtocolError::InvalidFormat));
+    }
+
+    #[test]
+    fn sealed_messages_open_on_the_peer() {
+        let (mut initiator, mut responder) = sessions();
+        for version in [1, 2] {
+            let sealed = seal(&mut initiator, version, version as u16);
+            assert_eq!(open(&mut responder, &sealed), Ok(version as u16));
+        }
+
+        // Each direction has its own key
+        let sealed = seal(&mut initiator, 2, 3);
+        let (mut other, _) = sessions();
+        assert_eq!(open(&mut other, &sealed), Err(ProtocolError::AuthenticationFailed));
+    }
+
+    #[test]
+    fn open_rejects_tampered_messages() {
+        let (mut initiator, mut responder) = sessions();
+        let sealed = seal(&mut initiator, 2, 1);
+        let (mut buffer, length) = sealed;
+        let header = MessageHeader::decode(&buffer).unwrap();
+        buffer[header.size()] ^= 0x01;
+        // Recompute the checksum so that only the tag detects the change
+        assert_eq!(write_envelope(&mut buffer, &header), Ok(length));
+
+        assert_eq!(open(&mut responder, &(buffer, length)), Err(ProtocolError::AuthenticationFailed));
+        // The forged message did not use up the sequence number
+        assert_eq!(open(&mut responder, &sealed), Ok(1));
+    }
+
+    #[test]
+    fn open_accepts_each_sequence_number_once() {
+        let (mut initiator, mut responder) = sessions();
+        let sealed = seal(&mut initiator, 2, 1);
+        assert_eq!(open(&mut responder, &sealed), Ok(1));
+        assert_eq!(open(&mut responder, &sealed), Err(ProtocolError::ReplayDetected));
+    }
+
+    #[test]
+    fn open_accepts_messages_out_of_order_within_the_window() {
+        let (mut initiator, mut responder) = sessions();
+        let first = seal(&mut initiator, 2, 1);
+        let second = seal(&mut initiator, 2, 2);
+        let third = seal(&mut initiator, 2, 3);
+        assert_eq!(open(&mut responder, &third), Ok(3));
+        assert_eq!(open(&mut responder, &first), Ok(1));
+        assert_eq!(open(&mut responder, &second), Ok(2));
+        assert_eq!(open(&mut responder, &first), Err(ProtocolError::ReplayDetected));
+
+        let old = seal(&mut initiator, 2, 10);
+        let new = seal(&mut initiator, 2, 10 + REPLAY_WINDOW_SIZE as u16);
+        assert_eq!(open(&mut responder, &new), Ok(10 + REPLAY_WINDOW_SIZE as u16));
+        assert_eq!(open(&mut responder, &old), Err(ProtocolError::ReplayDetected));
+    }
+
+    #[test]
+    fn counters_continue_across_sequence_number_wraps() {
+        let (mut initiator, mut responder) = sessions();
+        let sealed: [_; 4] = core::array::from_fn(|i| seal(&mut initiator, 2, 0xFFFEu16.wrapping_add(i as u16)));
+        assert_eq!(open(&mut responder, &sealed[0]), Ok(0xFFFE));
+        assert_eq!(open(&mut responder, &sealed[2]), Ok(0x0000));
+        assert_eq!(open(&mut responder, &sealed[1]), Ok(0xFFFF));
+        assert_eq!(open(&mut responder, &sealed[3]), Ok(0x0001));
+
+        // Replays from before the wrap are still detected
+        assert_eq!(open(&mut responder, &sealed[0]), Err(ProtocolError::ReplayDetected));
+        assert_eq!(open(&mut responder, &sealed[2]), Err(ProtocolError::ReplayDetected));
+    }
+}
@@ -0,0 +1,209 @@
+//! Surface interval and repetitive dive state
+//!
+//! This module keeps the residual tissue loading and oxygen exposure between
+//! dives. The state off-gasses during surface intervals and seeds the next
+//! dive, so repetitive dives get a correspondingly shorter NDL. It also
+//! provides the no-fly time derived from the residual loading.
+
+use serde::{Serialize, Deserialize};
+use crate::buhlmann::{GradientFactors, TissueState};
+use crate::dive_calc::{DiveProfile, GasType, PressureModel};
+use crate::oxygen::OxygenExposure;
+use crate::real::{self, Real};
+
+/// Cabin pressure assumed for commercial flights in bar (about 2400 m / 8000 ft)
+pub const AIRCRAFT_CABIN_PRESSURE_BAR: Real = real::lit(0.75);
+
+/// Lower bound for the no-fly time after any dive in minutes (12 hours)
+///
+/// The tissue model alone allows flying within minutes of most no-decompression
+/// dives, so the recommended minimum surface interval before flying is
+/// enforced on top of it.
+pub const MIN_NO_FLY_MINUTES: u16 = 720;
+
+/// Upper bound for the no-fly time in minutes (48 hours)
+pub const MAX_NO_FLY_MINUTES: u16 = 2880;
+
+/// Residual loading carried from one dive to the next
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceState {
+    /// Residual tissue loading
+    pub tissues: TissueState,
+    /// Residual oxygen exposure
+    pub oxygen: OxygenExposure,
+    /// Time spent at the surface since the last dive in seconds
+    pub surface_interval_seconds: u32,
+    /// No-fly time calculated at the end of the last dive in minutes
+    pub no_fly_minutes: u16,
+    /// Surface pressure and water density at the current location
+    pub pressure: PressureModel,
+}
+
+impl SurfaceState {
+    /// Creates a surface state with no residual loading (tissues saturated with air)
+    pub fn new() -> Self {
+        SurfaceState {
+            tissues: TissueState::surface_saturated(PressureModel::STANDARD.surface_pressure_bar()),
+            oxygen: OxygenExposure::new(),
+            surface_interval_seconds: 0,
+            no_fly_minutes: 0,
+            pressure: PressureModel::STANDARD,
+        }
+    }
+
+    /// Takes over the loading at the end of a dive and restarts the surface interval
+    ///
+    /// This is called when the dive ends (`Command::EndDive`) and also calculates
+    /// the no-fly time: the time until the tissues, off-gassing at the surface,
+    /// tolerate `AIRCRAFT_CABIN_PRESSURE_BAR` with the dive's GF-high, but at
+    /// least `MIN_NO_FLY_MINUTES` if the profile went below the surface.
+    pub fn end_dive(&mut self, profile: &DiveProfile) {
+        self.tissues = profile.tissues;
+        self.oxygen = profile.oxygen;
+        self.pressure = profile.pressure;
+        self.surface_interval_seconds = 0;
+        let minimum = if profile.max_depth_cm > 0 { MIN_NO_FLY_MINUTES } else { 0 };
+        let desaturation = self.tissues
+            .desaturation_minutes(
+                self.pressure.surface_pressure_bar(),
+                GasType::Air,
+                profile.gradient_factors.high_fraction(),
+                AIRCRAFT_CABIN_PRESSURE_BAR,
+                MAX_NO_FLY_MINUTES,
+            )
+            .unwrap_or(MAX_NO_FLY_MINUTES);
+        self.no_fly_minutes = desaturation.max(minimum);
+    }
+
+    /// Returns the remaining no-fly time in minutes
+    ///
+    /// The no-fly time counts down as `surface_interval` is called.
+    pub fn no_fly_remaining_minutes(&self) -> u16 {
+        let elapsed_minutes = (self.surface_interval_seconds / 60).min(u16::MAX as u32) as u16;
+        self.no_fly_minutes.saturating_sub(elapsed_minutes)
+    }
+
+    /// Updates the surface pressure, e.g. after a new barometer reading or a
+    /// change of altitude
+    pub fn set_pressure_model(&mut self, pressure: PressureModel) {
+        self.pressure = pressure;
+    }
+
+    /// Off-gasses the tissues and decays oxygen exposure for `seconds` spent at the surface
+    pub fn surface_interval(&mut self, seconds: u32) {
+        self.tissues.update_constant(self.pressure.surface_pressure_bar(), GasType::Air, seconds);
+        self.oxygen.surface_interval(seconds);
+        self.surface_interval_seconds = self.surface_interval_seconds.saturating_add(seconds);
+    }
+
+    /// Creates the profile for the next dive, seeded with the residual loading
+    /// and the current pressure model
+    pub fn start_dive(&self, gas: GasType) -> DiveProfile {
+        let mut profile = DiveProfile::with_pressure_model(gas, self.pressure);
+        profile.tissues = self.tissues;
+        profile.oxygen = self.oxygen;
+        profile
+    }
+
+    /// Returns the NDL in minutes for a dive started after a further surface interval
+    ///
+    /// This is a what-if query for repetitive dive planning; the state itself is
+    /// not modified.
+    ///
+    /// * `surface_interval_minutes` - Additional time spent at the surface before the dive
+    /// * `depth_cm` - Planned dive depth in centimeters; `Command::PlanRepetitiveDive`
+    ///   carries the depth in meters, so handlers multiply it by 100
+    /// * `gas` - Planned breathing gas
+    /// * `gradient_factors` - Gradient factors for the NDL calculation
+    pub fn ndl_after_surface_interval(
+        &self,
+        surface_interval_minutes: u16,
+        depth_cm: u16,
+        gas: GasType,
+        gradient_factors: GradientFactors,
+    ) -> u16 {
+        let mut planned = *self;
+        planned.surface_interval(surface_interval_minutes as u32 * 60);
+        planned.tissues.ndl_minutes(
+            self.pressure.depth_to_pressure(depth_cm),
+            gas,
+            gradient_factors,
+            self.pressure.surface_pressure_bar(),
+        )
+    }
+}
+
+impl Default for SurfaceState {
+    fn default() -> Self {
+        SurfaceState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the surface state after 25 minutes at 30 m on air
+    fn after_dive() -> SurfaceState {
+        let mut state = SurfaceState::new();
+        let mut profile = state.start_dive(GasType::Air);
+        profile.record_sample(3000, 25 * 60);
+        profile.record_sample(0, 4 * 60);
+        state.end_dive(&profile);
+        state
+    }
+
+    #[test]
+    fn repetitive_dives_have_a_shorter_ndl() {
+        let fresh = SurfaceState::new().ndl_after_surface_interval(0, 1800, GasType::Air, GradientFactors::DEFAULT);
+        let state = after_dive();
+        let ndl = |minutes| state.ndl_after_surface_interval(minutes, 1800, GasType::Air, GradientFactors::DEFAULT);
+
+        assert!(ndl(10) < fresh, "{} after 10 min, {} fresh", ndl(10), fresh);
+        assert!(ndl(10) < ndl(60));
+        assert!(ndl(60) < ndl(240));
+        assert!(ndl(240) <= fresh);
+        // The query does not change the state
+        assert_eq!(state, after_dive());
+    }
+
+    #[test]
+    fn short_dives_have_the_minimum_no_fly_time() {
+        let state = after_dive();
+        assert_eq!(state.no_fly_minutes, MIN_NO_FLY_MINUTES);
+        assert_eq!(state.no_fly_remaining_minutes(), MIN_NO_FLY_MINUTES);
+
+        // A profile that never left the surface does not restrict flying
+        let mut state = SurfaceState::new();
+        let profile = state.start_dive(GasType::Air);
+        state.end_dive(&profile);
+        assert_eq!(state.no_fly_minutes, 0);
+    }
+
+    #[test]
+    fn deco_dives_have_a_longer_no_fly_time() {
+        let mut state = SurfaceState::new();
+        let mut profile = state.start_dive(GasType::Air);
+        // Ten hours at 30 m load the slow compartments beyond the minimum
+        profile.record_sample(3000, 10 * 60 * 60);
+        profile.record_sample(600, 60 * 60);
+        profile.record_sample(300, 2 * 60 * 60);
+        profile.record_sample(0, 60);
+        state.end_dive(&profile);
+        assert!(state.no_fly_minutes > MIN_NO_FLY_MINUTES, "{} minutes", state.no_fly_minutes);
+        assert!(state.no_fly_minutes < MAX_NO_FLY_MINUTES, "{} minutes", state.no_fly_minutes);
+
+        state.surface_interval(60 * 60);
+        assert_eq!(state.no_fly_remaining_minutes(), state.no_fly_minutes - 60);
+    }
+
+    #[test]
+    fn no_fly_time_is_capped() {
+        let mut state = SurfaceState::new();
+        let mut profile = state.start_dive(GasType::Air);
+        profile.record_sample(10000, 3 * 24 * 60 * 60);
+        profile.record_sample(0, 60);
+        state.end_dive(&profile);
+        assert_eq!(state.no_fly_minutes, MAX_NO_FLY_MINUTES);
+    }
+}
@@ -0,0 +1,284 @@
+//! Byte transports
+//!
+//! This module defines the interface the higher layers use to move bytes
+//! between host and device, independent of whether they travel over a serial
+//! line, BLE or an in-memory channel, together with implementations for
+//! common links:
+//!
+//! * [`EmbeddedIo`] adapts any `embedded-io` serial driver (`embedded-io` feature)
+//! * [`AsyncTransport`] is the async counterpart of [`Transport`], implemented
+//!   by [`EmbeddedIo`] for `embedded-io-async` drivers (`async` feature)
+//! * [`loopback`] connects two ends in memory, e.g. a host and an emulated
+//!   device in tests (`std` feature)
+//! * [`StreamTransport`] runs over serial ports, pseudo-terminals and TCP
+//!   sockets (`std` feature)
+
+#[cfg(feature = "std")]
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::Path,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+    vec::Vec,
+};
+
+/// Blocking byte transport
+///
+/// Transports move raw bytes; message boundaries are recovered by the
+/// receiver with `StreamDecoder` or `FrameDecoder`.
+pub trait Transport {
+    /// Error reported by the underlying link
+    type Error;
+
+    /// Sends all bytes of `data`
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receives bytes into `buffer`
+    ///
+    /// Blocks until at least one byte is available or the transport's read
+    /// timeout elapses.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes received, or 0 if the timeout elapsed
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    type Error = T::Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        (**self).write_all(data)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        (**self).read(buffer)
+    }
+}
+
+/// Async byte transport with the same contract as [`Transport`]
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    /// Error reported by the underlying link
+    type Error;
+
+    /// Sends all bytes of `data`
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receives bytes into `buffer`
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes received, or 0 if the link timed out or was closed
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Adapter that makes an `embedded-io` driver, e.g. a HAL UART, a transport
+///
+/// With the `embedded-io` feature it implements [`Transport`] for blocking
+/// drivers, with the `async` feature [`AsyncTransport`] for
+/// `embedded-io-async` drivers. A driver's `read` returns 0 only at the end
+/// of the stream, which the transport reports like a timeout.
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Read + embedded_io::Write> Transport for EmbeddedIo<T> {
+    type Error = T::Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data)?;
+        self.0.flush()
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buffer)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: embedded_io_async::Read + embedded_io_async::Write> AsyncTransport for EmbeddedIo<T> {
+    type Error = T::Error;
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data).await?;
+        self.0.flush().await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buffer).await
+    }
+}
+
+/// One direction of a loopback connection
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+struct Pipe {
+    /// Bytes written and not yet read
+    bytes: Mutex<VecDeque<u8>>,
+    /// Signalled when bytes are written
+    written: Condvar,
+}
+
+/// One end of an in-memory connection created by [`loopback`]
+///
+/// Both ends can be moved to different threads, e.g. to run a `Client` and
+/// a `Dispatcher` against each other.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Loopback {
+    /// Bytes sent to this end
+    incoming: Arc<Pipe>,
+    /// Bytes sent to the other end
+    outgoing: Arc<Pipe>,
+    /// Time to wait for bytes in `read`
+    read_timeout: Duration,
+}
+
+/// Creates two connected in-memory transport ends
+///
+/// Bytes written to one end are read from the other in order.
+///
+/// # Arguments
+///
+/// * `read_timeout` - Time `read` waits for bytes before it returns 0
+#[cfg(feature = "std")]
+pub fn loopback(read_timeout: Duration) -> (Loopback, Loopback) {
+    let forward = Arc::new(Pipe::default());
+    let backward = Arc::new(Pipe::default());
+    (
+        Loopback { incoming: backward.clone(), outgoing: forward.clone(), read_timeout },
+        Loopback { incoming: forward, outgoing: backward, read_timeout },
+    )
+}
+
+#[cfg(feature = "std")]
+impl Transport for Loopback {
+    type Error = core::convert::Infallible;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut bytes = self.outgoing.bytes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        bytes.extend(data);
+        self.outgoing.written.notify_all();
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        let bytes = self.incoming.bytes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (mut bytes, _) = self
+            .incoming
+            .written
+            .wait_timeout_while(bytes, self.read_timeout, |bytes| bytes.is_empty())
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = buffer.len().min(bytes.len());
+        for (target, byte) in buffer.iter_mut().zip(bytes.drain(..count)) {
+            *target = byte;
+        }
+        Ok(count)
+    }
+}
+
+/// Transport over a `std::io` byte stream: serial ports, pseudo-terminals
+/// and TCP sockets
+///
+/// A background thread reads from the stream, so that `read` can time out
+/// on streams that have no read timeout of their own, such as device files.
+/// Serial line settings (baud rate, raw mode) are not changed; configure the
+/// port beforehand, e.g. with `stty`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StreamTransport<W> {
+    /// Stream for sending
+    writer: W,
+    /// Chunks received by the reader thread
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    /// Received chunk not yet returned by `read`
+    pending: Vec<u8>,
+    /// Number of bytes of `pending` already returned
+    offset: usize,
+    /// Time to wait for bytes in `read`
+    read_timeout: Duration,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> StreamTransport<W> {
+    /// Creates a transport from the two halves of a stream
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Stream to receive from; moved to the reader thread
+    /// * `writer` - Stream to send to
+    /// * `read_timeout` - Time `read` waits for bytes before it returns 0
+    pub fn new<R: Read + Send + 'static>(mut reader: R, writer: W, read_timeout: Duration) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 256];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(count) => Ok(buffer[..count].to_vec()),
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => Err(error),
+                };
+                let failed = chunk.is_err();
+                // Stop once the transport has been dropped or the stream failed
+                if sender.send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        StreamTransport { writer, chunks, pending: Vec::new(), offset: 0, read_timeout }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamTransport<File> {
+    /// Opens a serial port or pseudo-terminal, e.g. `/dev/ttyUSB0` or `/dev/pts/3`
+    pub fn open_serial<P: AsRef<Path>>(path: P, read_timeout: Duration) -> io::Result<Self> {
+        let port = File::options().read(true).write(true).open(path)?;
+        Ok(StreamTransport::new(port.try_clone()?, port, read_timeout))
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamTransport<TcpStream> {
+    /// Creates a transport over a connected TCP socket
+    pub fn from_tcp(stream: TcpStream, read_timeout: Duration) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(StreamTransport::new(stream.try_clone()?, stream, read_timeout))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Transport for StreamTransport<W> {
+    type Error = io::Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.writer.write_all(data)?;
+        self.writer.flush()
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.offset == self.pending.len() {
+            match self.chunks.recv_timeout(self.read_timeout) {
+                Ok(chunk) => {
+                    self.pending = chunk?;
+                    self.offset = 0;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(0),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed"));
+                }
+            }
+        }
+        let count = buffer.len().min(self.pending.len() - self.offset);
+        buffer[..count].copy_from_slice(&self.pending[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,161 @@
+//! Runs a `Client` against a `Dispatcher` over an in-memory transport
+
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use dive_computer_proto::client::{BatteryStatus, Client, ClientError};
+use dive_computer_proto::commands::{ErrorCode, Privilege, ResponsePayload};
+use dive_computer_proto::dispatcher::{CommandHandler, Dispatcher, HandlerResult};
+use dive_computer_proto::protocol::{validate_frame, Message, MessageKind, StreamDecoder, MAX_MESSAGE_SIZE};
+use dive_computer_proto::session::{BondKey, Role, SecurityMessage, Session, KEY_SIZE, NONCE_SIZE};
+use dive_computer_proto::transport::{loopback, Loopback, Transport};
+
+/// Bond key shared by host and device, as if they had paired before
+const BOND: [u8; KEY_SIZE] = [0x42; KEY_SIZE];
+
+/// Nonce of the host in `SessionStart`
+const HOST_NONCE: [u8; NONCE_SIZE] = [0x01; NONCE_SIZE];
+
+/// Nonce of the device in `SessionStart`
+const DEVICE_NONCE: [u8; NONCE_SIZE] = [0x02; NONCE_SIZE];
+
+/// Battery status reported by the device
+const BATTERY: BatteryStatus = BatteryStatus { level: 80, voltage: 3900, estimated_time_remaining: 600 };
+
+/// Device that counts factory resets
+#[derive(Debug, Default)]
+struct Device {
+    /// Number of factory resets performed
+    resets: u32,
+}
+
+impl CommandHandler for Device {
+    fn get_battery_status(&mut self) -> HandlerResult {
+        Ok(Some(ResponsePayload::BatteryStatus {
+            level: BATTERY.level,
+            voltage: BATTERY.voltage,
+            estimated_time_remaining: BATTERY.estimated_time_remaining,
+        }))
+    }
+
+    fn factory_reset(&mut self) -> HandlerResult {
+        self.resets += 1;
+        Ok(None)
+    }
+}
+
+/// Answers messages from the host until it stays silent for a read timeout
+/// and returns the number of factory resets
+///
+/// Commands without a session are granted `ReadOnly`; a session started with
+/// `SessionStart` grants `Destructive`.
+fn serve(mut transport: Loopback) -> u32 {
+    let mut dispatcher = Dispatcher::new(Device::default());
+    let mut session: Option<Session> = None;
+    let mut decoder = StreamDecoder::new();
+    let mut chunk = [0u8; 64];
+    loop {
+        let Ok(count) = transport.read(&mut chunk);
+        if count == 0 {
+            return dispatcher.handler().resets;
+        }
+        let mut offset = 0;
+        while offset < count {
+            offset += decoder.push(&chunk[offset..count]);
+            while let Some(frame) = decoder.next_frame() {
+                let frame = frame.unwrap();
+                let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+                buffer[..frame.len()].copy_from_slice(frame);
+                let length = frame.len();
+                let (header, _) = validate_frame(&buffer[..length]).unwrap();
+                let reply_length = match header.kind {
+                    MessageKind::Security => {
+                        let message = Message::<SecurityMessage>::try_from(&buffer[..length]).unwrap();
+                        let SecurityMessage::SessionStart { nonce } = message.payload else {
+                            panic!("unexpected {:?}", message.payload);
+                        };
+                        let mut started = Session::new(Role::Responder, &BondKey::from_bytes(BOND), DEVICE_NONCE, nonce);
+                        started.grant(Privilege::Destructive);
+                        session = Some(started);
+                        let reply = SecurityMessage::SessionStart { nonce: DEVICE_NONCE };
+                        Message::new(MessageKind::Security, header.sequence, reply)
+                            .unwrap()
+                            .serialize_into(&mut buffer)
+                            .unwrap()
+                    }
+                    MessageKind::Command => {
+                        let (length, granted) = match &mut session {
+                            Some(session) => (session.open(&mut buffer[..length]).unwrap(), session.privilege()),
+                            None => (length, Privilege::ReadOnly),
+                        };
+                        let response = dispatcher.dispatch_frame(&buffer[..length], granted, 0).unwrap();
+                        match &mut session {
+                            Some(session) => session.seal_message(&response, &mut buffer).unwrap(),
+                            None => response.serialize_into(&mut buffer).unwrap(),
+                        }
+                    }
+                    kind => panic!("unexpected {:?} message", kind),
+                };
+                let Ok(()) = transport.write_all(&buffer[..reply_length]);
+            }
+        }
+    }
+}
+
+/// Starts a device thread and returns a client connected to it
+fn connect() -> (Client<Loopback>, JoinHandle<u32>) {
+    let (host, device) = loopback(Duration::from_millis(500));
+    let device = thread::spawn(move || serve(device));
+    (Client::new(host), device)
+}
+
+/// Runs the `SessionStart` exchange and seals further commands of `client`
+fn start_session(client: &mut Client<Loopback>) {
+    let start = Message::new(MessageKind::Security, 0x8000, SecurityMessage::SessionStart { nonce: HOST_NONCE }).unwrap();
+    let (length, bytes) = start.serialize().unwrap();
+    let transport = client.transport_mut();
+    let Ok(()) = transport.write_all(&bytes[..length]);
+
+    let mut decoder = StreamDecoder::new();
+    let mut chunk = [0u8; 64];
+    let reply = loop {
+        let Ok(count) = transport.read(&mut chunk);
+        assert!(count > 0, "no SessionStart reply");
+        decoder.push(&chunk[..count]);
+        if let Some(reply) = decoder.next_message::<SecurityMessage>() {
+            break reply.unwrap();
+        }
+    };
+    let SecurityMessage::SessionStart { nonce } = reply.payload else {
+        panic!("unexpected {:?}", reply.payload);
+    };
+    client.start_session(Session::new(Role::Initiator, &BondKey::from_bytes(BOND), HOST_NONCE, nonce));
+}
+
+#[test]
+fn plain_commands_are_answered() {
+    let (mut client, device) = connect();
+    assert_eq!(client.get_battery_status(), Ok(BATTERY));
+    drop(client);
+    assert_eq!(device.join().unwrap(), 0);
+}
+
+#[test]
+fn factory_reset_without_session_is_denied() {
+    let (mut client, device) = connect();
+    let error = client.factory_reset().unwrap_err();
+    assert_eq!(error, ClientError::Device(ErrorCode::InsufficientPrivilege));
+    assert_eq!(error.error_code().map(u16::from), Some(0x08));
+    drop(client);
+    assert_eq!(device.join().unwrap(), 0);
+}
+
+#[test]
+fn sealed_commands_run_with_the_session_privilege() {
+    let (mut client, device) = connect();
+    start_session(&mut client);
+    assert_eq!(client.factory_reset(), Ok(()));
+    assert_eq!(client.get_battery_status(), Ok(BATTERY));
+    drop(client);
+    assert_eq!(device.join().unwrap(), 1);
+}